@@ -0,0 +1,202 @@
+use rusty_daw_core::SampleRate;
+
+/// The seed used when the output does not need to be reproducible.
+pub static DEFAULT_DITHER_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+/// The coefficients of the error feedback filter used for noise shaping. This is the
+/// 5-tap filter from Lipshitz, Vanderkooy & Wannamaker "Minimally Audible Noise Shaping",
+/// which pushes the noise away from the frequencies where our hearing is most sensitive.
+///
+/// These are designed for 44.1 kHz and still work well at 48 kHz. At other sample rates
+/// they would put the noise in the wrong place, so plain TPDF dither is used instead.
+///
+/// TODO: Use different coefficients for other sample rates.
+static NOISE_SHAPING_COEFFS: [f32; 5] = [2.033, -2.165, 1.959, -1.590, 0.6149];
+
+/// How a sample is converted to a lower bit depth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DitherMode {
+    /// Round to the nearest value.
+    None,
+    /// Add triangular (TPDF) noise of +/- 1 LSB before rounding.
+    Tpdf,
+    /// Add triangular (TPDF) noise before rounding, and shape the spectrum of the
+    /// resulting noise to make it less audible.
+    TpdfNoiseShaped,
+}
+
+impl Default for DitherMode {
+    fn default() -> Self {
+        DitherMode::Tpdf
+    }
+}
+
+/// Converts samples to integers of a lower bit depth.
+///
+/// The noise is generated from a seed, so the output is always the same for the same
+/// seed and input.
+pub struct Ditherer {
+    mode: DitherMode,
+
+    scale: f32,
+    min: f32,
+    max: f32,
+
+    rng_state: u64,
+
+    /// The last quantization errors of each channel (the newest error first).
+    errors: Vec<[f32; 5]>,
+}
+
+impl Ditherer {
+    /// Create a new ditherer.
+    ///
+    /// * `bits` - The bit depth of the output (i.e. `16` or `24`).
+    /// * `sample_rate` - The sample rate of the output. Noise shaping is only used at
+    /// 44.1 kHz and 48 kHz, `DitherMode::TpdfNoiseShaped` falls back to plain TPDF
+    /// dither at other sample rates.
+    pub fn new(
+        mode: DitherMode,
+        bits: u32,
+        num_channels: usize,
+        sample_rate: SampleRate,
+        seed: u64,
+    ) -> Self {
+        assert!((2..=32).contains(&bits));
+
+        let mode = if mode == DitherMode::TpdfNoiseShaped
+            && !(44_100.0..=48_000.0).contains(&sample_rate.0)
+        {
+            DitherMode::Tpdf
+        } else {
+            mode
+        };
+
+        let max = ((1u64 << (bits - 1)) - 1) as f32;
+
+        Self {
+            mode,
+            scale: max,
+            min: -max - 1.0,
+            max,
+            // Xorshift does not work with a state of zero.
+            rng_state: if seed == 0 { DEFAULT_DITHER_SEED } else { seed },
+            errors: vec![[0.0; 5]; num_channels],
+        }
+    }
+
+    /// Convert a sample in the range `[-1.0, 1.0]` to an integer value.
+    ///
+    /// Samples must be given in order for each channel, since the noise shaping depends
+    /// on the previous samples of the same channel.
+    pub fn quantize(&mut self, channel: usize, sample: f32) -> i32 {
+        let value = sample * self.scale;
+
+        let q = match self.mode {
+            DitherMode::None => value.round(),
+            DitherMode::Tpdf => (value + self.tpdf()).round(),
+            DitherMode::TpdfNoiseShaped => {
+                let errors = &self.errors[channel];
+                let shaped = value
+                    - errors
+                        .iter()
+                        .zip(NOISE_SHAPING_COEFFS.iter())
+                        .map(|(e, c)| e * c)
+                        .sum::<f32>();
+
+                let q = (shaped + self.tpdf()).round();
+
+                // Clipping can cause huge errors, which would make the filter unstable.
+                let error = (q.clamp(self.min, self.max) - shaped).clamp(-1.5, 1.5);
+
+                let errors = &mut self.errors[channel];
+                errors.rotate_right(1);
+                errors[0] = error;
+
+                q
+            }
+        };
+
+        q.clamp(self.min, self.max) as i32
+    }
+
+    /// Clear the noise shaping history of all channels. The sequence of noise is not
+    /// reset.
+    pub fn reset(&mut self) {
+        for errors in self.errors.iter_mut() {
+            *errors = [0.0; 5];
+        }
+    }
+
+    /// Generate triangular noise in the range `(-1.0, 1.0)`.
+    fn tpdf(&mut self) -> f32 {
+        self.uniform() - self.uniform()
+    }
+
+    /// Generate uniform noise in the range `[0.0, 1.0)` (xorshift64*).
+    fn uniform(&mut self) -> f32 {
+        self.rng_state ^= self.rng_state >> 12;
+        self.rng_state ^= self.rng_state << 25;
+        self.rng_state ^= self.rng_state >> 27;
+        let r = self.rng_state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+
+        // Use the top 24 bits so the result is exact in an `f32`.
+        (r >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dither_seeded() {
+        let input: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+
+        for &mode in [DitherMode::Tpdf, DitherMode::TpdfNoiseShaped].iter() {
+            let run = |seed: u64| {
+                let mut ditherer = Ditherer::new(mode, 16, 1, SampleRate::new(44_100.0), seed);
+                input.iter().map(|s| ditherer.quantize(0, *s)).collect::<Vec<i32>>()
+            };
+
+            assert_eq!(run(1), run(1));
+            assert_ne!(run(1), run(2));
+
+            // The error stays small.
+            for (q, s) in run(3).iter().zip(input.iter()) {
+                assert!((*q as f32 - (s * 32767.0)).abs() < 16.0);
+            }
+        }
+
+        let mut ditherer = Ditherer::new(DitherMode::None, 16, 1, SampleRate::new(44_100.0), 1);
+        assert_eq!(ditherer.quantize(0, 0.5), 16384);
+        assert_eq!(ditherer.quantize(0, 2.0), 32767);
+        assert_eq!(ditherer.quantize(0, -2.0), -32768);
+
+        let mut ditherer = Ditherer::new(DitherMode::Tpdf, 24, 1, SampleRate::new(44_100.0), 1);
+        assert!((ditherer.quantize(0, 0.25) - 2_097_152).abs() <= 1);
+    }
+
+    #[test]
+    fn noise_shaping_only_at_supported_sample_rates() {
+        let input: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+
+        let run = |mode: DitherMode, sample_rate: f64| {
+            let mut ditherer = Ditherer::new(mode, 16, 1, SampleRate::new(sample_rate), 1);
+            input.iter().map(|s| ditherer.quantize(0, *s)).collect::<Vec<i32>>()
+        };
+
+        for &sample_rate in [44_100.0, 48_000.0].iter() {
+            assert_ne!(
+                run(DitherMode::TpdfNoiseShaped, sample_rate),
+                run(DitherMode::Tpdf, sample_rate)
+            );
+        }
+        for &sample_rate in [22_050.0, 96_000.0].iter() {
+            assert_eq!(
+                run(DitherMode::TpdfNoiseShaped, sample_rate),
+                run(DitherMode::Tpdf, sample_rate)
+            );
+        }
+    }
+}
@@ -1 +1,2 @@
+pub mod dither;
 pub mod resample;
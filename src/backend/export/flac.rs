@@ -0,0 +1,436 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::backend::dsp::dither::{DitherMode, Ditherer};
+use crate::backend::RenderedAudio;
+
+use super::{ExportError, ExportFormat, WavBitDepth};
+
+/// The number of frames in each FLAC frame (except for the last one).
+const BLOCK_SIZE: usize = 4096;
+
+/// The highest order of the fixed predictors.
+const MAX_FIXED_ORDER: usize = 4;
+
+/// The highest Rice parameter that can be coded with a 4 bit parameter (`15` is the
+/// escape code).
+const MAX_RICE_PARAM: u32 = 14;
+
+const NUM_CHANNELS: usize = 2;
+
+/// Write the given audio to a stereo FLAC file, replacing the file if it already exists.
+///
+/// FLAC only stores integer samples, so this will return an error for `Float32`. The
+/// same `dither_seed` always produces the same file.
+pub fn write_flac(
+    path: &Path,
+    audio: &RenderedAudio,
+    bit_depth: WavBitDepth,
+    dither: DitherMode,
+    dither_seed: u64,
+) -> Result<(), ExportError> {
+    if bit_depth == WavBitDepth::Float32 {
+        return Err(ExportError::UnsupportedBitDepth { format: ExportFormat::Flac, bit_depth });
+    }
+
+    // The total number of frames is stored in 36 bits.
+    if audio.len() as u64 >= 1 << 36 {
+        return Err(ExportError::FileTooLarge(path.to_path_buf()));
+    }
+
+    let file =
+        File::create(path).map_err(|e| ExportError::CouldNotCreateFile((path.to_path_buf(), e)))?;
+    let mut writer = BufWriter::new(file);
+
+    write_flac_to(&mut writer, audio, bit_depth, dither, dither_seed)
+        .and_then(|_| writer.flush())
+        .map_err(|e| ExportError::ErrorWhileWriting((path.to_path_buf(), e)))
+}
+
+fn write_flac_to<W: Write>(
+    writer: &mut W,
+    audio: &RenderedAudio,
+    bit_depth: WavBitDepth,
+    dither: DitherMode,
+    dither_seed: u64,
+) -> io::Result<()> {
+    let bits = bit_depth.bytes_per_sample() as u32 * 8;
+    let sample_rate = audio.sample_rate.0.round() as u64;
+
+    writer.write_all(b"fLaC")?;
+
+    // The STREAMINFO block, which is also the last metadata block.
+    let mut info = BitWriter::default();
+    info.write(1, 1);
+    info.write(0, 7);
+    info.write(34, 24);
+    info.write(BLOCK_SIZE as u64, 16);
+    info.write(BLOCK_SIZE as u64, 16);
+    // The minimum and maximum frame sizes are unknown.
+    info.write(0, 24);
+    info.write(0, 24);
+    info.write(sample_rate, 20);
+    info.write(NUM_CHANNELS as u64 - 1, 3);
+    info.write(u64::from(bits) - 1, 5);
+    info.write(audio.len() as u64, 36);
+    // The MD5 signature is left unset, which means it is unknown.
+    info.write(0, 64);
+    info.write(0, 64);
+    writer.write_all(&info.bytes)?;
+
+    let mut ditherer = Ditherer::new(dither, bits, NUM_CHANNELS, audio.sample_rate, dither_seed);
+    let mut channels = [Vec::with_capacity(BLOCK_SIZE), Vec::with_capacity(BLOCK_SIZE)];
+
+    for (frame_number, start) in (0..audio.len()).step_by(BLOCK_SIZE).enumerate() {
+        let end = (start + BLOCK_SIZE).min(audio.len());
+
+        for c in channels.iter_mut() {
+            c.clear();
+        }
+        for (&l, &r) in audio.left[start..end].iter().zip(audio.right[start..end].iter()) {
+            channels[0].push(i64::from(ditherer.quantize(0, l)));
+            channels[1].push(i64::from(ditherer.quantize(1, r)));
+        }
+
+        writer.write_all(&encode_frame(frame_number as u64, &channels, bits))?;
+    }
+
+    Ok(())
+}
+
+/// Encode one FLAC frame holding the given channels.
+fn encode_frame(frame_number: u64, channels: &[Vec<i64>; NUM_CHANNELS], bits: u32) -> Vec<u8> {
+    let block_size = channels[0].len();
+
+    let mut frame = BitWriter::default();
+
+    // Sync code, with a fixed block size.
+    frame.write(0b1111_1111_1111_1000, 16);
+    // The block size is stored at the end of the header as a 16 bit value, and the
+    // sample rate is taken from the STREAMINFO block.
+    frame.write(0b0111, 4);
+    frame.write(0b0000, 4);
+    // Independent left and right channels.
+    frame.write(NUM_CHANNELS as u64 - 1, 4);
+    frame.write(if bits == 16 { 0b100 } else { 0b110 }, 3);
+    frame.write(0, 1);
+    frame.write_utf8(frame_number);
+    frame.write(block_size as u64 - 1, 16);
+
+    let crc = crc8(&frame.bytes);
+    frame.write(u64::from(crc), 8);
+
+    for samples in channels.iter() {
+        encode_subframe(&mut frame, samples, bits);
+    }
+
+    frame.align();
+    let crc = crc16(&frame.bytes);
+    frame.write(u64::from(crc), 16);
+
+    frame.bytes
+}
+
+/// Encode one channel of a frame, using whichever subframe type is the smallest.
+fn encode_subframe(frame: &mut BitWriter, samples: &[i64], bits: u32) {
+    let bits_u64 = u64::from(bits);
+
+    if samples.iter().all(|&s| s == samples[0]) {
+        frame.write(0b0000_0000, 8);
+        frame.write_signed(samples[0], bits);
+        return;
+    }
+
+    // Find the fixed predictor with the smallest residual.
+    let mut best: Option<(usize, u32, u64)> = None;
+    for order in 0..=MAX_FIXED_ORDER.min(samples.len() - 1) {
+        let residual = fixed_residual(samples, order);
+        let (rice_param, rice_bits) = best_rice_param(&residual);
+        let total_bits = (order as u64 * bits_u64) + 6 + rice_bits;
+
+        if best.map(|(_, _, b)| total_bits < b).unwrap_or(true) {
+            best = Some((order, rice_param, total_bits));
+        }
+    }
+    let (order, rice_param, total_bits) = best.unwrap();
+
+    if total_bits >= samples.len() as u64 * bits_u64 {
+        // The audio can't be predicted well, so store it as it is.
+        frame.write(0b0000_0010, 8);
+        for &s in samples.iter() {
+            frame.write_signed(s, bits);
+        }
+        return;
+    }
+
+    frame.write(0b0001_0000 | (order as u64 * 2), 8);
+    for &s in samples[0..order].iter() {
+        frame.write_signed(s, bits);
+    }
+
+    // A Rice coded residual with a single partition.
+    frame.write(0b00, 2);
+    frame.write(0, 4);
+    frame.write(u64::from(rice_param), 4);
+    for &r in fixed_residual(samples, order).iter() {
+        let u = zigzag(r);
+        frame.write_unary(u >> rice_param);
+        frame.write(u & ((1 << rice_param) - 1), rice_param);
+    }
+}
+
+/// The residual of the fixed predictor of the given order. The first `order` samples
+/// are stored as they are, so they are not part of the residual.
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |n: usize| samples[i - n];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Find the Rice parameter which codes the residual in the fewest bits, as
+/// `(parameter, bits)`.
+fn best_rice_param(residual: &[i64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAM)
+        .map(|k| {
+            let bits = residual
+                .iter()
+                .map(|&r| (zigzag(r) >> k) + 1 + u64::from(k))
+                .fold(0u64, |sum, b| sum.saturating_add(b));
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap()
+}
+
+/// Map a signed value to an unsigned one, so that small values stay small.
+#[inline]
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// CRC-8 with the polynomial `x^8 + x^2 + x + 1`.
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &b in bytes.iter() {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-16 with the polynomial `x^16 + x^15 + x^2 + 1`.
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in bytes.iter() {
+        crc ^= u16::from(b) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Writes values to a byte buffer bit by bit, starting with the most significant bit.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// The number of bits used in the last byte (between `0` and `7`).
+    used: u32,
+}
+
+impl BitWriter {
+    /// Write the lowest `bits` bits of `value`.
+    fn write(&mut self, value: u64, bits: u32) {
+        for i in (0..bits).rev() {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.used);
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    /// Write a signed value as a two's complement number of `bits` bits.
+    #[inline]
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Write `value` zeros followed by a one.
+    fn write_unary(&mut self, value: u64) {
+        for _ in 0..value {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    /// Write a number of up to 36 bits with the variable length coding used for frame
+    /// numbers (an extended form of UTF-8).
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+
+        // The number of continuation bytes.
+        let n = match value {
+            v if v < 0x800 => 1,
+            v if v < 0x1_0000 => 2,
+            v if v < 0x20_0000 => 3,
+            v if v < 0x400_0000 => 4,
+            v if v < 0x8000_0000 => 5,
+            _ => 6,
+        };
+
+        let prefix = (0xFF00u64 >> (n + 1)) & 0xFF;
+        self.write(prefix | (value >> (6 * n)), 8);
+        for i in (0..n).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    /// Pad the last byte with zeros.
+    #[inline]
+    fn align(&mut self) {
+        self.used = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_daw_core::SampleRate;
+
+    use super::*;
+
+    /// Reads back the subset of FLAC written by `write_flac_to()`.
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn read(&mut self, bits: u32) -> u64 {
+            let mut value = 0;
+            for _ in 0..bits {
+                let bit = (self.bytes[self.pos / 8] >> (7 - (self.pos % 8))) & 1;
+                value = (value << 1) | u64::from(bit);
+                self.pos += 1;
+            }
+            value
+        }
+
+        fn read_signed(&mut self, bits: u32) -> i64 {
+            let value = self.read(bits) as i64;
+            (value << (64 - bits)) >> (64 - bits)
+        }
+
+        fn read_subframe(&mut self, block_size: usize, bits: u32) -> Vec<i64> {
+            let kind = self.read(8);
+            match kind {
+                0b0000_0000 => vec![self.read_signed(bits); block_size],
+                0b0000_0010 => (0..block_size).map(|_| self.read_signed(bits)).collect(),
+                _ => {
+                    let order = ((kind >> 1) & 0b111) as usize;
+                    let mut samples: Vec<i64> =
+                        (0..order).map(|_| self.read_signed(bits)).collect();
+
+                    assert_eq!(self.read(6), 0);
+                    let k = self.read(4) as u32;
+                    for _ in order..block_size {
+                        let mut q = 0;
+                        while self.read(1) == 0 {
+                            q += 1;
+                        }
+                        let u = (q << k) | self.read(k);
+                        let r = ((u >> 1) as i64) ^ -((u & 1) as i64);
+
+                        let i = samples.len();
+                        let s = |n: usize| samples[i - n];
+                        let prediction = match order {
+                            0 => 0,
+                            1 => s(1),
+                            2 => 2 * s(1) - s(2),
+                            3 => 3 * s(1) - 3 * s(2) + s(3),
+                            _ => 4 * s(1) - 6 * s(2) + 4 * s(3) - s(4),
+                        };
+                        samples.push(prediction + r);
+                    }
+                    samples
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flac_round_trip() {
+        let len = BLOCK_SIZE + 1000;
+        let audio = RenderedAudio {
+            left: (0..len).map(|i| (i as f32 * 0.01).sin() * 0.5).collect(),
+            right: (0..len)
+                .map(|i| if i < BLOCK_SIZE { 0.25 } else { (i % 7) as f32 * 0.1 })
+                .collect(),
+            sample_rate: SampleRate::new(44_100.0),
+        };
+
+        let mut bytes = Vec::<u8>::new();
+        write_flac_to(&mut bytes, &audio, WavBitDepth::Int16, DitherMode::None, 0).unwrap();
+
+        assert_eq!(&bytes[0..4], b"fLaC");
+        let mut reader = BitReader { bytes: &bytes, pos: 8 * 8 };
+        assert_eq!(reader.read(16), BLOCK_SIZE as u64);
+        reader.read(16 + 24 + 24);
+        assert_eq!(reader.read(20), 44_100);
+        assert_eq!(reader.read(3), 1);
+        assert_eq!(reader.read(5), 15);
+        assert_eq!(reader.read(36), len as u64);
+        reader.read(128);
+
+        let mut ditherer = Ditherer::new(DitherMode::None, 16, 2, audio.sample_rate, 0);
+        for (frame_number, start) in (0..len).step_by(BLOCK_SIZE).enumerate() {
+            let frame_start = reader.pos / 8;
+            let block_size = (len - start).min(BLOCK_SIZE);
+
+            assert_eq!(reader.read(16), 0xFFF8);
+            reader.read(16);
+            assert_eq!(reader.read(8), frame_number as u64);
+            assert_eq!(reader.read(16), block_size as u64 - 1);
+            let crc = crc8(&bytes[frame_start..(reader.pos / 8)]);
+            assert_eq!(reader.read(8), u64::from(crc));
+
+            let left = reader.read_subframe(block_size, 16);
+            let right = reader.read_subframe(block_size, 16);
+            for (i, (l, r)) in left.iter().zip(right.iter()).enumerate() {
+                assert_eq!(*l, i64::from(ditherer.quantize(0, audio.left[start + i])));
+                assert_eq!(*r, i64::from(ditherer.quantize(1, audio.right[start + i])));
+            }
+
+            reader.pos += (8 - (reader.pos % 8)) % 8;
+            let crc = crc16(&bytes[frame_start..(reader.pos / 8)]);
+            assert_eq!(reader.read(16), u64::from(crc));
+        }
+        assert_eq!(reader.pos, bytes.len() * 8);
+    }
+
+    #[test]
+    fn frame_number_coding() {
+        let mut writer = BitWriter::default();
+        writer.write_utf8(0x7F);
+        writer.write_utf8(0x80);
+        writer.write_utf8(0x1_0000);
+        assert_eq!(writer.bytes, vec![0x7F, 0xC2, 0x80, 0xF0, 0x90, 0x80, 0x80]);
+    }
+}
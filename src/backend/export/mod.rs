@@ -0,0 +1,335 @@
+use rusty_daw_core::{MusicalTime, SampleRate, SampleTime};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::dsp::dither::DitherMode;
+use super::timeline::audio_clip::timeline_range;
+use super::timeline::{LoopState, TempoMap, TimelineTrackSaveState};
+use super::{OfflineRenderError, RenderedAudio, ResourceLoadError};
+
+mod flac;
+mod wav;
+
+pub use flac::write_flac;
+pub use wav::{write_wav, WavBitDepth};
+
+/// The file format of exported audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Wav,
+    /// Lossless compressed audio. This only supports integer bit depths.
+    Flac,
+}
+
+impl ExportFormat {
+    /// The file extension used for this format (without the dot).
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Wav => "wav",
+            ExportFormat::Flac => "flac",
+        }
+    }
+}
+
+/// Write the given audio to a stereo file in the given format, replacing the file if it
+/// already exists.
+pub fn write_audio_file(
+    path: &Path,
+    audio: &RenderedAudio,
+    format: ExportFormat,
+    bit_depth: WavBitDepth,
+    dither: DitherMode,
+    dither_seed: u64,
+) -> Result<(), ExportError> {
+    match format {
+        ExportFormat::Wav => write_wav(path, audio, bit_depth, dither, dither_seed),
+        ExportFormat::Flac => write_flac(path, audio, bit_depth, dither, dither_seed),
+    }
+}
+
+/// The range on the timeline to export.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportRange {
+    /// From the start of the timeline to the end of the last clip (with the groove
+    /// applied).
+    WholeSong,
+    /// The active loop region.
+    LoopRegion,
+    /// A range selected by the user.
+    Selection { start: MusicalTime, end: MusicalTime },
+}
+
+impl ExportRange {
+    /// Find the range `(start, end)` on the timeline to export.
+    ///
+    /// This will return an error if the range is empty, or if the loop region is exported
+    /// while the loop is inactive.
+    pub fn resolve(
+        &self,
+        loop_state: &LoopState,
+        timeline_tracks: &[TimelineTrackSaveState],
+        tempo_map: &TempoMap,
+    ) -> Result<(MusicalTime, MusicalTime), ExportError> {
+        let (start, end) = match self {
+            ExportRange::WholeSong => {
+                let mut end = SampleTime::new(0);
+                for track in timeline_tracks.iter() {
+                    for audio_clip in track.audio_clips.iter() {
+                        let (_, clip_end) =
+                            timeline_range(audio_clip, tempo_map, track.groove.as_ref());
+                        if clip_end > end {
+                            end = clip_end;
+                        }
+                    }
+                }
+
+                (MusicalTime::new(0.0), tempo_map.sample_to_musical(end))
+            }
+            ExportRange::LoopRegion => match loop_state {
+                LoopState::Active { loop_start, loop_end } => (*loop_start, *loop_end),
+                LoopState::Inactive => return Err(ExportError::LoopInactive),
+            },
+            ExportRange::Selection { start, end } => (*start, *end),
+        };
+
+        if !(end.0 > start.0) {
+            return Err(ExportError::EmptyRange { start, end });
+        }
+
+        Ok((start, end))
+    }
+}
+
+/// The settings used to export audio to a file.
+#[derive(Debug, Clone)]
+pub struct ExportSettings {
+    /// The path of the file to write to. Any existing file is replaced.
+    pub path: PathBuf,
+
+    pub range: ExportRange,
+    pub format: ExportFormat,
+    pub bit_depth: WavBitDepth,
+    pub sample_rate: SampleRate,
+
+    /// The dither used when converting to an integer bit depth.
+    pub dither: DitherMode,
+    /// The seed of the dither noise. The same seed always produces the same output.
+    pub dither_seed: u64,
+}
+
+/// Where the signal of each track is taken from when exporting stems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StemTapPoint {
+    /// Before the track's fader.
+    PreFader,
+    /// After the track's fader.
+    PostFader,
+}
+
+/// The settings used to export every timeline track to its own file.
+#[derive(Debug, Clone)]
+pub struct StemExportSettings {
+    /// The directory the files are written to. Each file is named after its track, and
+    /// any existing files are replaced.
+    pub directory: PathBuf,
+
+    pub range: ExportRange,
+    pub format: ExportFormat,
+    pub bit_depth: WavBitDepth,
+    pub sample_rate: SampleRate,
+
+    /// The dither used when converting to an integer bit depth.
+    pub dither: DitherMode,
+    /// The seed of the dither noise. The same seed always produces the same output.
+    pub dither_seed: u64,
+
+    /// Where the signal of each track is taken from.
+    ///
+    /// TODO: Tracks don't have faders yet, so both tap points currently give the same
+    /// output.
+    pub tap_point: StemTapPoint,
+
+    /// Whether or not each stem is sent through the processing on the master output.
+    ///
+    /// TODO: There is no processing on the master output yet, so this currently has no
+    /// effect.
+    pub include_master: bool,
+}
+
+/// Find a name for the file of each stem.
+///
+/// Characters which are not allowed in file names are replaced, and a number is added
+/// to the names of tracks which share a name with an earlier track.
+pub fn stem_file_names(
+    timeline_tracks: &[TimelineTrackSaveState],
+    format: ExportFormat,
+) -> Vec<String> {
+    let mut names = Vec::<String>::with_capacity(timeline_tracks.len());
+
+    for (i, track) in timeline_tracks.iter().enumerate() {
+        let mut base: String = track
+            .name
+            .trim()
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+        if base.is_empty() || base.chars().all(|c| c == '.') {
+            base = format!("Track {}", i + 1);
+        }
+
+        let extension = format.extension();
+        let mut name = format!("{}.{}", base, extension);
+        let mut n = 2;
+        while names.iter().any(|other| other.eq_ignore_ascii_case(&name)) {
+            name = format!("{} {}.{}", base, n, extension);
+            n += 1;
+        }
+
+        names.push(name);
+    }
+
+    names
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ExportError {
+    EmptyRange {
+        start: MusicalTime,
+        end: MusicalTime,
+    },
+    LoopInactive,
+    /// Some of the audio used by the project could not be loaded.
+    ResourceLoad(Vec<ResourceLoadError>),
+    /// The audio graph for the project could not be built.
+    Graph(String),
+    Render(OfflineRenderError),
+    CouldNotCreateFile((PathBuf, std::io::Error)),
+    ErrorWhileWriting((PathBuf, std::io::Error)),
+    FileTooLarge(PathBuf),
+    CouldNotCreateDirectory((PathBuf, std::io::Error)),
+    UnsupportedBitDepth {
+        format: ExportFormat,
+        bit_depth: WavBitDepth,
+    },
+}
+
+impl Error for ExportError {}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ExportError::*;
+
+        match self {
+            EmptyRange { start, end } => {
+                write!(f, "Failed to export: range is empty | start: {:?} | end: {:?}", start, end)
+            }
+            LoopInactive => write!(f, "Failed to export: the loop region is not active"),
+            ResourceLoad(errors) => {
+                write!(f, "Failed to export: {} resource(s) failed to load", errors.len())?;
+                for e in errors.iter() {
+                    write!(f, "\n    {}", e)?;
+                }
+                Ok(())
+            }
+            Graph(e) => write!(f, "Failed to export: could not build audio graph | {}", e),
+            Render(e) => write!(f, "Failed to export: {}", e),
+            CouldNotCreateFile((path, e)) => {
+                write!(f, "Failed to export: could not create file | {} | path: {:?}", e, path)
+            }
+            ErrorWhileWriting((path, e)) => {
+                write!(f, "Failed to export: error while writing file | {} | path: {:?}", e, path)
+            }
+            FileTooLarge(path) => {
+                write!(f, "Failed to export: file is too large | path: {:?}", path)
+            }
+            CouldNotCreateDirectory((path, e)) => {
+                write!(f, "Failed to export: could not create directory | {} | path: {:?}", e, path)
+            }
+            UnsupportedBitDepth { format, bit_depth } => write!(
+                f,
+                "Failed to export: bit depth is not supported by the format | format: {:?} | bit depth: {:?}",
+                format, bit_depth
+            ),
+        }
+    }
+}
+
+impl From<Vec<ResourceLoadError>> for ExportError {
+    fn from(errors: Vec<ResourceLoadError>) -> Self {
+        ExportError::ResourceLoad(errors)
+    }
+}
+
+impl From<OfflineRenderError> for ExportError {
+    fn from(e: OfflineRenderError) -> Self {
+        ExportError::Render(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::timeline::TimelineTrackId;
+
+    #[test]
+    fn stem_names() {
+        let track = |name: &str| TimelineTrackSaveState {
+            id: TimelineTrackId::new(),
+            name: String::from(name),
+            audio_clips: Vec::new(),
+            groove: None,
+        };
+
+        let names = stem_file_names(
+            &[track("Drums"), track("Bass/Synth"), track("drums"), track(" "), track("Drums")],
+            ExportFormat::Wav,
+        );
+
+        assert_eq!(
+            names,
+            vec!["Drums.wav", "Bass_Synth.wav", "drums 2.wav", "Track 4.wav", "Drums 3.wav"]
+        );
+
+        let names = stem_file_names(&[track("Drums")], ExportFormat::Flac);
+        assert_eq!(names, vec!["Drums.flac"]);
+    }
+
+    #[test]
+    fn whole_song_uses_groove() {
+        use crate::backend::timeline::{
+            AudioClipFades, AudioClipId, AudioClipSaveState, Groove, SwingDivision,
+        };
+        use rusty_daw_core::{SampleRate, Seconds};
+
+        // At 120 BPM and 48 kHz, one beat is 24000 samples long.
+        let tempo_map = TempoMap::new(120.0, SampleRate::new(48_000.0));
+
+        // The clip starts on the second 8th note, which the swing delays by a quarter beat.
+        let track = TimelineTrackSaveState {
+            id: TimelineTrackId::new(),
+            name: String::from("Drums"),
+            audio_clips: vec![AudioClipSaveState {
+                id: AudioClipId::new(),
+                name: String::from("Drums"),
+                pcm_path: PathBuf::new(),
+                timeline_start: MusicalTime::new(0.5),
+                duration: Seconds(1.0),
+                clip_start_offset: Seconds(0.0),
+                clip_gain_db: 0.0,
+                fades: AudioClipFades::no_fade(),
+            }],
+            groove: Some(Groove::swing(SwingDivision::Eighth, 75.0)),
+        };
+
+        let (start, end) =
+            ExportRange::WholeSong.resolve(&LoopState::Inactive, &[track], &tempo_map).unwrap();
+        assert_eq!(start, MusicalTime::new(0.0));
+        assert!((end.0 - 2.75).abs() < 1.0e-6);
+    }
+}
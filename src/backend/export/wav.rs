@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::backend::dsp::dither::{DitherMode, Ditherer};
+use crate::backend::RenderedAudio;
+
+use super::ExportError;
+
+/// The format of the samples in an exported WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavBitDepth {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl WavBitDepth {
+    #[inline]
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            WavBitDepth::Int16 => 2,
+            WavBitDepth::Int24 => 3,
+            WavBitDepth::Float32 => 4,
+        }
+    }
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+const NUM_CHANNELS: u16 = 2;
+
+/// Write the given audio to a stereo WAV file, replacing the file if it already exists.
+///
+/// The dither is only applied to integer formats. The same `dither_seed` always
+/// produces the same file.
+pub fn write_wav(
+    path: &Path,
+    audio: &RenderedAudio,
+    bit_depth: WavBitDepth,
+    dither: DitherMode,
+    dither_seed: u64,
+) -> Result<(), ExportError> {
+    let data_len = audio.len() * NUM_CHANNELS as usize * bit_depth.bytes_per_sample();
+    if data_len > (u32::MAX as usize) - 64 {
+        return Err(ExportError::FileTooLarge(path.to_path_buf()));
+    }
+
+    let file =
+        File::create(path).map_err(|e| ExportError::CouldNotCreateFile((path.to_path_buf(), e)))?;
+    let mut writer = BufWriter::new(file);
+
+    write_wav_to(&mut writer, audio, bit_depth, dither, dither_seed)
+        .and_then(|_| writer.flush())
+        .map_err(|e| ExportError::ErrorWhileWriting((path.to_path_buf(), e)))
+}
+
+fn write_wav_to<W: Write>(
+    writer: &mut W,
+    audio: &RenderedAudio,
+    bit_depth: WavBitDepth,
+    dither: DitherMode,
+    dither_seed: u64,
+) -> io::Result<()> {
+    let bytes_per_sample = bit_depth.bytes_per_sample() as u32;
+    let block_align = u32::from(NUM_CHANNELS) * bytes_per_sample;
+    let data_len = audio.len() as u32 * block_align;
+    let sample_rate = audio.sample_rate.0.round() as u32;
+
+    let is_float = bit_depth == WavBitDepth::Float32;
+
+    // Non-PCM formats need an extended "fmt " chunk and a "fact" chunk.
+    let fmt_len: u32 = if is_float { 18 } else { 16 };
+    let fact_len: u32 = if is_float { 12 } else { 0 };
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(4 + (8 + fmt_len) + fact_len + (8 + data_len)).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&fmt_len.to_le_bytes())?;
+    writer.write_all(
+        &(if is_float { WAVE_FORMAT_IEEE_FLOAT } else { WAVE_FORMAT_PCM }).to_le_bytes(),
+    )?;
+    writer.write_all(&NUM_CHANNELS.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align).to_le_bytes())?;
+    writer.write_all(&(block_align as u16).to_le_bytes())?;
+    writer.write_all(&(bytes_per_sample as u16 * 8).to_le_bytes())?;
+
+    if is_float {
+        // Size of the extension.
+        writer.write_all(&0u16.to_le_bytes())?;
+
+        writer.write_all(b"fact")?;
+        writer.write_all(&4u32.to_le_bytes())?;
+        writer.write_all(&(audio.len() as u32).to_le_bytes())?;
+    }
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+
+    let bits = bytes_per_sample * 8;
+    let mut ditherer =
+        Ditherer::new(dither, bits, usize::from(NUM_CHANNELS), audio.sample_rate, dither_seed);
+
+    for (&l, &r) in audio.left.iter().zip(audio.right.iter()) {
+        for (channel, &s) in [l, r].iter().enumerate() {
+            match bit_depth {
+                WavBitDepth::Int16 => {
+                    let s = ditherer.quantize(channel, s) as i16;
+                    writer.write_all(&s.to_le_bytes())?;
+                }
+                WavBitDepth::Int24 => {
+                    let s = ditherer.quantize(channel, s);
+                    writer.write_all(&s.to_le_bytes()[0..3])?;
+                }
+                WavBitDepth::Float32 => {
+                    writer.write_all(&s.to_le_bytes())?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_daw_core::SampleRate;
+
+    use super::*;
+
+    #[test]
+    fn wav_header() {
+        let audio = RenderedAudio {
+            left: vec![0.0, 1.0, -1.0],
+            right: vec![0.5, 0.0, 2.0],
+            sample_rate: SampleRate::new(48_000.0),
+        };
+
+        let mut bytes = Vec::<u8>::new();
+        write_wav_to(&mut bytes, &audio, WavBitDepth::Int16, DitherMode::None, 0).unwrap();
+
+        assert_eq!(bytes.len(), 44 + (3 * 2 * 2));
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]), 36 + 12);
+        assert_eq!(u32::from_le_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]), 48_000);
+        assert_eq!(i16::from_le_bytes([bytes[46], bytes[47]]), 16384);
+        assert_eq!(i16::from_le_bytes([bytes[54], bytes[55]]), i16::MAX);
+
+        let mut bytes = Vec::<u8>::new();
+        write_wav_to(&mut bytes, &audio, WavBitDepth::Int24, DitherMode::None, 0).unwrap();
+        assert_eq!(bytes.len(), 44 + (3 * 2 * 3));
+
+        let mut bytes = Vec::<u8>::new();
+        write_wav_to(&mut bytes, &audio, WavBitDepth::Float32, DitherMode::None, 0).unwrap();
+        assert_eq!(bytes.len(), 58 + (3 * 2 * 4));
+        assert_eq!(&bytes[50..54], b"data");
+    }
+}
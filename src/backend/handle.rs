@@ -12,7 +12,8 @@ use std::time::Duration;
 use crate::backend::resource_loader::ResourceLoader;
 use crate::backend::save_state::BackendSaveState;
 use crate::backend::timeline::{
-    AudioClipResourceCache, TimelineTransport, TimelineTransportHandle, TimelineTransportSaveState,
+    AudioClipResourceCache, Groove, TempoMap, TimeSignatureMap, TimelineTransport,
+    TimelineTransportHandle, TimelineTransportSaveState,
 };
 
 use super::MAX_BLOCKSIZE;
@@ -37,6 +38,16 @@ pub struct GlobalNodeData {
     pub transport: TimelineTransport,
 }
 
+/// Why the audio graph could not be modified.
+#[derive(Debug)]
+pub enum ModifyGraphError {
+    /// The graph did not compile. The audio thread keeps running the last graph that
+    /// compiled.
+    Compiler(CompilerError),
+    /// The graph was never handed over to be modified.
+    NotModified,
+}
+
 pub struct BackendHandle {
     graph_interface: GraphInterface<GlobalNodeData, MAX_BLOCKSIZE>,
 
@@ -124,8 +135,17 @@ impl BackendHandle {
             TimelineTransport::new(coll_handle.clone(), sample_rate);
 
         timeline_transport_handle._update_tempo_map(save_state.tempo_map.clone());
+        timeline_transport_handle._update_time_signature_map(save_state.time_signature_map.clone());
         timeline_transport_handle
             .seek_to(save_state.timeline_transport.seek_to, &mut save_state.timeline_transport);
+        timeline_transport_handle.set_count_in_mode(
+            save_state.timeline_transport.count_in_mode,
+            &mut save_state.timeline_transport,
+        );
+        timeline_transport_handle.set_playback_rate(
+            save_state.timeline_transport.playback_rate,
+            &mut save_state.timeline_transport,
+        );
         if let Err(_) = timeline_transport_handle.set_loop_state(
             save_state.timeline_transport.loop_state.clone(),
             &mut save_state.timeline_transport,
@@ -136,6 +156,16 @@ impl BackendHandle {
             );
         }
 
+        if let Err(_) = timeline_transport_handle.set_punch_state(
+            save_state.timeline_transport.punch_state.clone(),
+            &mut save_state.timeline_transport,
+        ) {
+            log::error!(
+                "Failed to set punch state on timeline transport: {:?}",
+                save_state.timeline_transport.punch_state
+            );
+        }
+
         let (graph_interface, rt_graph_interface) = GraphInterface::new(
             sample_rate,
             coll_handle.clone(),
@@ -167,11 +197,46 @@ impl BackendHandle {
         self.timeline_transport._update_tempo_map(save_state.tempo_map.clone());
     }
 
+    /// Replace the entire tempo map of the project.
+    ///
+    /// Note that all `TimelineTrackHandle`s must also be updated with the new tempo map
+    /// using `TimelineTrackHandle::update_tempo_map()`.
+    pub fn set_tempo_map(&mut self, mut tempo_map: TempoMap, save_state: &mut BackendSaveState) {
+        tempo_map.sample_rate = self.sample_rate;
+
+        save_state.tempo_map = tempo_map;
+
+        self.timeline_transport._update_tempo_map(save_state.tempo_map.clone());
+    }
+
+    /// Replace the entire time signature map of the project.
+    pub fn set_time_signature_map(
+        &mut self,
+        time_signature_map: TimeSignatureMap,
+        save_state: &mut BackendSaveState,
+    ) {
+        save_state.time_signature_map = time_signature_map;
+
+        self.timeline_transport._update_time_signature_map(save_state.time_signature_map.clone());
+    }
+
+    /// Set the global groove of the project.
+    ///
+    /// Note that all `TimelineTrackHandle`s must also be updated with the new tempo map
+    /// using `TimelineTrackHandle::update_tempo_map()`.
+    pub fn set_groove(&mut self, groove: Groove, save_state: &mut BackendSaveState) {
+        save_state.tempo_map.set_groove(groove);
+
+        self.timeline_transport._update_tempo_map(save_state.tempo_map.clone());
+    }
+
     // We are using a closure for all modifications to the graph instead of using individual methods to act on
     // the graph. This is so the graph only gets compiled once after the user is done, instead of being recompiled
     // after every method.
-    // TODO: errors and reverting to previous working state
-    pub fn modify_graph<
+    //
+    // If the graph fails to compile, the audio thread keeps running the last graph that compiled, but the changes
+    // stay in the graph. Use `modify_graph_or_revert()` to undo them.
+    pub(crate) fn modify_graph<
         F: FnOnce(GraphStateRef<'_, GlobalNodeData, MAX_BLOCKSIZE>, &ResourceCache),
     >(
         &mut self,
@@ -181,6 +246,45 @@ impl BackendHandle {
         self.graph_interface.modify_graph(|g| f(g, &resource_cache))
     }
 
+    /// Modify the graph as a single transaction.
+    ///
+    /// `f` makes the changes and returns what is needed to undo them (i.e. the references
+    /// to any new nodes). If the graph fails to compile, `revert` is called with that
+    /// value to bring the graph back to the last state that compiled, and the error is
+    /// returned.
+    pub fn modify_graph_or_revert<
+        T,
+        F: FnOnce(GraphStateRef<'_, GlobalNodeData, MAX_BLOCKSIZE>, &ResourceCache) -> T,
+        R: FnOnce(GraphStateRef<'_, GlobalNodeData, MAX_BLOCKSIZE>, &ResourceCache, T),
+    >(
+        &mut self,
+        f: F,
+        revert: R,
+    ) -> Result<(T, Option<CompilerWarning>), ModifyGraphError> {
+        let mut changes = None;
+        let res = self.modify_graph(|graph, resource_cache| {
+            changes = Some(f(graph, resource_cache));
+        });
+
+        match (res, changes) {
+            (Ok(warning), Some(changes)) => Ok((changes, warning)),
+            (Ok(_), None) => Err(ModifyGraphError::NotModified),
+            (Err(e), Some(changes)) => {
+                if let Err(revert_e) = self
+                    .modify_graph(|graph, resource_cache| revert(graph, resource_cache, changes))
+                {
+                    log::error!(
+                        "Failed to revert audio graph to the last working state: {:?}",
+                        revert_e
+                    );
+                }
+                Err(ModifyGraphError::Compiler(e))
+            }
+            // Nothing was changed, so there is nothing to revert.
+            (Err(e), None) => Err(ModifyGraphError::Compiler(e)),
+        }
+    }
+
     pub fn timeline_transport<'a>(
         &self,
         save_state: &'a BackendSaveState,
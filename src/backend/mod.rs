@@ -1,13 +1,16 @@
 pub mod cpu_id;
 pub mod dsp;
+pub mod export;
 pub mod handle;
 pub mod hardware_io;
+pub mod offline_render;
 pub mod resource_loader;
 pub mod rt_thread;
 pub mod save_state;
 pub mod timeline;
 
 pub use handle::*;
+pub use offline_render::{OfflineRenderError, OfflineRenderer, RenderedAudio};
 pub use resource_loader::*;
 pub use save_state::*;
 
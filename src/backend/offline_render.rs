@@ -0,0 +1,202 @@
+use std::error::Error;
+use std::fmt;
+
+use basedrop::{Shared, SharedCell};
+use rusty_daw_audio_graph::AudioGraphExecutor;
+use rusty_daw_core::{MusicalTime, SampleRate, SampleTime, Seconds};
+
+use super::timeline::{CountInMode, LoopState};
+use super::{BackendHandle, BackendSaveState, GlobalNodeData, MAX_BLOCKSIZE};
+
+/// Before a render starts and after it is done, the graph runs (with its output discarded)
+/// for this long so any fades from starting, seeking or stopping the transport are
+/// finished outside of the rendered range.
+pub static OFFLINE_RENDER_SETTLE_TIME: Seconds = Seconds(50.0 / 1_000.0);
+
+/// The audio produced by an [`OfflineRenderer`].
+///
+/// [`OfflineRenderer`]: struct.OfflineRenderer.html
+#[derive(Debug, Clone)]
+pub struct RenderedAudio {
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+
+    pub sample_rate: SampleRate,
+}
+
+impl RenderedAudio {
+    /// The length of the rendered audio in frames.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+}
+
+#[derive(Debug)]
+pub enum OfflineRenderError {
+    /// The end of the range is not after the start.
+    InvalidRange { start: MusicalTime, end: MusicalTime },
+    /// The progress callback asked for the render to stop.
+    Cancelled,
+}
+
+impl Error for OfflineRenderError {}
+
+impl fmt::Display for OfflineRenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OfflineRenderError::InvalidRange { start, end } => {
+                write!(f, "Failed to render: invalid range | start: {:?} | end: {:?}", start, end)
+            }
+            OfflineRenderError::Cancelled => write!(f, "Render cancelled"),
+        }
+    }
+}
+
+/// Runs an audio graph and its `TimelineTransport` faster than realtime.
+///
+/// This owns its own `BackendHandle` and executor, so they must not be shared with a
+/// running audio stream. Build the graph to render through `backend_handle_mut()`
+/// before calling `render()`.
+pub struct OfflineRenderer {
+    backend_handle: BackendHandle,
+    executor: Shared<SharedCell<AudioGraphExecutor<GlobalNodeData, MAX_BLOCKSIZE>>>,
+
+    /// Interleaved output of the executor for a single block.
+    interleaved: Vec<f32>,
+}
+
+impl OfflineRenderer {
+    pub fn new(
+        backend_handle: BackendHandle,
+        executor: Shared<SharedCell<AudioGraphExecutor<GlobalNodeData, MAX_BLOCKSIZE>>>,
+    ) -> Self {
+        Self { backend_handle, executor, interleaved: vec![0.0; MAX_BLOCKSIZE * 2] }
+    }
+
+    /// Create a new renderer with an empty graph from the given save state.
+    pub fn from_save_state(sample_rate: SampleRate, save_state: &mut BackendSaveState) -> Self {
+        let (backend_handle, executor) = BackendHandle::from_save_state(sample_rate, save_state);
+        Self::new(backend_handle, executor)
+    }
+
+    /// Render the range `[start, end)` on the timeline.
+    ///
+    /// The loop, count-in, and playback rate of the transport are ignored while rendering
+    /// (and restored afterwards).
+    ///
+    /// `progress` is called after every block with the fraction of the range that has been
+    /// rendered so far (between `0.0` and `1.0`). Return `false` from it to cancel the
+    /// render.
+    pub fn render<P: FnMut(f32) -> bool>(
+        &mut self,
+        start: MusicalTime,
+        end: MusicalTime,
+        save_state: &mut BackendSaveState,
+        mut progress: P,
+    ) -> Result<RenderedAudio, OfflineRenderError> {
+        let start_smp = save_state.tempo_map.musical_to_nearest_sample_round(start);
+        let end_smp = save_state.tempo_map.musical_to_nearest_sample_round(end);
+        if end_smp <= start_smp {
+            return Err(OfflineRenderError::InvalidRange { start, end });
+        }
+        let total_frames = (end_smp - start_smp).0 as usize;
+
+        let sample_rate = self.backend_handle.sample_rate();
+        let settle_frames = (OFFLINE_RENDER_SETTLE_TIME.0 * sample_rate.0).round() as usize;
+
+        // Start playing a little before the range so the transport has finished fading in
+        // (and crossfading out of its previous position) by the time the range starts.
+        let prime_start = save_state
+            .tempo_map
+            .sample_to_musical(start_smp - SampleTime::from_usize(settle_frames));
+
+        let prev_state = save_state.timeline_transport.clone();
+
+        {
+            let (transport, transport_save_state) =
+                self.backend_handle.timeline_transport_mut(save_state);
+
+            transport.set_count_in_mode(CountInMode::Off, transport_save_state);
+            transport.set_playback_rate(1.0, transport_save_state);
+            // Disabling the loop never fails.
+            let _ = transport.set_loop_state(LoopState::Inactive, transport_save_state);
+            transport.seek_to(prime_start, transport_save_state);
+            transport.set_playing(true, transport_save_state);
+        }
+
+        self.run_discarded(settle_frames);
+
+        let mut rendered = RenderedAudio {
+            left: Vec::with_capacity(total_frames),
+            right: Vec::with_capacity(total_frames),
+            sample_rate,
+        };
+
+        let mut cancelled = false;
+        while rendered.len() < total_frames {
+            let frames = (total_frames - rendered.len()).min(MAX_BLOCKSIZE);
+
+            self.process_block(frames);
+
+            for frame in self.interleaved[0..frames * 2].chunks_exact(2) {
+                rendered.left.push(frame[0]);
+                rendered.right.push(frame[1]);
+            }
+
+            if !progress(rendered.len() as f32 / total_frames as f32) {
+                cancelled = true;
+                break;
+            }
+        }
+
+        {
+            let (transport, transport_save_state) =
+                self.backend_handle.timeline_transport_mut(save_state);
+
+            transport.set_playing(false, transport_save_state);
+            transport.set_count_in_mode(prev_state.count_in_mode, transport_save_state);
+            transport.set_playback_rate(prev_state.playback_rate, transport_save_state);
+            let _ = transport.set_loop_state(prev_state.loop_state, transport_save_state);
+            transport.seek_to(prev_state.seek_to, transport_save_state);
+        }
+
+        // Let the transport finish stopping so the next render starts cleanly.
+        self.run_discarded(settle_frames);
+
+        if cancelled {
+            return Err(OfflineRenderError::Cancelled);
+        }
+
+        Ok(rendered)
+    }
+
+    /// Run the graph for the given number of frames and throw away its output.
+    fn run_discarded(&mut self, frames: usize) {
+        let mut done = 0;
+        while done < frames {
+            let block_frames = (frames - done).min(MAX_BLOCKSIZE);
+            self.process_block(block_frames);
+            done += block_frames;
+        }
+    }
+
+    fn process_block(&mut self, frames: usize) {
+        let data = &mut self.interleaved[0..frames * 2];
+        for s in data.iter_mut() {
+            *s = 0.0;
+        }
+
+        self.executor.get().process(data, |mut global_node_data, frames| {
+            global_node_data.transport.process(frames);
+        });
+    }
+
+    pub fn backend_handle(&self) -> &BackendHandle {
+        &self.backend_handle
+    }
+
+    pub fn backend_handle_mut(&mut self) -> &mut BackendHandle {
+        &mut self.backend_handle
+    }
+}
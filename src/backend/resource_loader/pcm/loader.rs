@@ -229,6 +229,24 @@ pub enum PcmLoadError {
     ErrorWhileDecoding((PathBuf, symphonia::core::errors::Error)),
 }
 
+impl PcmLoadError {
+    /// The path of the file that could not be loaded.
+    pub fn path(&self) -> &PathBuf {
+        use PcmLoadError::*;
+
+        match self {
+            PathNotFound((path, _)) => path,
+            UnkownFormat((path, _)) => path,
+            NoTrackFound(path) => path,
+            NoChannelsFound(path) => path,
+            UnkownChannelFormat((path, _)) => path,
+            FileTooLarge(path) => path,
+            CouldNotCreateDecoder((path, _)) => path,
+            ErrorWhileDecoding((path, _)) => path,
+        }
+    }
+}
+
 impl Error for PcmLoadError {}
 
 impl fmt::Display for PcmLoadError {
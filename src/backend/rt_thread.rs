@@ -2,21 +2,24 @@ use basedrop::{Shared, SharedCell};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use log::info;
 use rusty_daw_audio_graph::AudioGraphExecutor;
+use rusty_daw_core::SampleRate;
 
+use super::dsp::dither::{DitherMode, Ditherer, DEFAULT_DITHER_SEED};
 use super::{GlobalNodeData, MAX_BLOCKSIZE};
 
 // This function is temporary. Eventually we should use rusty-daw-io instead.
 pub fn run_with_default_output(
     executor: Shared<SharedCell<AudioGraphExecutor<GlobalNodeData, MAX_BLOCKSIZE>>>,
+    dither: DitherMode,
 ) -> Result<cpal::Stream, ()> {
     let host = cpal::default_host();
     let device = host.default_output_device().ok_or_else(|| ())?;
     let config = device.default_output_config().map_err(|_| ())?;
 
     let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => run::<f32>(&device, &config.into(), executor)?,
-        cpal::SampleFormat::I16 => run::<i16>(&device, &config.into(), executor)?,
-        cpal::SampleFormat::U16 => run::<u16>(&device, &config.into(), executor)?,
+        cpal::SampleFormat::F32 => run::<f32>(&device, &config.into(), executor, dither)?,
+        cpal::SampleFormat::I16 => run::<i16>(&device, &config.into(), executor, dither)?,
+        cpal::SampleFormat::U16 => run::<u16>(&device, &config.into(), executor, dither)?,
     };
 
     Ok(stream)
@@ -26,6 +29,7 @@ pub fn run<T>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
     executor: Shared<SharedCell<AudioGraphExecutor<GlobalNodeData, MAX_BLOCKSIZE>>>,
+    dither: DitherMode,
 ) -> Result<cpal::Stream, ()>
 where
     T: cpal::Sample,
@@ -35,14 +39,39 @@ where
 
     let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
 
+    // Integer formats are processed in `f32` first so they can be dithered.
+    let mut ditherer = match T::FORMAT {
+        cpal::SampleFormat::F32 => None,
+        cpal::SampleFormat::I16 | cpal::SampleFormat::U16 => {
+            let sample_rate = SampleRate::new(f64::from(config.sample_rate.0));
+            Some(Ditherer::new(dither, 16, channels, sample_rate, DEFAULT_DITHER_SEED))
+        }
+    };
+    let mut scratch: Vec<f32> = vec![0.0; MAX_BLOCKSIZE * channels];
+
     let stream = device
         .build_output_stream(
             config,
             move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-                // Where the magic happens!
-                executor.get().process(data, |mut global_node_data, frames| {
-                    global_node_data.transport.process(frames);
-                });
+                if let Some(ditherer) = &mut ditherer {
+                    for chunk in data.chunks_mut(scratch.len()) {
+                        let scratch = &mut scratch[0..chunk.len()];
+
+                        executor.get().process(scratch, |mut global_node_data, frames| {
+                            global_node_data.transport.process(frames);
+                        });
+
+                        for (i, (out, s)) in chunk.iter_mut().zip(scratch.iter()).enumerate() {
+                            let s = ditherer.quantize(i % channels, *s) as i16;
+                            *out = T::from(&s);
+                        }
+                    }
+                } else {
+                    // Where the magic happens!
+                    executor.get().process(data, |mut global_node_data, frames| {
+                        global_node_data.transport.process(frames);
+                    });
+                }
             },
             err_fn,
         )
@@ -1,7 +1,8 @@
 use rusty_daw_core::{SampleRate, Seconds};
+#[cfg(feature = "gui")]
 use tuix::Lens;
 
-use crate::backend::timeline::TempoMap;
+use crate::backend::timeline::{MetronomeSaveState, TempoMap, TimeSignatureMap};
 
 use crate::backend::timeline::{
     audio_clip::DEFAULT_AUDIO_CLIP_DECLICK_TIME, TimelineTransportSaveState,
@@ -10,11 +11,14 @@ use crate::backend::timeline::{
 /// This struct should contain all information needed to create a "save file"
 /// for the backend.
 ///
-/// TODO: Project file format. This will need to be future-proof.
-#[derive(Debug, Clone, Lens)]
+/// This is saved to disk as part of the project file (see `project::load_project_file`).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "gui", derive(Lens))]
 pub struct BackendSaveState {
     pub timeline_transport: TimelineTransportSaveState,
     pub tempo_map: TempoMap,
+    pub time_signature_map: TimeSignatureMap,
+    pub metronome: MetronomeSaveState,
     pub audio_clip_declick_time: Seconds,
 }
 
@@ -23,16 +27,24 @@ impl Default for BackendSaveState {
         Self {
             timeline_transport: TimelineTransportSaveState::default(),
             tempo_map: TempoMap::default(),
+            time_signature_map: TimeSignatureMap::default(),
+            metronome: MetronomeSaveState::default(),
             audio_clip_declick_time: DEFAULT_AUDIO_CLIP_DECLICK_TIME,
         }
     }
 }
 
 impl BackendSaveState {
-    pub fn new(timeline_transport: TimelineTransportSaveState, tempo_map: TempoMap) -> Self {
+    pub fn new(
+        timeline_transport: TimelineTransportSaveState,
+        tempo_map: TempoMap,
+        time_signature_map: TimeSignatureMap,
+    ) -> Self {
         Self {
             timeline_transport,
             tempo_map,
+            time_signature_map,
+            metronome: MetronomeSaveState::default(),
             audio_clip_declick_time: DEFAULT_AUDIO_CLIP_DECLICK_TIME,
         }
     }
@@ -43,6 +55,8 @@ impl BackendSaveState {
         Self {
             timeline_transport: self.timeline_transport.clone(),
             tempo_map,
+            time_signature_map: self.time_signature_map.clone(),
+            metronome: self.metronome.clone(),
             audio_clip_declick_time: self.audio_clip_declick_time,
         }
     }
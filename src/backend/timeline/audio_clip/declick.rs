@@ -25,14 +25,15 @@ pub struct AudioClipDeclick {
     seek_crossfade_in: SmoothF32<MAX_BLOCKSIZE>,
     seek_crossfade_out: SmoothF32<MAX_BLOCKSIZE>,
 
-    stop_fade_playhead: Option<SampleTime>,
-    stop_fade_next_playhead: SampleTime,
+    // The playheads of fading out audio, as `(playhead, sub-sample part)` pairs.
+    stop_fade_playhead: Option<(SampleTime, f64)>,
+    stop_fade_next_playhead: (SampleTime, f64),
 
-    loop_crossfade_out_playhead: SampleTime,
-    loop_crossfade_out_next_playhead: SampleTime,
+    loop_crossfade_out_playhead: (SampleTime, f64),
+    loop_crossfade_out_next_playhead: (SampleTime, f64),
 
-    seek_crossfade_out_playhead: SampleTime,
-    seek_crossfade_out_next_playhead: SampleTime,
+    seek_crossfade_out_playhead: (SampleTime, f64),
+    seek_crossfade_out_next_playhead: (SampleTime, f64),
 
     playing: bool,
     active: bool,
@@ -67,13 +68,13 @@ impl AudioClipDeclick {
             seek_crossfade_out,
 
             stop_fade_playhead: None,
-            stop_fade_next_playhead: SampleTime(0),
+            stop_fade_next_playhead: (SampleTime(0), 0.0),
 
-            loop_crossfade_out_playhead: SampleTime(0),
-            loop_crossfade_out_next_playhead: SampleTime(0),
+            loop_crossfade_out_playhead: (SampleTime(0), 0.0),
+            loop_crossfade_out_next_playhead: (SampleTime(0), 0.0),
 
-            seek_crossfade_out_playhead: SampleTime(0),
-            seek_crossfade_out_next_playhead: SampleTime(0),
+            seek_crossfade_out_playhead: (SampleTime(0), 0.0),
+            seek_crossfade_out_next_playhead: (SampleTime(0), 0.0),
 
             playing: false,
             active: false,
@@ -82,26 +83,29 @@ impl AudioClipDeclick {
 
     pub fn update_tempo_map(&mut self, old_tempo_map: &TempoMap, new_tempo_map: &TempoMap) {
         if self.stop_fade_playhead.is_some() {
-            let mt = old_tempo_map.sample_to_musical(self.stop_fade_next_playhead);
-            self.stop_fade_next_playhead = new_tempo_map.musical_to_nearest_sample_round(mt);
+            let mt = old_tempo_map.sample_to_musical(self.stop_fade_next_playhead.0);
+            self.stop_fade_next_playhead = (new_tempo_map.musical_to_nearest_sample_round(mt), 0.0);
         }
 
         if self.seek_crossfade_out.is_active() {
-            let mt = old_tempo_map.sample_to_musical(self.seek_crossfade_out_next_playhead);
+            let mt = old_tempo_map.sample_to_musical(self.seek_crossfade_out_next_playhead.0);
             self.seek_crossfade_out_next_playhead =
-                new_tempo_map.musical_to_nearest_sample_round(mt);
+                (new_tempo_map.musical_to_nearest_sample_round(mt), 0.0);
         }
 
         if self.loop_crossfade_out.is_active() {
-            let mt = old_tempo_map.sample_to_musical(self.loop_crossfade_out_next_playhead);
+            let mt = old_tempo_map.sample_to_musical(self.loop_crossfade_out_next_playhead.0);
             self.loop_crossfade_out_next_playhead =
-                new_tempo_map.musical_to_nearest_sample_round(mt);
+                (new_tempo_map.musical_to_nearest_sample_round(mt), 0.0);
         }
     }
 
     pub fn process(&mut self, frames: usize, timeline: &TimelineTransport) {
         let frames = frames.min(MAX_BLOCKSIZE);
 
+        // The playheads of fading out audio move at the same rate as the transport.
+        let timeline_len = frames as f64 * timeline.playback_rate();
+
         let mut just_stopped = false;
 
         if self.stop_fade_playhead.is_some() {
@@ -109,7 +113,7 @@ impl AudioClipDeclick {
                 self.stop_fade_playhead = None;
             } else {
                 self.stop_fade_playhead = Some(self.stop_fade_next_playhead);
-                self.stop_fade_next_playhead += SampleTime::from_usize(frames);
+                self.stop_fade_next_playhead = advance(self.stop_fade_next_playhead, timeline_len);
             }
         }
 
@@ -124,13 +128,15 @@ impl AudioClipDeclick {
                 self.start_stop_fade.set(0.0);
                 just_stopped = true;
 
-                self.stop_fade_playhead = Some(timeline.playhead());
-                self.stop_fade_next_playhead = timeline.playhead() + SampleTime::from_usize(frames);
+                let playhead = (timeline.playhead(), timeline.playhead_fract());
+                self.stop_fade_playhead = Some(playhead);
+                self.stop_fade_next_playhead = advance(playhead, timeline_len);
             }
         }
 
-        // Process the start/stop fades.
-        self.start_stop_fade.process(frames);
+        // Process the start/stop fades. When a count-in ends in the middle of this process
+        // cycle, the fade in starts at the frame where playback starts.
+        self.start_stop_fade.process(frames - timeline.play_offset().min(frames));
         self.start_stop_fade.update_status();
 
         // If the transport is not playing and did not just stop playing, then don't
@@ -155,15 +161,17 @@ impl AudioClipDeclick {
             self.seek_crossfade_out.process(frames);
             self.loop_crossfade_out.update_status();
 
-            self.seek_crossfade_out_playhead = seek_info.seeked_from_playhead;
+            self.seek_crossfade_out_playhead =
+                (seek_info.seeked_from_playhead, seek_info.seeked_from_playhead_fract);
             self.seek_crossfade_out_next_playhead =
-                seek_info.seeked_from_playhead + SampleTime::from_usize(frames);
+                advance(self.seek_crossfade_out_playhead, timeline_len);
         } else {
             // Process any still-active seek crossfades.
 
             if self.seek_crossfade_out.is_active() {
                 self.seek_crossfade_out_playhead = self.seek_crossfade_out_next_playhead;
-                self.seek_crossfade_out_next_playhead += SampleTime::from_usize(frames);
+                self.seek_crossfade_out_next_playhead =
+                    advance(self.seek_crossfade_out_next_playhead, timeline_len);
             }
 
             self.seek_crossfade_in.process(frames);
@@ -173,9 +181,8 @@ impl AudioClipDeclick {
             self.seek_crossfade_out.update_status();
         }
 
-        if let Some(loop_back) = timeline.do_loop_back() {
-            let second_frames =
-                ((loop_back.playhead_end - loop_back.loop_start).0 as usize).min(MAX_BLOCKSIZE);
+        if let Some(jump) = timeline.playhead_jump() {
+            let second_frames = frames - jump.first_frames.min(frames);
 
             // Start the crossfade.
 
@@ -193,15 +200,16 @@ impl AudioClipDeclick {
                 self.loop_crossfade_out.update_status();
             }
 
-            self.loop_crossfade_out_playhead = timeline.playhead();
+            self.loop_crossfade_out_playhead = (timeline.playhead(), timeline.playhead_fract());
             self.loop_crossfade_out_next_playhead =
-                timeline.playhead() + SampleTime::from_usize(frames);
+                advance(self.loop_crossfade_out_playhead, timeline_len);
         } else {
             // Process any still-active loop crossfades.
 
             if self.loop_crossfade_out.is_active() {
                 self.loop_crossfade_out_playhead = self.loop_crossfade_out_next_playhead;
-                self.loop_crossfade_out_next_playhead += SampleTime::from_usize(frames);
+                self.loop_crossfade_out_next_playhead =
+                    advance(self.loop_crossfade_out_next_playhead, timeline_len);
             }
 
             self.loop_crossfade_in.process(frames);
@@ -223,7 +231,9 @@ impl AudioClipDeclick {
         self.active
     }
 
-    pub fn stop_fade_playhead(&self) -> Option<SampleTime> {
+    /// The playhead of the audio fading out after stopping, as a `(playhead, sub-sample part)`
+    /// pair.
+    pub fn stop_fade_playhead(&self) -> Option<(SampleTime, f64)> {
         self.stop_fade_playhead
     }
 
@@ -235,7 +245,7 @@ impl AudioClipDeclick {
         self.loop_crossfade_in.output()
     }
 
-    pub fn loop_crossfade_out(&self) -> (SmoothOutputF32<MAX_BLOCKSIZE>, SampleTime) {
+    pub fn loop_crossfade_out(&self) -> (SmoothOutputF32<MAX_BLOCKSIZE>, (SampleTime, f64)) {
         (self.loop_crossfade_out.output(), self.loop_crossfade_out_playhead)
     }
 
@@ -243,7 +253,14 @@ impl AudioClipDeclick {
         self.seek_crossfade_in.output()
     }
 
-    pub fn seek_crossfade_out(&self) -> (SmoothOutputF32<MAX_BLOCKSIZE>, SampleTime) {
+    pub fn seek_crossfade_out(&self) -> (SmoothOutputF32<MAX_BLOCKSIZE>, (SampleTime, f64)) {
         (self.seek_crossfade_out.output(), self.seek_crossfade_out_playhead)
     }
 }
+
+/// Move a `(playhead, sub-sample part)` pair forward by `len` samples on the timeline.
+#[inline]
+fn advance(playhead: (SampleTime, f64), len: f64) -> (SampleTime, f64) {
+    let end = playhead.1 + len;
+    (playhead.0 + SampleTime::new(end.floor() as i64), end.fract())
+}
@@ -7,12 +7,13 @@ use rusty_daw_core::{
 };
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+#[cfg(feature = "gui")]
 use tuix::Lens;
 
 use crate::backend::resource_loader::{AnyPcm, PcmLoadError, ResourceLoader};
 use crate::backend::{ResourceCache, MAX_BLOCKSIZE};
 
-use super::{AudioClipSaveState, TempoMap};
+use super::{AudioClipId, AudioClipSaveState, Groove, TempoMap};
 
 mod declick;
 mod resource;
@@ -23,7 +24,8 @@ pub use resource::{AudioClipResource, AudioClipResourceCache};
 pub static AUDIO_CLIP_GAIN_MIN_DB: f32 = -40.0;
 pub static AUDIO_CLIP_GAIN_MAX_DB: f32 = 40.0;
 
-#[derive(Debug, Clone, Copy, Lens)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "gui", derive(Lens))]
 pub struct AudioClipFades {
     pub start_fade_duration: Seconds,
     pub end_fade_duration: Seconds,
@@ -93,6 +95,21 @@ impl Default for AudioClipFades {
     }
 }
 
+/// Find where the clip starts and ends on the timeline, with the groove applied to the
+/// start of the clip.
+pub(crate) fn timeline_range(
+    save_state: &AudioClipSaveState,
+    tempo_map: &TempoMap,
+    track_groove: Option<&Groove>,
+) -> (SampleTime, SampleTime) {
+    let start_secs = tempo_map.event_to_seconds(save_state.timeline_start, track_groove);
+
+    (
+        tempo_map.seconds_to_nearest_sample_round(start_secs),
+        tempo_map.seconds_to_nearest_sample_round(start_secs + save_state.duration),
+    )
+}
+
 #[derive(Clone)]
 struct AudioClipFadesProcInfo {
     start_fade_duration: usize,
@@ -106,13 +123,22 @@ struct AudioClipFadesProcInfo {
 }
 
 pub struct AudioClipHandle {
+    id: AudioClipId,
+
     clip_gain_db: ParamF32Handle,
 
+    /// The groove of the track this clip belongs to (if it overrides the global groove).
+    track_groove: Option<Groove>,
+
     info: Shared<SharedCell<AudioClipProcInfo>>,
     coll_handle: Handle,
 }
 
 impl AudioClipHandle {
+    pub fn id(&self) -> AudioClipId {
+        self.id
+    }
+
     /// Set the name displayed on this audio clip.
     pub fn set_name(&mut self, name: String, save_state: &mut AudioClipSaveState) {
         save_state.name = name;
@@ -141,11 +167,10 @@ impl AudioClipHandle {
         save_state.timeline_start = timeline_start;
 
         let mut new_info = AudioClipProcInfo::clone(&self.info.get());
-        new_info.timeline_start =
-            tempo_map.musical_to_nearest_sample_round(save_state.timeline_start);
-        new_info.timeline_end = tempo_map.seconds_to_nearest_sample_round(
-            tempo_map.musical_to_seconds(save_state.timeline_start) + save_state.duration,
-        );
+        let (timeline_start, timeline_end) =
+            timeline_range(save_state, tempo_map, self.track_groove.as_ref());
+        new_info.timeline_start = timeline_start;
+        new_info.timeline_end = timeline_end;
         new_info.fades = save_state.fades.to_proc_info(
             tempo_map.sample_rate,
             new_info.timeline_start,
@@ -165,9 +190,8 @@ impl AudioClipHandle {
         save_state.duration = duration;
 
         let mut new_info = AudioClipProcInfo::clone(&self.info.get());
-        new_info.timeline_end = tempo_map.seconds_to_nearest_sample_round(
-            tempo_map.musical_to_seconds(save_state.timeline_start) + save_state.duration,
-        );
+        let (_, timeline_end) = timeline_range(save_state, tempo_map, self.track_groove.as_ref());
+        new_info.timeline_end = timeline_end;
         new_info.fades = save_state.fades.to_proc_info(
             tempo_map.sample_rate,
             new_info.timeline_start,
@@ -194,6 +218,9 @@ impl AudioClipHandle {
     }
 
     /// Set the PCM resource to use from the given path to an audio file.
+    ///
+    /// This can be used while the clip is playing. If the file could not be loaded, the
+    /// clip will play silence.
     pub fn set_pcm(
         &mut self,
         pcm_path: PathBuf,
@@ -201,10 +228,10 @@ impl AudioClipHandle {
         cache: &Arc<Mutex<AudioClipResourceCache>>,
         save_state: &mut AudioClipSaveState,
     ) -> Result<(), PcmLoadError> {
-        let (resource, pcm_load_res) = { cache.lock().unwrap().cache(save_state, resource_loader) };
-
         save_state.pcm_path = pcm_path;
 
+        let (resource, pcm_load_res) = { cache.lock().unwrap().cache(save_state, resource_loader) };
+
         let mut new_info = AudioClipProcInfo::clone(&self.info.get());
         new_info.resource = resource;
 
@@ -231,17 +258,28 @@ impl AudioClipHandle {
         self.info.set(Shared::new(&self.coll_handle, new_info));
     }
 
+    /// Set the groove of the track this clip belongs to. `None` means the clip follows
+    /// the global groove.
+    pub(super) fn set_track_groove(
+        &mut self,
+        track_groove: Option<Groove>,
+        tempo_map: &TempoMap,
+        save_state: &AudioClipSaveState,
+    ) {
+        self.track_groove = track_groove;
+        self.update_tempo_map(tempo_map, save_state);
+    }
+
     pub(super) fn update_tempo_map(
         &mut self,
         tempo_map: &TempoMap,
         save_state: &AudioClipSaveState,
     ) {
         let mut new_info = AudioClipProcInfo::clone(&self.info.get());
-        new_info.timeline_start =
-            tempo_map.musical_to_nearest_sample_round(save_state.timeline_start);
-        new_info.timeline_end = tempo_map.seconds_to_nearest_sample_round(
-            tempo_map.musical_to_seconds(save_state.timeline_start) + save_state.duration,
-        );
+        let (timeline_start, timeline_end) =
+            timeline_range(save_state, tempo_map, self.track_groove.as_ref());
+        new_info.timeline_start = timeline_start;
+        new_info.timeline_end = timeline_end;
         new_info.fades = save_state.fades.to_proc_info(
             tempo_map.sample_rate,
             new_info.timeline_start,
@@ -272,6 +310,8 @@ pub(super) struct AudioClipProcInfo {
 
 #[derive(Clone)]
 pub struct AudioClipProcess {
+    id: AudioClipId,
+
     // Wrapping params in a shared pointer so we can clone this struct when compiling
     // a new list of processes. This should never cause a panic because this struct is the
     // only place this is ever borrowed.
@@ -285,6 +325,7 @@ impl AudioClipProcess {
         save_state: &AudioClipSaveState,
         resource_cache: &ResourceCache,
         tempo_map: &TempoMap,
+        track_groove: Option<&Groove>,
         coll_handle: &Handle,
     ) -> (Self, AudioClipHandle, Result<(), PcmLoadError>) {
         let clip_gain_db =
@@ -308,10 +349,7 @@ impl AudioClipProcess {
                 .cache(save_state, &resource_cache.resource_loader)
         };
 
-        let timeline_start = tempo_map.musical_to_nearest_sample_round(save_state.timeline_start);
-        let timeline_end = tempo_map.seconds_to_nearest_sample_round(
-            tempo_map.musical_to_seconds(save_state.timeline_start) + save_state.duration,
-        );
+        let (timeline_start, timeline_end) = timeline_range(save_state, tempo_map, track_groove);
 
         let info = Shared::new(
             coll_handle,
@@ -335,20 +373,39 @@ impl AudioClipProcess {
 
         (
             Self {
+                id: save_state.id,
                 params: Shared::new(
                     &coll_handle,
                     AtomicRefCell::new(AudioClipParams { clip_gain_amp: gain_amp }),
                 ),
                 info: Shared::clone(&info),
             },
-            AudioClipHandle { clip_gain_db: gain_handle, info, coll_handle: coll_handle.clone() },
+            AudioClipHandle {
+                id: save_state.id,
+                clip_gain_db: gain_handle,
+                track_groove: track_groove.cloned(),
+                info,
+                coll_handle: coll_handle.clone(),
+            },
             pcm_load_res,
         )
     }
 
+    pub fn id(&self) -> AudioClipId {
+        self.id
+    }
+
+    /// Fill the output buffer with samples from this audio clip.
+    ///
+    /// * `playhead` - The position on the timeline of the first frame.
+    /// * `playhead_fract` - The sub-sample part of `playhead` (between `0.0` and `1.0`).
+    /// * `playback_rate` - The number of samples on the timeline the playhead moves each
+    /// frame.
     pub fn process(
         &self,
         playhead: SampleTime,
+        playhead_fract: f64,
+        playback_rate: f64,
         frames: usize,
         out: &mut StereoBlockBuffer<f32, MAX_BLOCKSIZE>,
         out_offset: usize,
@@ -358,6 +415,21 @@ impl AudioClipProcess {
         let mut params = self.params.borrow_mut();
         let amp = params.clip_gain_amp.smoothed(frames);
 
+        if playback_rate != 1.0 || playhead_fract != 0.0 {
+            // The samples don't line up 1:1 with the output, so we need to resample.
+            simd::process_varispeed_fallback(
+                playhead,
+                playhead_fract,
+                playback_rate,
+                &*info,
+                out,
+                &amp,
+                out_offset,
+                frames,
+            );
+            return;
+        }
+
         let mut copy_frames = frames;
         let mut copy_out_offset = out_offset;
         let mut skip = 0;
@@ -422,6 +494,84 @@ mod simd {
     use crate::backend::MAX_BLOCKSIZE;
     use rusty_daw_core::{SampleTime, SmoothOutputF32};
 
+    /// Fill samples into the output buffer when the playhead does not move at a rate of
+    /// exactly one sample per frame.
+    ///
+    /// TODO: Use something better than linear interpolation.
+    pub(super) fn process_varispeed_fallback(
+        playhead: SampleTime,
+        playhead_fract: f64,
+        playback_rate: f64,
+        info: &AudioClipProcInfo,
+        out: &mut StereoBlockBuffer<f32, MAX_BLOCKSIZE>,
+        amp: &SmoothOutputF32<MAX_BLOCKSIZE>,
+        out_offset: usize,
+        frames: usize,
+    ) {
+        // Hint to compiler to optimize loops.
+        let frames = frames.min(MAX_BLOCKSIZE - out_offset.min(MAX_BLOCKSIZE));
+
+        let pcm_len = info.resource.pcm.len();
+
+        // The position of the first frame relative to the start of the clip on the timeline.
+        let clip_start = (playhead - info.timeline_start).0 as f64 + playhead_fract;
+        let clip_len = (info.timeline_end - info.timeline_start).0 as f64;
+
+        // The position of the first frame in the PCM resource.
+        let pcm_start =
+            clip_start + (info.clip_start_offset - info.resource.original_offset).0 as f64;
+
+        let start_fade_end = (info.fades.start_fade_timeline_end - info.timeline_start).0 as f64;
+        let end_fade_start = (info.fades.end_fade_timeline_start - info.timeline_start).0 as f64;
+
+        for i in 0..frames {
+            let offset = i as f64 * playback_rate;
+
+            let clip_pos = clip_start + offset;
+            let pcm_pos = pcm_start + offset;
+            if clip_pos < 0.0 || pcm_pos < 0.0 {
+                // Not reached the start of the clip yet (add silence).
+                continue;
+            }
+            if clip_pos >= clip_len || pcm_pos >= pcm_len as f64 {
+                // Past the end of the clip (add silence).
+                break;
+            }
+
+            let index = pcm_pos as usize;
+            let next_index = (index + 1).min(pcm_len - 1);
+            let fract = (pcm_pos - index as f64) as f32;
+
+            let (left, right) = match &*info.resource.pcm {
+                AnyPcm::Mono(pcm) => {
+                    let data = pcm.data();
+                    let s = data[index] + ((data[next_index] - data[index]) * fract);
+                    (s, s)
+                }
+                AnyPcm::Stereo(pcm) => {
+                    let (l, r) = pcm.left_right();
+                    (
+                        l[index] + ((l[next_index] - l[index]) * fract),
+                        r[index] + ((r[next_index] - r[index]) * fract),
+                    )
+                }
+            };
+
+            let mut total_amp = amp[i];
+            if clip_pos < start_fade_end {
+                total_amp *= (clip_pos as f32 * info.fades.start_fade_delta).min(1.0);
+            }
+            if clip_pos >= end_fade_start {
+                total_amp *= (1.0
+                    - ((clip_pos - end_fade_start) as f32 * info.fades.end_fade_delta))
+                    .max(0.0);
+            }
+
+            out.left[out_offset + i] += left * total_amp;
+            out.right[out_offset + i] += right * total_amp;
+        }
+    }
+
     pub(super) fn process_fallback(
         playhead: SampleTime,
         info: &AudioClipProcInfo,
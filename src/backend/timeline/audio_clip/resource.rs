@@ -184,7 +184,11 @@ impl AudioClipResourceCache {
             let new_key =
                 ResourceKey { pcm_path: state.pcm_path.clone(), resampled_type, effect_params };
 
-            let _ = self.resources.insert(new_key, Shared::clone(&new_resource));
+            // Don't cache the empty resource used when loading failed, so the file is loaded
+            // again the next time (i.e. after it was relinked).
+            if pcm_load_res.is_ok() {
+                let _ = self.resources.insert(new_key, Shared::clone(&new_resource));
+            }
 
             (new_resource, pcm_load_res)
         }
@@ -0,0 +1,273 @@
+use rusty_daw_core::MusicalTime;
+
+/// The lowest swing amount (straight timing).
+pub static MIN_SWING_PERCENT: f64 = 50.0;
+/// The highest swing amount.
+pub static MAX_SWING_PERCENT: f64 = 75.0;
+
+/// The note value that swing is applied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwingDivision {
+    /// Swing every second 8th note.
+    Eighth,
+    /// Swing every second 16th note.
+    Sixteenth,
+}
+
+impl SwingDivision {
+    /// The length of a pair of notes in units of [`MusicalTime`] (quarter notes).
+    ///
+    /// [`MusicalTime`]: ../struct.MusicalTime.html
+    #[inline]
+    pub fn pair_len(&self) -> f64 {
+        match self {
+            SwingDivision::Eighth => 1.0,
+            SwingDivision::Sixteenth => 0.5,
+        }
+    }
+}
+
+/// A repeating pattern of timing offsets, usually imported from a recorded performance.
+///
+/// The template maps "straight" positions inside a cycle of `len` beats to their "grooved"
+/// positions. Positions in between are interpolated linearly, so the ordering of events
+/// is never changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrooveTemplate {
+    pub name: String,
+
+    len: f64,
+
+    /// `(straight, grooved)` pairs in units of beats from the start of the cycle, sorted by
+    /// position. The start and end of the cycle are implicitly mapped to themselves.
+    points: Vec<(f64, f64)>,
+}
+
+impl GrooveTemplate {
+    /// Create a new groove template.
+    ///
+    /// * `len` - The length of one cycle of the template in beats.
+    /// * `points` - `(straight, grooved)` pairs of positions from the start of the cycle.
+    ///
+    /// This will return an error if `len` is not positive, if any position lies outside of
+    /// the cycle, or if either the straight or the grooved positions are not strictly
+    /// increasing.
+    pub fn new(
+        name: String,
+        len: MusicalTime,
+        points: Vec<(MusicalTime, MusicalTime)>,
+    ) -> Result<Self, ()> {
+        let len = len.0;
+        if !(len.is_finite() && len > 0.0) {
+            return Err(());
+        }
+
+        let mut prev = (0.0, 0.0);
+        for &(straight, grooved) in points.iter() {
+            if straight.0 <= prev.0
+                || grooved.0 <= prev.1
+                || straight.0 >= len
+                || grooved.0 >= len
+                || !grooved.0.is_finite()
+            {
+                return Err(());
+            }
+            prev = (straight.0, grooved.0);
+        }
+
+        Ok(Self { name, len, points: points.iter().map(|(s, g)| (s.0, g.0)).collect() })
+    }
+
+    /// Import a groove template from the positions of hits in a recorded performance.
+    ///
+    /// Each hit is matched to its nearest grid line (within half a grid step), and the
+    /// average offset of all hits on a grid line becomes that grid line's groove. Grid lines
+    /// that would reorder events are ignored.
+    ///
+    /// * `len` - The length of one cycle of the template in beats (usually one bar).
+    /// * `grid` - The spacing between grid lines in beats (i.e. `0.25` for 16th notes).
+    pub fn from_hits(
+        name: String,
+        len: MusicalTime,
+        grid: MusicalTime,
+        hits: &[MusicalTime],
+    ) -> Result<Self, ()> {
+        let (len, grid) = (len.0, grid.0);
+        if !(len.is_finite() && grid.is_finite() && grid > 0.0 && len >= grid) {
+            return Err(());
+        }
+
+        let n_slots = (len / grid).round() as usize;
+        let mut offsets = vec![(0.0, 0usize); n_slots];
+
+        for hit in hits.iter() {
+            let pos = hit.0.rem_euclid(len);
+            let slot = (pos / grid).round();
+            let offset = pos - (slot * grid);
+
+            // The last grid line wraps around to the start of the cycle.
+            let slot = slot as usize % n_slots;
+
+            offsets[slot].0 += offset;
+            offsets[slot].1 += 1;
+        }
+
+        let mut points = Vec::<(MusicalTime, MusicalTime)>::new();
+        let mut prev_grooved = 0.0;
+        for (slot, &(offset_sum, n_hits)) in offsets.iter().enumerate().skip(1) {
+            if n_hits == 0 {
+                continue;
+            }
+
+            let straight = slot as f64 * grid;
+            let grooved = straight + (offset_sum / n_hits as f64);
+
+            if grooved > prev_grooved && grooved < len {
+                points.push((MusicalTime(straight), MusicalTime(grooved)));
+                prev_grooved = grooved;
+            }
+        }
+
+        Self::new(name, MusicalTime(len), points)
+    }
+
+    /// The length of one cycle of this template in beats.
+    #[inline]
+    pub fn len(&self) -> MusicalTime {
+        MusicalTime(self.len)
+    }
+
+    /// The `(straight, grooved)` pairs of positions in this template, sorted by position.
+    pub fn points(&self) -> impl Iterator<Item = (MusicalTime, MusicalTime)> + '_ {
+        self.points.iter().map(|&(s, g)| (MusicalTime(s), MusicalTime(g)))
+    }
+
+    fn warp(&self, beats: f64) -> f64 {
+        let cycle_start = (beats / self.len).floor() * self.len;
+        let pos = beats - cycle_start;
+
+        let i = self.points.partition_point(|p| p.0 <= pos);
+
+        let (s0, g0) = if i == 0 { (0.0, 0.0) } else { self.points[i - 1] };
+        let (s1, g1) = self.points.get(i).copied().unwrap_or((self.len, self.len));
+
+        cycle_start + g0 + ((pos - s0) * (g1 - g0) / (s1 - s0))
+    }
+}
+
+/// The groove (swing) applied to events on the timeline.
+///
+/// A groove only changes *when* events are played. The positions stored in the project
+/// (in [`MusicalTime`]) are never modified.
+///
+/// [`MusicalTime`]: ../struct.MusicalTime.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum Groove {
+    /// Straight timing.
+    None,
+    /// Delay every second note of the given division.
+    Swing {
+        division: SwingDivision,
+        /// The position of the swung note as a percentage of the length of a pair of notes,
+        /// where `50.0` is straight and `66.7` is a triplet feel.
+        percent: f64,
+    },
+    /// Use an imported groove template.
+    Template(GrooveTemplate),
+}
+
+impl Groove {
+    /// Create a new swing groove. The amount will be clamped to the range
+    /// `[MIN_SWING_PERCENT, MAX_SWING_PERCENT]`.
+    pub fn swing(division: SwingDivision, percent: f64) -> Self {
+        let percent = if percent.is_nan() { MIN_SWING_PERCENT } else { percent };
+
+        Groove::Swing { division, percent: percent.clamp(MIN_SWING_PERCENT, MAX_SWING_PERCENT) }
+    }
+
+    /// Returns `true` if this groove does not change the timing of any events.
+    pub fn is_straight(&self) -> bool {
+        match self {
+            Groove::None => true,
+            Groove::Swing { percent, .. } => *percent <= MIN_SWING_PERCENT,
+            Groove::Template(template) => template.points.iter().all(|(s, g)| s == g),
+        }
+    }
+
+    /// Return the position where an event at `musical_time` should actually be played.
+    pub fn warp(&self, musical_time: MusicalTime) -> MusicalTime {
+        match self {
+            Groove::None => musical_time,
+            Groove::Swing { division, percent } => {
+                let pair_len = division.pair_len();
+                let swung = (percent / 100.0).clamp(0.5, 0.75);
+
+                let pair_start = (musical_time.0 / pair_len).floor() * pair_len;
+                let pos = (musical_time.0 - pair_start) / pair_len;
+
+                let warped = if pos < 0.5 {
+                    pos * swung * 2.0
+                } else {
+                    swung + ((pos - 0.5) * (1.0 - swung) * 2.0)
+                };
+
+                MusicalTime(pair_start + (warped * pair_len))
+            }
+            Groove::Template(template) => MusicalTime(template.warp(musical_time.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: MusicalTime, b: f64) {
+        assert!((a.0 - b).abs() < 1.0e-9, "{} != {}", a.0, b);
+    }
+
+    #[test]
+    fn groove_swing() {
+        let groove = Groove::swing(SwingDivision::Eighth, 60.0);
+
+        assert_close(groove.warp(MusicalTime::new(0.0)), 0.0);
+        assert_close(groove.warp(MusicalTime::new(0.5)), 0.6);
+        assert_close(groove.warp(MusicalTime::new(1.0)), 1.0);
+        assert_close(groove.warp(MusicalTime::new(3.5)), 3.6);
+        assert_close(groove.warp(MusicalTime::new(0.25)), 0.3);
+
+        let groove = Groove::swing(SwingDivision::Sixteenth, 75.0);
+
+        assert_close(groove.warp(MusicalTime::new(0.25)), 0.375);
+        assert_close(groove.warp(MusicalTime::new(0.5)), 0.5);
+
+        assert!(Groove::swing(SwingDivision::Eighth, 20.0).is_straight());
+    }
+
+    #[test]
+    fn groove_template() {
+        let hits = [
+            MusicalTime::new(0.0),
+            MusicalTime::new(1.1),
+            MusicalTime::new(2.0),
+            MusicalTime::new(3.04),
+            MusicalTime::new(5.06),
+        ];
+        let template =
+            GrooveTemplate::from_hits(String::from("test"), 4.0.into(), 1.0.into(), &hits).unwrap();
+        let groove = Groove::Template(template);
+
+        assert_close(groove.warp(MusicalTime::new(1.0)), 1.08);
+        assert_close(groove.warp(MusicalTime::new(2.0)), 2.0);
+        assert_close(groove.warp(MusicalTime::new(3.0)), 3.04);
+        assert_close(groove.warp(MusicalTime::new(7.0)), 7.04);
+        assert_close(groove.warp(MusicalTime::new(8.0)), 8.0);
+
+        assert!(GrooveTemplate::new(
+            String::from("invalid"),
+            4.0.into(),
+            vec![(2.0.into(), 2.5.into()), (3.0.into(), 2.4.into())],
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Tracks and clips share the same counter, so no two objects ever have the same ID.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Make sure an ID that was loaded from a project is never handed out again.
+fn reserve_id(id: u64) {
+    NEXT_ID.fetch_max(id.saturating_add(1), Ordering::Relaxed);
+}
+
+/// The unique ID of a timeline track. Unlike its index, this never changes when other
+/// tracks are added, removed or moved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimelineTrackId(u64);
+
+impl TimelineTrackId {
+    /// Create an ID that is not used by any other track or clip.
+    pub fn new() -> Self {
+        Self(next_id())
+    }
+
+    /// Use an ID that was saved in a project file.
+    pub fn from_raw(id: u64) -> Self {
+        reserve_id(id);
+        Self(id)
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for TimelineTrackId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for TimelineTrackId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The unique ID of an audio clip. Unlike its index, this never changes when other
+/// clips are added or removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AudioClipId(u64);
+
+impl AudioClipId {
+    /// Create an ID that is not used by any other track or clip.
+    pub fn new() -> Self {
+        Self(next_id())
+    }
+
+    /// Use an ID that was saved in a project file.
+    pub fn from_raw(id: u64) -> Self {
+        reserve_id(id);
+        Self(id)
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for AudioClipId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for AudioClipId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_ids() {
+        let track = TimelineTrackId::new();
+        let clip = AudioClipId::new();
+        assert_ne!(track.raw(), clip.raw());
+
+        // IDs loaded from a project are never handed out again.
+        let loaded = AudioClipId::from_raw(clip.raw() + 1000);
+        assert!(TimelineTrackId::new().raw() > loaded.raw());
+        assert!(AudioClipId::new().raw() > loaded.raw());
+    }
+}
@@ -0,0 +1,515 @@
+use basedrop::{Handle, Shared, SharedCell};
+use rusty_daw_audio_graph::node::{DB_GRADIENT, SMOOTH_SECS};
+use rusty_daw_audio_graph::{AudioGraphNode, ProcBuffers, ProcInfo};
+use rusty_daw_core::{MusicalTime, ParamF32, ParamF32Handle, SampleRate, SampleTime, Unit};
+use smallvec::SmallVec;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::backend::dsp::resample;
+use crate::backend::resource_loader::{
+    AnyPcm, MonoPcm, PcmLoadError, ResourceLoadError, ResourceLoader, StereoPcm,
+};
+use crate::backend::{GlobalNodeData, ResourceCache, MAX_BLOCKSIZE};
+
+use super::{MetronomeSaveState, TimelineTransport};
+
+pub static METRONOME_GAIN_MIN_DB: f32 = -60.0;
+pub static METRONOME_GAIN_MAX_DB: f32 = 12.0;
+
+/// The maximum number of clicks that can start in a single process cycle. Any clicks
+/// past this are ignored (this can only happen at absurdly high tempos).
+const MAX_CLICKS_PER_BLOCK: usize = 8;
+
+/// Which of the two click sounds to play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetronomeClick {
+    /// The click played on every beat.
+    Beat,
+    /// The accented click played on the first beat of every bar.
+    Downbeat,
+}
+
+pub struct MetronomeHandle {
+    gain_db: ParamF32Handle,
+
+    info: Shared<SharedCell<MetronomeProcInfo>>,
+
+    sample_rate: SampleRate,
+    coll_handle: Handle,
+}
+
+impl MetronomeHandle {
+    /// Enable/disable the metronome.
+    pub fn set_enabled(&mut self, enabled: bool, save_state: &mut MetronomeSaveState) {
+        save_state.enabled = enabled;
+
+        let mut new_info = MetronomeProcInfo::clone(&self.info.get());
+        new_info.enabled = enabled;
+
+        self.info.set(Shared::new(&self.coll_handle, new_info));
+    }
+
+    /// Set the volume of the metronome.
+    ///
+    /// Returns the gain (this may be clamped to fit within range of the gain parameter).
+    pub fn set_gain_db(&mut self, gain_db: f32, save_state: &mut MetronomeSaveState) -> f32 {
+        self.gain_db.set_value(gain_db);
+
+        // Make sure value is clamped within range.
+        let gain_db = self.gain_db.value();
+        save_state.gain_db = gain_db;
+
+        gain_db
+    }
+
+    /// Set the sample to use for the given click from the given path to an audio file. If
+    /// `pcm_path` is `None`, then the built-in click sound will be used.
+    ///
+    /// If the file could not be loaded, then the built-in click sound will be used and
+    /// an error will be returned.
+    pub fn set_click_sample(
+        &mut self,
+        click: MetronomeClick,
+        pcm_path: Option<PathBuf>,
+        resource_loader: &Arc<Mutex<ResourceLoader>>,
+        save_state: &mut MetronomeSaveState,
+    ) -> Result<(), PcmLoadError> {
+        let (pcm, pcm_load_res) = load_click(
+            click,
+            pcm_path.as_ref(),
+            resource_loader,
+            self.sample_rate,
+            &self.coll_handle,
+        );
+
+        let mut new_info = MetronomeProcInfo::clone(&self.info.get());
+        match click {
+            MetronomeClick::Beat => {
+                save_state.click_path = pcm_path;
+                new_info.click = pcm;
+            }
+            MetronomeClick::Downbeat => {
+                save_state.downbeat_click_path = pcm_path;
+                new_info.downbeat_click = pcm;
+            }
+        }
+
+        self.info.set(Shared::new(&self.coll_handle, new_info));
+
+        pcm_load_res
+    }
+}
+
+#[derive(Clone)]
+struct MetronomeProcInfo {
+    enabled: bool,
+
+    click: Shared<AnyPcm>,
+    downbeat_click: Shared<AnyPcm>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClickVoice {
+    click: MetronomeClick,
+    /// The current frame in the click sample.
+    pos: usize,
+}
+
+/// A node which plays a click on every beat while the timeline transport is playing.
+pub struct MetronomeNode {
+    gain_amp: ParamF32<MAX_BLOCKSIZE>,
+
+    info: Shared<SharedCell<MetronomeProcInfo>>,
+
+    voice: Option<ClickVoice>,
+}
+
+impl MetronomeNode {
+    pub fn new(
+        save_state: &MetronomeSaveState,
+        resource_cache: &ResourceCache,
+        sample_rate: SampleRate,
+        coll_handle: &Handle,
+    ) -> (Self, MetronomeHandle, Vec<ResourceLoadError>) {
+        let gain_db = save_state.gain_db.clamp(METRONOME_GAIN_MIN_DB, METRONOME_GAIN_MAX_DB);
+
+        let (gain_amp, gain_handle) = ParamF32::from_value(
+            gain_db,
+            METRONOME_GAIN_MIN_DB,
+            METRONOME_GAIN_MAX_DB,
+            DB_GRADIENT,
+            Unit::Decibels,
+            SMOOTH_SECS,
+            sample_rate,
+        );
+
+        let mut errors = Vec::<ResourceLoadError>::new();
+
+        let (click, res) = load_click(
+            MetronomeClick::Beat,
+            save_state.click_path.as_ref(),
+            &resource_cache.resource_loader,
+            sample_rate,
+            coll_handle,
+        );
+        if let Err(e) = res {
+            errors.push(ResourceLoadError::PCM(e));
+        }
+
+        let (downbeat_click, res) = load_click(
+            MetronomeClick::Downbeat,
+            save_state.downbeat_click_path.as_ref(),
+            &resource_cache.resource_loader,
+            sample_rate,
+            coll_handle,
+        );
+        if let Err(e) = res {
+            errors.push(ResourceLoadError::PCM(e));
+        }
+
+        let info = Shared::new(
+            coll_handle,
+            SharedCell::new(Shared::new(
+                coll_handle,
+                MetronomeProcInfo { enabled: save_state.enabled, click, downbeat_click },
+            )),
+        );
+
+        (
+            Self { gain_amp, info: Shared::clone(&info), voice: None },
+            MetronomeHandle {
+                gain_db: gain_handle,
+                info,
+                sample_rate,
+                coll_handle: coll_handle.clone(),
+            },
+            errors,
+        )
+    }
+
+    /// Find all the beats that land in the range `[start, end)` on the timeline (in
+    /// units of samples), and add them to `clicks` as `(frame offset, click)` pairs.
+    fn find_clicks(
+        transport: &TimelineTransport,
+        start: f64,
+        end: f64,
+        out_offset: usize,
+        clicks: &mut SmallVec<[(usize, MetronomeClick); MAX_CLICKS_PER_BLOCK]>,
+    ) {
+        let tempo_map = transport.tempo_map();
+        let time_signature_map = transport.time_signature_map();
+        let playback_rate = transport.playback_rate();
+
+        // Start searching one sample early since the beat that rounds to `start` could
+        // lie slightly before it.
+        let mut search_from =
+            tempo_map.sample_to_musical(SampleTime::new(start.floor() as i64 - 1));
+
+        while clicks.len() < MAX_CLICKS_PER_BLOCK {
+            let (beat, is_downbeat) = time_signature_map.next_beat_line(search_from);
+            let beat_smp = tempo_map.musical_to_nearest_sample_round(beat).0 as f64;
+
+            if beat_smp >= end {
+                break;
+            }
+
+            if beat_smp >= start {
+                let click =
+                    if is_downbeat { MetronomeClick::Downbeat } else { MetronomeClick::Beat };
+                let offset = ((beat_smp - start) / playback_rate).ceil() as usize;
+                clicks.push((offset + out_offset, click));
+            }
+
+            // Search for the beat after this one.
+            search_from = MusicalTime(beat.0 + 1.0e-6);
+        }
+    }
+
+    /// Find all the clicks in the current process cycle of `frames` frames, taking the
+    /// count-in and the playhead jumping into account. The count-in always clicks, but
+    /// clicks during playback are only added if `enabled` is `true`.
+    fn find_cycle_clicks(
+        transport: &TimelineTransport,
+        frames: usize,
+        enabled: bool,
+        clicks: &mut SmallVec<[(usize, MetronomeClick); MAX_CLICKS_PER_BLOCK]>,
+    ) {
+        let playback_rate = transport.playback_rate();
+
+        if let Some(count_in) = transport.count_in() {
+            let start = count_in.playhead.0 as f64;
+
+            // The last cycle of the count-in may end before the end of this process cycle.
+            let end = (start + (count_in.frames as f64 * playback_rate)).min(count_in.end.0 as f64);
+
+            Self::find_clicks(transport, start, end, 0, clicks);
+        }
+
+        if enabled && transport.is_playing() {
+            // Playback may start partway through this process cycle if a count-in just ended.
+            let play_offset = transport.play_offset().min(frames);
+            let playhead = transport.playhead().0 as f64
+                + transport.playhead_fract()
+                + (play_offset as f64 * playback_rate);
+
+            if let Some(jump) = transport.playhead_jump() {
+                let first_frames = jump.first_frames.min(frames);
+                let resume_at = jump.resume_at.0 as f64 + jump.resume_fract;
+                let second_len = (frames - first_frames) as f64 * playback_rate;
+
+                Self::find_clicks(
+                    transport,
+                    playhead,
+                    jump.jump_from.0 as f64,
+                    play_offset,
+                    clicks,
+                );
+                Self::find_clicks(
+                    transport,
+                    resume_at,
+                    resume_at + second_len,
+                    first_frames,
+                    clicks,
+                );
+            } else {
+                let end = playhead + ((frames - play_offset) as f64 * playback_rate);
+
+                Self::find_clicks(transport, playhead, end, play_offset, clicks);
+            }
+        }
+    }
+}
+
+impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for MetronomeNode {
+    fn debug_name(&self) -> &'static str {
+        "MetronomeNode"
+    }
+
+    fn indep_stereo_out_ports(&self) -> u32 {
+        1
+    }
+
+    fn process(
+        &mut self,
+        proc_info: &ProcInfo<MAX_BLOCKSIZE>,
+        buffers: ProcBuffers<f32, MAX_BLOCKSIZE>,
+        global_data: &GlobalNodeData,
+    ) {
+        if buffers.indep_stereo_out.is_empty() {
+            // Nothing to do.
+            return;
+        }
+
+        let stereo_out = &mut *buffers.indep_stereo_out[0].atomic_borrow_mut();
+        let frames = proc_info.frames();
+
+        // Clear output buffer to 0.0 because clicks will add their samples instead
+        // of overwriting them.
+        stereo_out.clear_frames(frames);
+
+        let transport = &global_data.transport;
+        let info = self.info.get();
+        let gain = self.gain_amp.smoothed(frames);
+
+        let count_in = transport.count_in();
+
+        if transport.did_seek().is_some() || !(transport.is_playing() || count_in.is_some()) {
+            // Don't let a click ring out after seeking or stopping.
+            self.voice = None;
+        }
+
+        // The count-in always clicks, even when the metronome is disabled.
+        if !info.enabled && count_in.is_none() {
+            self.voice = None;
+            return;
+        }
+
+        let mut clicks = SmallVec::<[(usize, MetronomeClick); MAX_CLICKS_PER_BLOCK]>::new();
+        Self::find_cycle_clicks(transport, frames, info.enabled, &mut clicks);
+
+        if self.voice.is_none() && clicks.is_empty() {
+            // Nothing to play.
+            return;
+        }
+
+        let mut next_click = 0;
+        for i in 0..frames {
+            while next_click < clicks.len() && clicks[next_click].0 <= i {
+                self.voice = Some(ClickVoice { click: clicks[next_click].1, pos: 0 });
+                next_click += 1;
+            }
+
+            if let Some(voice) = &mut self.voice {
+                let pcm = match voice.click {
+                    MetronomeClick::Beat => &*info.click,
+                    MetronomeClick::Downbeat => &*info.downbeat_click,
+                };
+
+                if voice.pos >= pcm.len() {
+                    self.voice = None;
+                    continue;
+                }
+
+                let (left, right) = match pcm {
+                    AnyPcm::Mono(pcm) => (pcm.data()[voice.pos], pcm.data()[voice.pos]),
+                    AnyPcm::Stereo(pcm) => (pcm.left()[voice.pos], pcm.right()[voice.pos]),
+                };
+
+                stereo_out.left[i] = left * gain[i];
+                stereo_out.right[i] = right * gain[i];
+
+                voice.pos += 1;
+            }
+        }
+    }
+}
+
+/// Load a click sample from disk, or use the built-in click sound if `pcm_path` is `None`
+/// or the file could not be loaded.
+fn load_click(
+    click: MetronomeClick,
+    pcm_path: Option<&PathBuf>,
+    resource_loader: &Arc<Mutex<ResourceLoader>>,
+    sample_rate: SampleRate,
+    coll_handle: &Handle,
+) -> (Shared<AnyPcm>, Result<(), PcmLoadError>) {
+    let pcm_path = if let Some(pcm_path) = pcm_path {
+        pcm_path
+    } else {
+        return (Shared::new(coll_handle, default_click(click, sample_rate)), Ok(()));
+    };
+
+    let (pcm, pcm_load_res) = { resource_loader.lock().unwrap().pcm_loader.load(pcm_path) };
+
+    if pcm_load_res.is_err() {
+        return (Shared::new(coll_handle, default_click(click, sample_rate)), pcm_load_res);
+    }
+
+    if pcm.sample_rate() == sample_rate || pcm.len() < 2 {
+        return (pcm, pcm_load_res);
+    }
+
+    // TODO: Use something better than linear resampling.
+    let resample_ratio = sample_rate.0 / pcm.sample_rate().0;
+    let resampled_pcm = match &*pcm {
+        AnyPcm::Mono(pcm) => AnyPcm::Mono(MonoPcm::new(
+            resample::linear_resample_non_rt_mono(pcm.data(), resample_ratio),
+            sample_rate,
+        )),
+        AnyPcm::Stereo(pcm) => {
+            let (res_l, res_r) =
+                resample::linear_resample_non_rt_stereo(pcm.left(), pcm.right(), resample_ratio);
+
+            AnyPcm::Stereo(StereoPcm::new(res_l, res_r, sample_rate))
+        }
+    };
+
+    (Shared::new(coll_handle, resampled_pcm), pcm_load_res)
+}
+
+/// Generate the built-in click sound (a short decaying sine blip).
+fn default_click(click: MetronomeClick, sample_rate: SampleRate) -> AnyPcm {
+    let (freq, amp) = match click {
+        MetronomeClick::Beat => (1_000.0, 0.5),
+        MetronomeClick::Downbeat => (1_500.0, 0.7),
+    };
+
+    let len = (sample_rate.0 * 0.03).round() as usize;
+    let decay = 1.0 / (sample_rate.0 * 0.006);
+
+    let data = (0..len)
+        .map(|i| {
+            let t = i as f64;
+            let phase = 2.0 * std::f64::consts::PI * freq * t / sample_rate.0;
+            (phase.sin() * (-t * decay).exp() * amp) as f32
+        })
+        .collect();
+
+    AnyPcm::Mono(MonoPcm::new(data, sample_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+    use rusty_daw_core::{MusicalTime, SampleRate};
+    use smallvec::SmallVec;
+
+    use super::{MetronomeClick, MetronomeNode, MAX_CLICKS_PER_BLOCK};
+    use crate::backend::timeline::{
+        LoopState, TimeSignature, TimeSignatureMap, TimelineTransport, TimelineTransportSaveState,
+    };
+
+    // At 120 BPM and 48 kHz, one beat is 24000 samples long.
+    const BEAT: f64 = 24_000.0;
+
+    fn cycle_clicks(transport: &TimelineTransport, frames: usize) -> Vec<(usize, MetronomeClick)> {
+        let mut clicks = SmallVec::<[(usize, MetronomeClick); MAX_CLICKS_PER_BLOCK]>::new();
+        MetronomeNode::find_cycle_clicks(transport, frames, true, &mut clicks);
+        clicks.to_vec()
+    }
+
+    #[test]
+    fn metronome_clicks_on_loop_back() {
+        let collector = Collector::new();
+        let (mut transport, mut handle) =
+            TimelineTransport::new(collector.handle(), SampleRate::new(48_000.0));
+        let mut save_state = TimelineTransportSaveState::default();
+
+        let loop_state = LoopState::Active {
+            loop_start: MusicalTime::new(0.0),
+            loop_end: MusicalTime::new(4.0),
+        };
+        handle.set_loop_state(loop_state, &mut save_state).unwrap();
+        handle.seek_to(MusicalTime::new((4.0 * BEAT - 64.0) / BEAT), &mut save_state);
+        handle.set_playing(true, &mut save_state);
+
+        // The beat at the loop end is never played, but the downbeat at the loop start is.
+        transport.process(128);
+        assert!(transport.do_loop_back().is_some());
+        assert_eq!(cycle_clicks(&transport, 128), vec![(64, MetronomeClick::Downbeat)]);
+    }
+
+    #[test]
+    fn metronome_clicks_after_seek() {
+        let collector = Collector::new();
+        let (mut transport, mut handle) =
+            TimelineTransport::new(collector.handle(), SampleRate::new(48_000.0));
+        let mut save_state = TimelineTransportSaveState::default();
+
+        handle.set_playing(true, &mut save_state);
+        transport.process(128);
+        assert_eq!(cycle_clicks(&transport, 128), vec![(0, MetronomeClick::Downbeat)]);
+
+        // Seeking onto a beat clicks right away.
+        handle.seek_to(MusicalTime::new(1.0), &mut save_state);
+        transport.process(128);
+        assert!(transport.did_seek().is_some());
+        assert_eq!(cycle_clicks(&transport, 128), vec![(0, MetronomeClick::Beat)]);
+
+        // Seeking between beats waits for the next one.
+        handle.seek_to(MusicalTime::new(1.5), &mut save_state);
+        transport.process(128);
+        assert!(cycle_clicks(&transport, 128).is_empty());
+    }
+
+    #[test]
+    fn metronome_clicks_follow_meter_changes() {
+        let collector = Collector::new();
+        let (mut transport, mut handle) =
+            TimelineTransport::new(collector.handle(), SampleRate::new(48_000.0));
+        let mut save_state = TimelineTransportSaveState::default();
+
+        let cue = MusicalTime::new((3.0 * BEAT - 64.0) / BEAT);
+        handle.seek_to(cue, &mut save_state);
+        handle.set_playing(true, &mut save_state);
+        transport.process(128);
+        assert_eq!(cycle_clicks(&transport, 128), vec![(64, MetronomeClick::Beat)]);
+
+        // In 3/4, the fourth beat is the first beat of the second bar.
+        handle._update_time_signature_map(TimeSignatureMap::new(TimeSignature::new(3, 4).unwrap()));
+        handle.seek_to(cue, &mut save_state);
+        transport.process(128);
+        assert_eq!(cycle_clicks(&transport, 128), vec![(64, MetronomeClick::Downbeat)]);
+    }
+}
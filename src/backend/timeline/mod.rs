@@ -1,16 +1,31 @@
 // TODO: Eventually this should be moved into the `rusty-daw-timeline` repo.
 
+mod groove;
+mod id;
 mod save_state;
 mod tempo_map;
+mod time_signature_map;
 
 pub mod audio_clip;
+pub mod metronome_node;
 pub mod timeline_track_node;
 pub mod transport;
 
 pub use audio_clip::{
     AudioClipFades, AudioClipHandle, AudioClipProcess, AudioClipResource, AudioClipResourceCache,
 };
-pub use save_state::{AudioClipSaveState, TimelineTrackSaveState, TimelineTransportSaveState};
-pub use tempo_map::TempoMap;
+pub use groove::{Groove, GrooveTemplate, SwingDivision};
+pub use id::{AudioClipId, TimelineTrackId};
+pub use metronome_node::{MetronomeClick, MetronomeHandle, MetronomeNode};
+pub use save_state::{
+    AudioClipSaveState, MetronomeSaveState, TimelineTrackSaveState, TimelineTransportSaveState,
+};
+pub use tempo_map::{TempoMap, TempoPoint, TempoRamp, MAX_BPM, MIN_BPM};
+pub use time_signature_map::{
+    BarsBeatsTicks, TimeSignature, TimeSignatureMap, TimeSignaturePoint, TICKS_PER_BEAT,
+};
 pub use timeline_track_node::{TimelineTrackHandle, TimelineTrackNode};
-pub use transport::{LoopState, TimelineTransport, TimelineTransportHandle};
+pub use transport::{
+    CountInMode, JumpMode, LoopState, Marker, PunchState, PunchStateProcInfo, StopBehavior,
+    TimelineTransport, TimelineTransportHandle, MAX_PLAYBACK_RATE, MIN_PLAYBACK_RATE,
+};
@@ -1,33 +1,90 @@
 use rusty_daw_core::{MusicalTime, Seconds};
 use std::path::PathBuf;
+#[cfg(feature = "gui")]
 use tuix::Lens;
 
-use super::{AudioClipFades, LoopState};
+use super::{
+    AudioClipFades, AudioClipId, CountInMode, Groove, LoopState, Marker, PunchState, StopBehavior,
+    TimelineTrackId,
+};
 
-#[derive(Debug, Clone, Copy, Lens)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "gui", derive(Lens))]
 pub struct TimelineTransportSaveState {
     pub seek_to: MusicalTime,
     pub loop_state: LoopState,
+    pub punch_state: PunchState,
+    pub count_in_mode: CountInMode,
+    pub playback_rate: f64,
+    pub stop_behavior: StopBehavior,
+
+    /// The markers on the timeline, sorted by position.
+    pub markers: Vec<Marker>,
 }
 
 impl Default for TimelineTransportSaveState {
     fn default() -> Self {
-        Self { seek_to: MusicalTime::new(0.0), loop_state: LoopState::Inactive }
+        Self {
+            seek_to: MusicalTime::new(0.0),
+            loop_state: LoopState::Inactive,
+            punch_state: PunchState::Inactive,
+            count_in_mode: CountInMode::Off,
+            playback_rate: 1.0,
+            stop_behavior: StopBehavior::ReturnToPlayStart,
+            markers: Vec::new(),
+        }
     }
 }
 
-#[derive(Debug, Clone, Lens)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "gui", derive(Lens))]
+pub struct MetronomeSaveState {
+    /// Whether or not the metronome clicks while the transport is playing.
+    pub enabled: bool,
+
+    /// The volume of the metronome in decibels.
+    pub gain_db: f32,
+
+    /// The path to the audio file played on every beat. If this is `None`, then the
+    /// built-in click sound is used.
+    pub click_path: Option<PathBuf>,
+
+    /// The path to the audio file played on the first beat of every bar. If this is
+    /// `None`, then the built-in click sound is used.
+    pub downbeat_click_path: Option<PathBuf>,
+}
+
+impl Default for MetronomeSaveState {
+    fn default() -> Self {
+        Self { enabled: false, gain_db: -6.0, click_path: None, downbeat_click_path: None }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "gui", derive(Lens))]
 pub struct TimelineTrackSaveState {
+    /// The ID of this timeline track. This is unique within the project and never
+    /// changes.
+    pub id: TimelineTrackId,
+
     /// The name displayed on this timeline track.
     pub name: String,
 
     /// The audio clips on this timeline track. These may not be
     /// in any particular order.
     pub audio_clips: Vec<AudioClipSaveState>,
+
+    /// The groove applied to the events on this track. If this is `None`, then
+    /// the project's global groove is used instead.
+    pub groove: Option<Groove>,
 }
 
-#[derive(Debug, Clone, Lens)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "gui", derive(Lens))]
 pub struct AudioClipSaveState {
+    /// The ID of this audio clip. This is unique within the project and never changes.
+    pub id: AudioClipId,
+
     /// The name displayed on the audio clip.
     pub name: String,
 
@@ -1,9 +1,132 @@
 use rusty_daw_core::{MusicalTime, SampleRate, SampleTime, Seconds};
 
-// TODO: Make tempo map work like series of automation lines/curves between points in time.
+use super::Groove;
+
+/// The minimum tempo allowed in a [`TempoMap`], in beats per minute.
+///
+/// [`TempoMap`]: struct.TempoMap.html
+pub const MIN_BPM: f64 = 0.1;
+/// The maximum tempo allowed in a [`TempoMap`], in beats per minute.
+///
+/// [`TempoMap`]: struct.TempoMap.html
+pub const MAX_BPM: f64 = 100_000.0;
+
+/// How the tempo moves from one [`TempoPoint`] to the next.
+///
+/// [`TempoPoint`]: struct.TempoPoint.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TempoRamp {
+    /// The tempo stays constant until the next point, where it jumps to the new tempo.
+    Step,
+    /// The tempo changes linearly (per beat) towards the tempo of the next point.
+    Linear,
+    /// The tempo changes exponentially (by a constant ratio per beat) towards the tempo
+    /// of the next point. This sounds more even than a linear ramp over large tempo changes.
+    Curved,
+}
+
+/// A single tempo change in a [`TempoMap`].
+///
+/// [`TempoMap`]: struct.TempoMap.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoPoint {
+    /// Where this tempo change happens on the timeline.
+    pub position: MusicalTime,
+
+    /// The tempo at this point in beats per minute.
+    pub bpm: f64,
+
+    /// How the tempo moves from this point to the next point. This is ignored on the
+    /// last point in the map.
+    pub ramp: TempoRamp,
+}
+
+/// A cached segment between two tempo points. This is used so conversions don't
+/// have to integrate over every previous segment.
+#[derive(Debug, Clone, Copy)]
+struct TempoSegment {
+    start_beats: f64,
+    start_seconds: f64,
+
+    /// The length of this segment in beats. This is `f64::INFINITY` for the last segment.
+    len_beats: f64,
+
+    start_bps: f64,
+    end_bps: f64,
+
+    ramp: TempoRamp,
+}
+
+impl TempoSegment {
+    fn is_constant(&self) -> bool {
+        self.ramp == TempoRamp::Step
+            || !self.len_beats.is_finite()
+            || (self.end_bps - self.start_bps).abs() < 1.0e-12
+    }
+
+    /// The number of seconds elapsed `beats` beats after the start of this segment.
+    fn beats_to_seconds(&self, beats: f64) -> f64 {
+        if beats <= 0.0 || self.is_constant() {
+            return beats / self.start_bps;
+        }
+
+        match self.ramp {
+            TempoRamp::Linear => {
+                // bps(b) = start_bps + k * b
+                let k = (self.end_bps - self.start_bps) / self.len_beats;
+                (k * beats / self.start_bps).ln_1p() / k
+            }
+            _ => {
+                // bps(b) = start_bps * r^(b / len_beats)
+                let ln_r = (self.end_bps / self.start_bps).ln();
+                -(self.len_beats / (self.start_bps * ln_r))
+                    * (-ln_r * beats / self.len_beats).exp_m1()
+            }
+        }
+    }
+
+    /// The number of beats elapsed `seconds` seconds after the start of this segment.
+    fn seconds_to_beats(&self, seconds: f64) -> f64 {
+        if seconds <= 0.0 || self.is_constant() {
+            return seconds * self.start_bps;
+        }
+
+        match self.ramp {
+            TempoRamp::Linear => {
+                let k = (self.end_bps - self.start_bps) / self.len_beats;
+                self.start_bps * (k * seconds).exp_m1() / k
+            }
+            _ => {
+                let ln_r = (self.end_bps / self.start_bps).ln();
+                -(self.len_beats / ln_r)
+                    * (-seconds * self.start_bps * ln_r / self.len_beats).ln_1p()
+            }
+        }
+    }
+
+    /// The tempo in beats per second `beats` beats after the start of this segment.
+    fn bps_at(&self, beats: f64) -> f64 {
+        if beats <= 0.0 || self.is_constant() {
+            return self.start_bps;
+        }
+
+        let beats = beats.min(self.len_beats);
+
+        match self.ramp {
+            TempoRamp::Linear => {
+                self.start_bps + (self.end_bps - self.start_bps) * beats / self.len_beats
+            }
+            _ => self.start_bps * (self.end_bps / self.start_bps).powf(beats / self.len_beats),
+        }
+    }
+}
 
 /// A map of all tempo changes in the current project.
 ///
+/// The tempo map is made up of an ordered list of [`TempoPoint`]s, much like an automation
+/// lane. The first point is always at the start of the timeline, and the tempo after the
+/// last point stays constant.
+///
 /// Here is the intended workflow for keeping time:
 /// 1. The GUI/non-realtime thread stores all events in [`MusicalTime`] (unit of beats).
 /// 2. The GUI/non-realtime thread creates a new [`TempoMap`] on project startup and whenever anything about
@@ -21,34 +144,272 @@ use rusty_daw_core::{MusicalTime, SampleRate, SampleTime, Seconds};
 /// should be seeked to the realtime thread. The realtime thread then uses it, the [`TempoMap`], and the [`SampleRate`] to find
 /// the nearest (floored) sample to set as the new playhead.
 ///
+/// The tempo map also holds the project's global [`Groove`]. Processors should convert the positions of their
+/// events (audio clip starts, notes, etc.) using the `event_to_*` methods so the groove is applied. The playhead,
+/// loop points, and everything else use the regular (straight) conversions.
+///
 /// [`TempoMap`]: struct.TempoMap.html
+/// [`TempoPoint`]: struct.TempoPoint.html
+/// [`Groove`]: enum.Groove.html
 /// [`SampleTime`]: ../struct.Sampletime.html
 /// [`SampleRate`]: ../struct.SampleRate.html
 #[derive(Debug, Clone)]
 pub struct TempoMap {
     pub sample_rate: SampleRate,
 
-    /// Temporary static tempo
-    beats_per_second: f64,
-    seconds_per_beat: f64,
+    points: Vec<TempoPoint>,
+    segments: Vec<TempoSegment>,
+
+    groove: Groove,
 }
 
 impl TempoMap {
-    /// Temporary static tempo
+    /// Create a new tempo map with a single constant tempo.
     pub fn new(bpm: f64, sample_rate: SampleRate) -> Self {
-        TempoMap { beats_per_second: bpm / 60.0, seconds_per_beat: 60.0 / bpm, sample_rate }
+        let mut new_self = TempoMap {
+            sample_rate,
+            points: vec![TempoPoint {
+                position: MusicalTime::new(0.0),
+                bpm: bpm.clamp(MIN_BPM, MAX_BPM),
+                ramp: TempoRamp::Step,
+            }],
+            segments: Vec::new(),
+            groove: Groove::None,
+        };
+        new_self.compute_segments();
+        new_self
     }
 
-    /// Temporary static tempo
+    /// Create a new tempo map from the given list of points.
+    ///
+    /// This will return an error if the points are not valid (see [`TempoMap::set_points`]).
+    ///
+    /// [`TempoMap::set_points`]: struct.TempoMap.html#method.set_points
+    pub fn from_points(points: Vec<TempoPoint>, sample_rate: SampleRate) -> Result<Self, ()> {
+        let mut new_self = TempoMap::new(120.0, sample_rate);
+        new_self.set_points(points)?;
+        Ok(new_self)
+    }
+
+    /// The tempo at the start of the timeline in beats per minute.
     #[inline]
     pub fn bpm(&self) -> f64 {
-        self.beats_per_second * 60.0
+        self.points[0].bpm
     }
 
-    /// Temporary static tempo
+    /// Set the tempo at the start of the timeline. All other tempo points are left
+    /// untouched.
     pub fn set_bpm(&mut self, bpm: f64) {
-        self.beats_per_second = bpm / 60.0;
-        self.seconds_per_beat = 60.0 / bpm;
+        self.points[0].bpm = bpm.clamp(MIN_BPM, MAX_BPM);
+        self.compute_segments();
+    }
+
+    /// The tempo at the given position on the timeline in beats per minute.
+    pub fn bpm_at(&self, musical_time: MusicalTime) -> f64 {
+        let segment = self.segment_at_beats(musical_time.0);
+        segment.bps_at(musical_time.0 - segment.start_beats) * 60.0
+    }
+
+    /// All tempo points in this map, sorted by position.
+    #[inline]
+    pub fn points(&self) -> &[TempoPoint] {
+        &self.points
+    }
+
+    /// Replace all the tempo points in this map.
+    ///
+    /// This will return an error if the list is empty, if the first point is not at
+    /// the start of the timeline, if the points are not sorted by position (with no
+    /// two points sharing the same position), or if any tempo is out of range.
+    pub fn set_points(&mut self, points: Vec<TempoPoint>) -> Result<(), ()> {
+        if points.is_empty() || points[0].position.0 != 0.0 {
+            return Err(());
+        }
+        for (i, point) in points.iter().enumerate() {
+            if !(MIN_BPM..=MAX_BPM).contains(&point.bpm) {
+                return Err(());
+            }
+            if !point.position.0.is_finite()
+                || (i > 0 && point.position.0 <= points[i - 1].position.0)
+            {
+                return Err(());
+            }
+        }
+
+        self.points = points;
+        self.compute_segments();
+
+        Ok(())
+    }
+
+    /// Insert a new tempo point. If a point already exists at the same position, then
+    /// it will be replaced.
+    ///
+    /// This will return an error if the position is before the start of the timeline
+    /// or if the tempo is out of range.
+    pub fn insert_point(&mut self, point: TempoPoint) -> Result<(), ()> {
+        if point.position.0 < 0.0
+            || !point.position.0.is_finite()
+            || !(MIN_BPM..=MAX_BPM).contains(&point.bpm)
+        {
+            return Err(());
+        }
+
+        let i = self.points.partition_point(|p| p.position.0 < point.position.0);
+        if i < self.points.len() && self.points[i].position.0 == point.position.0 {
+            self.points[i] = point;
+        } else {
+            self.points.insert(i, point);
+        }
+        self.compute_segments();
+
+        Ok(())
+    }
+
+    /// Remove the tempo point at the given index.
+    ///
+    /// This will return an error if the index is out of range. The first point cannot
+    /// be removed.
+    pub fn remove_point(&mut self, index: usize) -> Result<TempoPoint, ()> {
+        if index == 0 || index >= self.points.len() {
+            return Err(());
+        }
+
+        let point = self.points.remove(index);
+        self.compute_segments();
+
+        Ok(point)
+    }
+
+    /// The global groove of the project.
+    #[inline]
+    pub fn groove(&self) -> &Groove {
+        &self.groove
+    }
+
+    /// Set the global groove of the project.
+    pub fn set_groove(&mut self, groove: Groove) {
+        self.groove = groove;
+    }
+
+    /// Return where an event at the given [`MusicalTime`] should actually be played after
+    /// the groove is applied.
+    ///
+    /// * `track_groove` - The groove of the track this event belongs to. If this is `None`,
+    /// then the global groove is used instead.
+    ///
+    /// [`MusicalTime`]: ../struct.MusicalTime.html
+    #[inline]
+    pub fn grooved_musical(
+        &self,
+        musical_time: MusicalTime,
+        track_groove: Option<&Groove>,
+    ) -> MusicalTime {
+        track_groove.unwrap_or(&self.groove).warp(musical_time)
+    }
+
+    /// Convert the [`MusicalTime`] of an event (such as the start of an audio clip or a note)
+    /// into the corresponding time in [`Seconds`] with the groove applied.
+    ///
+    /// * `track_groove` - The groove of the track this event belongs to. If this is `None`,
+    /// then the global groove is used instead.
+    ///
+    /// Note that this must be re-calculated after recieving a new [`TempoMap`].
+    ///
+    /// [`MusicalTime`]: ../struct.MusicalTime.html
+    /// [`Seconds`]: ../struct.Seconds.html
+    /// [`TempoMap`]: struct.TempoMap.html
+    #[inline]
+    pub fn event_to_seconds(
+        &self,
+        musical_time: MusicalTime,
+        track_groove: Option<&Groove>,
+    ) -> Seconds {
+        self.musical_to_seconds(self.grooved_musical(musical_time, track_groove))
+    }
+
+    /// Convert the [`MusicalTime`] of an event (such as the start of an audio clip or a note)
+    /// into the corresponding discrete [`SampleTime`] with the groove applied. This will be
+    /// rounded to the nearest sample.
+    ///
+    /// * `track_groove` - The groove of the track this event belongs to. If this is `None`,
+    /// then the global groove is used instead.
+    ///
+    /// Note that this must be re-calculated after recieving a new [`TempoMap`].
+    ///
+    /// [`MusicalTime`]: ../struct.MusicalTime.html
+    /// [`SampleTime`]: ../struct.SampleTime.html
+    /// [`TempoMap`]: struct.TempoMap.html
+    #[inline]
+    pub fn event_to_nearest_sample_round(
+        &self,
+        musical_time: MusicalTime,
+        track_groove: Option<&Groove>,
+    ) -> SampleTime {
+        self.musical_to_nearest_sample_round(self.grooved_musical(musical_time, track_groove))
+    }
+
+    /// Convert the [`MusicalTime`] of an event (such as a note) into the corresponding discrete
+    /// [`SampleTime`] floored to the nearest sample with the groove applied, while also returning
+    /// the fractional sub-sample part.
+    ///
+    /// * `track_groove` - The groove of the track this event belongs to. If this is `None`,
+    /// then the global groove is used instead.
+    ///
+    /// Note that this must be re-calculated after recieving a new [`TempoMap`].
+    ///
+    /// [`MusicalTime`]: ../struct.MusicalTime.html
+    /// [`SampleTime`]: ../struct.SampleTime.html
+    /// [`TempoMap`]: struct.TempoMap.html
+    #[inline]
+    pub fn event_to_sub_sample(
+        &self,
+        musical_time: MusicalTime,
+        track_groove: Option<&Groove>,
+    ) -> (SampleTime, f64) {
+        self.musical_to_sub_sample(self.grooved_musical(musical_time, track_groove))
+    }
+
+    fn compute_segments(&mut self) {
+        self.segments.clear();
+
+        let mut start_seconds = 0.0;
+        for (i, point) in self.points.iter().enumerate() {
+            let (len_beats, end_bps) = if let Some(next) = self.points.get(i + 1) {
+                let end_bps =
+                    if point.ramp == TempoRamp::Step { point.bpm / 60.0 } else { next.bpm / 60.0 };
+                (next.position.0 - point.position.0, end_bps)
+            } else {
+                (f64::INFINITY, point.bpm / 60.0)
+            };
+
+            let segment = TempoSegment {
+                start_beats: point.position.0,
+                start_seconds,
+                len_beats,
+                start_bps: point.bpm / 60.0,
+                end_bps,
+                ramp: point.ramp,
+            };
+
+            if len_beats.is_finite() {
+                start_seconds += segment.beats_to_seconds(len_beats);
+            }
+
+            self.segments.push(segment);
+        }
+    }
+
+    #[inline]
+    fn segment_at_beats(&self, beats: f64) -> &TempoSegment {
+        let i = self.segments.partition_point(|s| s.start_beats <= beats);
+        &self.segments[i.saturating_sub(1)]
+    }
+
+    #[inline]
+    fn segment_at_seconds(&self, seconds: f64) -> &TempoSegment {
+        let i = self.segments.partition_point(|s| s.start_seconds <= seconds);
+        &self.segments[i.saturating_sub(1)]
     }
 
     /// Convert the given [`MusicalTime`] into the corresponding time in [`Seconds`].
@@ -60,7 +421,10 @@ impl TempoMap {
     /// [`TempoMap`]: struct.TempoMap.html
     #[inline]
     pub fn musical_to_seconds(&self, musical_time: MusicalTime) -> Seconds {
-        Seconds(musical_time.0 * self.seconds_per_beat)
+        let segment = self.segment_at_beats(musical_time.0);
+        Seconds(
+            segment.start_seconds + segment.beats_to_seconds(musical_time.0 - segment.start_beats),
+        )
     }
 
     /// Convert the given [`Seconds`] into the corresponding [`MusicalTime`].
@@ -71,7 +435,10 @@ impl TempoMap {
     /// [`MusicalTime`]: ../struct.MusicalTime.html
     #[inline]
     pub fn seconds_to_musical(&self, seconds: Seconds) -> MusicalTime {
-        MusicalTime(seconds.0 * self.beats_per_second)
+        let segment = self.segment_at_seconds(seconds.0);
+        MusicalTime(
+            segment.start_beats + segment.seconds_to_beats(seconds.0 - segment.start_seconds),
+        )
     }
 
     /// Convert the given [`SampleTime`] into the corresponding [`MusicalTime`].
@@ -83,7 +450,7 @@ impl TempoMap {
     /// [`TempoMap`]: struct.TempoMap.html
     #[inline]
     pub fn sample_to_musical(&self, sample_time: SampleTime) -> MusicalTime {
-        MusicalTime(sample_time.to_seconds(self.sample_rate).0 * self.beats_per_second)
+        self.seconds_to_musical(sample_time.to_seconds(self.sample_rate))
     }
 
     /// Convert the given [`MusicalTime`] into the corresponding discrete [`SampleTime`].
@@ -196,3 +563,92 @@ impl Default for TempoMap {
         TempoMap::new(110.0, SampleRate::default())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1.0e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn tempo_map_constant() {
+        let tempo_map = TempoMap::new(120.0, SampleRate::new(48_000.0));
+
+        assert_close(tempo_map.musical_to_seconds(MusicalTime::new(4.0)).0, 2.0);
+        assert_close(tempo_map.seconds_to_musical(Seconds::new(3.0)).0, 6.0);
+        assert_eq!(
+            tempo_map.musical_to_nearest_sample_round(MusicalTime::new(1.0)),
+            SampleTime::new(24_000)
+        );
+        assert_close(tempo_map.sample_to_musical(SampleTime::new(48_000)).0, 2.0);
+    }
+
+    #[test]
+    fn tempo_map_step() {
+        let mut tempo_map = TempoMap::new(120.0, SampleRate::new(48_000.0));
+        tempo_map
+            .insert_point(TempoPoint {
+                position: MusicalTime::new(4.0),
+                bpm: 60.0,
+                ramp: TempoRamp::Step,
+            })
+            .unwrap();
+
+        // 4 beats at 120 bpm, then 2 beats at 60 bpm.
+        assert_close(tempo_map.musical_to_seconds(MusicalTime::new(6.0)).0, 4.0);
+        assert_close(tempo_map.seconds_to_musical(Seconds::new(4.0)).0, 6.0);
+        assert_close(tempo_map.bpm_at(MusicalTime::new(3.0)), 120.0);
+        assert_close(tempo_map.bpm_at(MusicalTime::new(5.0)), 60.0);
+    }
+
+    #[test]
+    fn tempo_map_ramps() {
+        for ramp in [TempoRamp::Linear, TempoRamp::Curved].iter() {
+            let tempo_map = TempoMap::from_points(
+                vec![
+                    TempoPoint { position: MusicalTime::new(0.0), bpm: 120.0, ramp: *ramp },
+                    TempoPoint {
+                        position: MusicalTime::new(8.0),
+                        bpm: 180.0,
+                        ramp: TempoRamp::Step,
+                    },
+                ],
+                SampleRate::new(48_000.0),
+            )
+            .unwrap();
+
+            // The ramp must be faster than the start tempo and slower than the end tempo.
+            let ramp_secs = tempo_map.musical_to_seconds(MusicalTime::new(8.0)).0;
+            assert!(ramp_secs < 4.0 && ramp_secs > 8.0 / 3.0);
+
+            assert_close(tempo_map.bpm_at(MusicalTime::new(0.0)), 120.0);
+            assert_close(tempo_map.bpm_at(MusicalTime::new(8.0)), 180.0);
+
+            // The tempo after the last point stays constant.
+            assert_close(tempo_map.musical_to_seconds(MusicalTime::new(11.0)).0, ramp_secs + 1.0);
+
+            for &beats in [0.5, 3.0, 7.9, 8.0, 12.25].iter() {
+                let secs = tempo_map.musical_to_seconds(MusicalTime::new(beats));
+                assert_close(tempo_map.seconds_to_musical(secs).0, beats);
+            }
+        }
+    }
+
+    #[test]
+    fn tempo_map_invalid_points() {
+        let mut tempo_map = TempoMap::default();
+
+        assert!(tempo_map.set_points(Vec::new()).is_err());
+        assert!(tempo_map
+            .set_points(vec![TempoPoint {
+                position: MusicalTime::new(1.0),
+                bpm: 120.0,
+                ramp: TempoRamp::Step,
+            }])
+            .is_err());
+        assert!(tempo_map.remove_point(0).is_err());
+        assert_eq!(tempo_map.points().len(), 1);
+    }
+}
@@ -0,0 +1,466 @@
+use rusty_daw_core::MusicalTime;
+use std::fmt;
+
+/// The number of ticks in a single beat of a time signature.
+pub static TICKS_PER_BEAT: u32 = 960;
+
+/// The largest allowed numerator and denominator in a [`TimeSignature`].
+///
+/// [`TimeSignature`]: struct.TimeSignature.html
+pub static MAX_TIME_SIGNATURE_VALUE: u32 = 64;
+
+/// A musical meter, such as 4/4 or 6/8.
+///
+/// Note that one unit of [`MusicalTime`] is always one quarter note, so a beat in 6/8
+/// is half a unit of [`MusicalTime`] long.
+///
+/// [`MusicalTime`]: ../struct.MusicalTime.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    /// The number of beats in a bar.
+    pub numerator: u32,
+    /// The note value of a single beat (4 is a quarter note, 8 is an eighth note, etc).
+    pub denominator: u32,
+}
+
+impl TimeSignature {
+    /// Create a new time signature.
+    ///
+    /// This will return an error if the numerator is not within the range
+    /// `[1, MAX_TIME_SIGNATURE_VALUE]`, or if the denominator is not a power of two
+    /// within that range.
+    pub fn new(numerator: u32, denominator: u32) -> Result<Self, ()> {
+        if numerator == 0
+            || numerator > MAX_TIME_SIGNATURE_VALUE
+            || !denominator.is_power_of_two()
+            || denominator > MAX_TIME_SIGNATURE_VALUE
+        {
+            return Err(());
+        }
+
+        Ok(Self { numerator, denominator })
+    }
+
+    /// Returns `true` if this time signature can be created with `TimeSignature::new()`.
+    pub fn is_valid(&self) -> bool {
+        Self::new(self.numerator, self.denominator).is_ok()
+    }
+
+    /// The length of a single beat in units of [`MusicalTime`] (quarter notes).
+    ///
+    /// [`MusicalTime`]: ../struct.MusicalTime.html
+    #[inline]
+    pub fn beat_len(&self) -> f64 {
+        4.0 / f64::from(self.denominator)
+    }
+
+    /// The length of a single bar in units of [`MusicalTime`] (quarter notes).
+    ///
+    /// [`MusicalTime`]: ../struct.MusicalTime.html
+    #[inline]
+    pub fn bar_len(&self) -> f64 {
+        f64::from(self.numerator) * self.beat_len()
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self { numerator: 4, denominator: 4 }
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+impl std::str::FromStr for TimeSignature {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(2, '/');
+
+        let numerator = parts.next().ok_or(())?.trim().parse::<u32>().map_err(|_| ())?;
+        let denominator = parts.next().ok_or(())?.trim().parse::<u32>().map_err(|_| ())?;
+
+        Self::new(numerator, denominator)
+    }
+}
+
+/// A position on the timeline in bars, beats, and ticks.
+///
+/// Bars and beats are counted from 1 (the first beat of the song is `1.1.0`), and ticks
+/// are counted from 0 up to `TICKS_PER_BEAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BarsBeatsTicks {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+impl BarsBeatsTicks {
+    pub fn new(bar: u32, beat: u32, tick: u32) -> Self {
+        Self { bar, beat, tick }
+    }
+}
+
+impl Default for BarsBeatsTicks {
+    fn default() -> Self {
+        Self { bar: 1, beat: 1, tick: 0 }
+    }
+}
+
+impl fmt::Display for BarsBeatsTicks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.bar, self.beat, self.tick)
+    }
+}
+
+/// A single meter change in a [`TimeSignatureMap`].
+///
+/// [`TimeSignatureMap`]: struct.TimeSignatureMap.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSignaturePoint {
+    /// Where this meter change happens on the timeline. If this does not land on a bar
+    /// line of the previous meter, then the bar before this change will be shortened.
+    pub position: MusicalTime,
+
+    pub time_signature: TimeSignature,
+}
+
+/// A cached segment between two meter changes.
+#[derive(Debug, Clone, Copy)]
+struct TimeSignatureSegment {
+    start_beats: f64,
+    /// The (zero-based) index of the first bar in this segment.
+    start_bar: u32,
+
+    time_signature: TimeSignature,
+}
+
+/// A map of all time signature (meter) changes in the current project.
+///
+/// Like the [`TempoMap`], the first point is always at the start of the timeline.
+///
+/// [`TempoMap`]: struct.TempoMap.html
+#[derive(Debug, Clone)]
+pub struct TimeSignatureMap {
+    points: Vec<TimeSignaturePoint>,
+    segments: Vec<TimeSignatureSegment>,
+}
+
+impl TimeSignatureMap {
+    /// Create a new map with a single time signature.
+    pub fn new(time_signature: TimeSignature) -> Self {
+        let mut new_self = Self {
+            points: vec![TimeSignaturePoint { position: MusicalTime::new(0.0), time_signature }],
+            segments: Vec::new(),
+        };
+        new_self.compute_segments();
+        new_self
+    }
+
+    /// The time signature at the start of the timeline.
+    #[inline]
+    pub fn time_signature(&self) -> TimeSignature {
+        self.points[0].time_signature
+    }
+
+    /// Set the time signature at the start of the timeline. All other meter changes are
+    /// left untouched.
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) -> Result<(), ()> {
+        if !time_signature.is_valid() {
+            return Err(());
+        }
+
+        self.points[0].time_signature = time_signature;
+        self.compute_segments();
+
+        Ok(())
+    }
+
+    /// The time signature at the given position on the timeline.
+    pub fn time_signature_at(&self, musical_time: MusicalTime) -> TimeSignature {
+        self.segment_at_beats(musical_time.0).time_signature
+    }
+
+    /// All meter changes in this map, sorted by position.
+    #[inline]
+    pub fn points(&self) -> &[TimeSignaturePoint] {
+        &self.points
+    }
+
+    /// Replace all the meter changes in this map.
+    ///
+    /// This will return an error if the list is empty, if the first point is not at
+    /// the start of the timeline, if the points are not sorted by position (with no
+    /// two points sharing the same position), or if any time signature is invalid.
+    pub fn set_points(&mut self, points: Vec<TimeSignaturePoint>) -> Result<(), ()> {
+        if points.is_empty() || points[0].position.0 != 0.0 {
+            return Err(());
+        }
+        for (i, point) in points.iter().enumerate() {
+            if !point.time_signature.is_valid() {
+                return Err(());
+            }
+            if !point.position.0.is_finite()
+                || (i > 0 && point.position.0 <= points[i - 1].position.0)
+            {
+                return Err(());
+            }
+        }
+
+        self.points = points;
+        self.compute_segments();
+
+        Ok(())
+    }
+
+    /// Insert a new meter change. If a change already exists at the same position, then
+    /// it will be replaced.
+    ///
+    /// This will return an error if the position is before the start of the timeline
+    /// or if the time signature is invalid.
+    pub fn insert_point(&mut self, point: TimeSignaturePoint) -> Result<(), ()> {
+        if point.position.0 < 0.0
+            || !point.position.0.is_finite()
+            || !point.time_signature.is_valid()
+        {
+            return Err(());
+        }
+
+        let i = self.points.partition_point(|p| p.position.0 < point.position.0);
+        if i < self.points.len() && self.points[i].position.0 == point.position.0 {
+            self.points[i] = point;
+        } else {
+            self.points.insert(i, point);
+        }
+        self.compute_segments();
+
+        Ok(())
+    }
+
+    /// Remove the meter change at the given index.
+    ///
+    /// This will return an error if the index is out of range. The first point cannot
+    /// be removed.
+    pub fn remove_point(&mut self, index: usize) -> Result<TimeSignaturePoint, ()> {
+        if index == 0 || index >= self.points.len() {
+            return Err(());
+        }
+
+        let point = self.points.remove(index);
+        self.compute_segments();
+
+        Ok(point)
+    }
+
+    /// Convert the given [`MusicalTime`] into the corresponding position in bars, beats,
+    /// and ticks. The tick is floored to the nearest tick.
+    ///
+    /// Positions before the start of the timeline are clamped to `1.1.0`.
+    ///
+    /// [`MusicalTime`]: ../struct.MusicalTime.html
+    pub fn musical_to_bbt(&self, musical_time: MusicalTime) -> BarsBeatsTicks {
+        if musical_time.0 <= 0.0 || musical_time.0.is_nan() {
+            return BarsBeatsTicks::default();
+        }
+
+        let segment = self.segment_at_beats(musical_time.0);
+        let beat_len = segment.time_signature.beat_len();
+        let bar_len = segment.time_signature.bar_len();
+
+        // Add a tiny offset to guard against floating point error right before a bar or beat line.
+        let offset = musical_time.0 - segment.start_beats + 1.0e-9;
+
+        let bars = (offset / bar_len).floor();
+        let beats_in_bar = offset - (bars * bar_len);
+        let beats = (beats_in_bar / beat_len).floor();
+        let ticks = ((beats_in_bar - (beats * beat_len)) / beat_len * f64::from(TICKS_PER_BEAT))
+            .floor() as u32;
+
+        BarsBeatsTicks {
+            bar: segment.start_bar + bars as u32 + 1,
+            beat: (beats as u32).min(segment.time_signature.numerator - 1) + 1,
+            tick: ticks.min(TICKS_PER_BEAT - 1),
+        }
+    }
+
+    /// Convert the given position in bars, beats, and ticks into the corresponding
+    /// [`MusicalTime`].
+    ///
+    /// Beats and ticks past the end of the bar carry over into the next bar.
+    ///
+    /// [`MusicalTime`]: ../struct.MusicalTime.html
+    pub fn bbt_to_musical(&self, bbt: BarsBeatsTicks) -> MusicalTime {
+        let bar = bbt.bar.max(1) - 1;
+        let beat = bbt.beat.max(1) - 1;
+
+        let segment = self.segment_at_bar(bar);
+        let beat_len = segment.time_signature.beat_len();
+
+        MusicalTime(
+            segment.start_beats
+                + (f64::from(bar - segment.start_bar) * segment.time_signature.bar_len())
+                + (f64::from(beat) * beat_len)
+                + (f64::from(bbt.tick) / f64::from(TICKS_PER_BEAT) * beat_len),
+        )
+    }
+
+    /// Returns the position of the start of the given (one-based) bar.
+    pub fn bar_start(&self, bar: u32) -> MusicalTime {
+        self.bbt_to_musical(BarsBeatsTicks::new(bar, 1, 0))
+    }
+
+    /// Returns the position of the first bar line at or after the given position.
+    pub fn next_bar_line(&self, musical_time: MusicalTime) -> MusicalTime {
+        let bbt = self.musical_to_bbt(musical_time);
+        let bar_start = self.bar_start(bbt.bar);
+        if bar_start.0 >= musical_time.0 - 1.0e-9 {
+            bar_start
+        } else {
+            let next_bar_start = self.bar_start(bbt.bar + 1);
+            // The bar could have been shortened by a meter change.
+            match self.points.iter().find(|p| p.position.0 > musical_time.0) {
+                Some(p) if p.position.0 < next_bar_start.0 => p.position,
+                _ => next_bar_start,
+            }
+        }
+    }
+
+    /// Returns the position of the first beat line at or after the given position, and
+    /// whether or not that beat is the first beat of a bar.
+    pub fn next_beat_line(&self, musical_time: MusicalTime) -> (MusicalTime, bool) {
+        let i =
+            self.segments.partition_point(|s| s.start_beats <= musical_time.0).saturating_sub(1);
+        let segment = &self.segments[i];
+
+        let beat_len = segment.time_signature.beat_len();
+        let bar_len = segment.time_signature.bar_len();
+
+        let bar_start = segment.start_beats
+            + (((musical_time.0 - segment.start_beats) / bar_len).floor() * bar_len);
+        let beat = ((musical_time.0 - bar_start) / beat_len - 1.0e-9).ceil().max(0.0);
+
+        let (pos, is_downbeat) = if beat >= f64::from(segment.time_signature.numerator) {
+            (bar_start + bar_len, true)
+        } else {
+            (bar_start + (beat * beat_len), beat == 0.0)
+        };
+
+        // The bar could have been shortened by a meter change.
+        if let Some(next_segment) = self.segments.get(i + 1) {
+            if pos >= next_segment.start_beats - 1.0e-9 {
+                return (MusicalTime(next_segment.start_beats), true);
+            }
+        }
+
+        (MusicalTime(pos), is_downbeat)
+    }
+
+    fn compute_segments(&mut self) {
+        self.segments.clear();
+
+        let mut start_bar = 0;
+        for (i, point) in self.points.iter().enumerate() {
+            self.segments.push(TimeSignatureSegment {
+                start_beats: point.position.0,
+                start_bar,
+                time_signature: point.time_signature,
+            });
+
+            if let Some(next) = self.points.get(i + 1) {
+                // A partial bar before a meter change still counts as a whole bar.
+                let len_bars = ((next.position.0 - point.position.0)
+                    / point.time_signature.bar_len()
+                    - 1.0e-9)
+                    .ceil()
+                    .max(1.0);
+                start_bar += len_bars as u32;
+            }
+        }
+    }
+
+    #[inline]
+    fn segment_at_beats(&self, beats: f64) -> &TimeSignatureSegment {
+        let i = self.segments.partition_point(|s| s.start_beats <= beats);
+        &self.segments[i.saturating_sub(1)]
+    }
+
+    #[inline]
+    fn segment_at_bar(&self, bar: u32) -> &TimeSignatureSegment {
+        let i = self.segments.partition_point(|s| s.start_bar <= bar);
+        &self.segments[i.saturating_sub(1)]
+    }
+}
+
+impl Default for TimeSignatureMap {
+    fn default() -> Self {
+        TimeSignatureMap::new(TimeSignature::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_signature_map_bbt() {
+        let mut map = TimeSignatureMap::default();
+
+        assert_eq!(map.musical_to_bbt(MusicalTime::new(0.0)), BarsBeatsTicks::new(1, 1, 0));
+        assert_eq!(map.musical_to_bbt(MusicalTime::new(66.5)), BarsBeatsTicks::new(17, 3, 480));
+        assert_eq!(map.bbt_to_musical(BarsBeatsTicks::new(17, 3, 0)), MusicalTime::new(66.0));
+
+        // Switch to 6/8 at bar 3.
+        map.insert_point(TimeSignaturePoint {
+            position: MusicalTime::new(8.0),
+            time_signature: TimeSignature::new(6, 8).unwrap(),
+        })
+        .unwrap();
+
+        assert_eq!(map.musical_to_bbt(MusicalTime::new(7.0)), BarsBeatsTicks::new(2, 4, 0));
+        assert_eq!(map.musical_to_bbt(MusicalTime::new(8.0)), BarsBeatsTicks::new(3, 1, 0));
+        assert_eq!(map.musical_to_bbt(MusicalTime::new(11.5)), BarsBeatsTicks::new(4, 2, 0));
+        assert_eq!(map.bar_start(4), MusicalTime::new(11.0));
+        assert_eq!(map.bbt_to_musical(BarsBeatsTicks::new(4, 2, 0)), MusicalTime::new(11.5));
+
+        for &beats in [0.25, 3.0, 7.75, 8.0, 9.5, 30.125].iter() {
+            let bbt = map.musical_to_bbt(MusicalTime::new(beats));
+            assert!((map.bbt_to_musical(bbt).0 - beats).abs() < 1.0e-3);
+        }
+    }
+
+    #[test]
+    fn time_signature_map_partial_bar() {
+        let mut map = TimeSignatureMap::default();
+
+        // Change to 3/4 halfway through bar 2.
+        map.insert_point(TimeSignaturePoint {
+            position: MusicalTime::new(6.0),
+            time_signature: TimeSignature::new(3, 4).unwrap(),
+        })
+        .unwrap();
+
+        assert_eq!(map.musical_to_bbt(MusicalTime::new(5.0)), BarsBeatsTicks::new(2, 2, 0));
+        assert_eq!(map.musical_to_bbt(MusicalTime::new(6.0)), BarsBeatsTicks::new(3, 1, 0));
+        assert_eq!(map.next_bar_line(MusicalTime::new(4.5)), MusicalTime::new(6.0));
+        assert_eq!(map.next_bar_line(MusicalTime::new(6.5)), MusicalTime::new(9.0));
+
+        assert_eq!(map.next_beat_line(MusicalTime::new(-0.5)), (MusicalTime::new(0.0), true));
+        assert_eq!(map.next_beat_line(MusicalTime::new(0.0)), (MusicalTime::new(0.0), true));
+        assert_eq!(map.next_beat_line(MusicalTime::new(0.5)), (MusicalTime::new(1.0), false));
+        assert_eq!(map.next_beat_line(MusicalTime::new(3.5)), (MusicalTime::new(4.0), true));
+        assert_eq!(map.next_beat_line(MusicalTime::new(5.5)), (MusicalTime::new(6.0), true));
+        assert_eq!(map.next_beat_line(MusicalTime::new(8.5)), (MusicalTime::new(9.0), true));
+    }
+
+    #[test]
+    fn time_signature_parse() {
+        assert_eq!("7/8".parse::<TimeSignature>(), Ok(TimeSignature::new(7, 8).unwrap()));
+        assert!("4/3".parse::<TimeSignature>().is_err());
+        assert!("0/4".parse::<TimeSignature>().is_err());
+        assert!("4".parse::<TimeSignature>().is_err());
+    }
+}
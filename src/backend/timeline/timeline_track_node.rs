@@ -8,7 +8,8 @@ use crate::backend::resource_loader::{PcmLoadError, ResourceLoadError};
 use crate::backend::{GlobalNodeData, ResourceCache, MAX_BLOCKSIZE};
 
 use super::{
-    AudioClipHandle, AudioClipProcess, AudioClipSaveState, TempoMap, TimelineTrackSaveState,
+    AudioClipHandle, AudioClipId, AudioClipProcess, AudioClipSaveState, Groove, TempoMap,
+    TimelineTrackSaveState,
 };
 
 pub struct TimelineTrackHandle {
@@ -26,30 +27,29 @@ impl TimelineTrackHandle {
         save_state.name = name;
     }
 
-    /// Return an immutable handle to the audio clip with the given index.
+    /// Return the index of the audio clip with the given ID.
+    pub fn audio_clip_index(&self, id: AudioClipId) -> Option<usize> {
+        self.audio_clip_handles.iter().position(|c| c.id() == id)
+    }
+
+    /// Return an immutable handle to the audio clip with the given ID.
     pub fn audio_clip<'a>(
         &'a self,
-        index: usize,
+        id: AudioClipId,
         save_state: &'a TimelineTrackSaveState,
     ) -> Option<(&'a AudioClipHandle, &'a AudioClipSaveState)> {
-        if let Some(audio_clip) = self.audio_clip_handles.get(index) {
-            Some((audio_clip, &save_state.audio_clips[index]))
-        } else {
-            None
-        }
+        let index = self.audio_clip_index(id)?;
+        Some((&self.audio_clip_handles[index], &save_state.audio_clips[index]))
     }
 
-    /// Return a mutable handle to the audio clip with the given index.
+    /// Return a mutable handle to the audio clip with the given ID.
     pub fn audio_clip_mut<'a>(
         &'a mut self,
-        index: usize,
+        id: AudioClipId,
         save_state: &'a mut TimelineTrackSaveState,
     ) -> Option<(&'a mut AudioClipHandle, &'a mut AudioClipSaveState)> {
-        if let Some(audio_clip) = self.audio_clip_handles.get_mut(index) {
-            Some((audio_clip, &mut save_state.audio_clips[index]))
-        } else {
-            None
-        }
+        let index = self.audio_clip_index(id)?;
+        Some((&mut self.audio_clip_handles[index], &mut save_state.audio_clips[index]))
     }
 
     /// Add a new audio clip to this track.
@@ -60,8 +60,31 @@ impl TimelineTrackHandle {
         tempo_map: &TempoMap,
         save_state: &mut TimelineTrackSaveState,
     ) -> Result<(), PcmLoadError> {
-        let (audio_clip_proc, params_handle, pcm_load_res) =
-            AudioClipProcess::new(&clip, resource_cache, tempo_map, &self.coll_handle);
+        let index = self.audio_clip_handles.len();
+        self.insert_audio_clip(index, clip, resource_cache, tempo_map, save_state)
+    }
+
+    /// Insert a new audio clip into this track at the given index.
+    ///
+    /// If the index is out of bounds, the clip is added to the end. The ID of the clip
+    /// must not be used by any other clip on this track.
+    pub fn insert_audio_clip(
+        &mut self,
+        index: usize,
+        clip: AudioClipSaveState,
+        resource_cache: &ResourceCache,
+        tempo_map: &TempoMap,
+        save_state: &mut TimelineTrackSaveState,
+    ) -> Result<(), PcmLoadError> {
+        let index = index.min(self.audio_clip_handles.len());
+
+        let (audio_clip_proc, params_handle, pcm_load_res) = AudioClipProcess::new(
+            &clip,
+            resource_cache,
+            tempo_map,
+            save_state.groove.as_ref(),
+            &self.coll_handle,
+        );
 
         // Compile the new process.
 
@@ -71,27 +94,25 @@ impl TimelineTrackHandle {
         let mut new_audio_clip_procs = Vec::clone(&new_process.audio_clips);
 
         // Add the new clip.
-        new_audio_clip_procs.push(audio_clip_proc);
+        new_audio_clip_procs.insert(index, audio_clip_proc);
 
         // Use the new process info.
         new_process.audio_clips = Shared::new(&self.coll_handle, new_audio_clip_procs);
         self.process.set(Shared::new(&self.coll_handle, new_process));
 
-        self.audio_clip_handles.push(params_handle);
-        save_state.audio_clips.push(clip);
+        self.audio_clip_handles.insert(index, params_handle);
+        save_state.audio_clips.insert(index, clip);
 
         pcm_load_res
     }
 
-    /// Remove an audio clip from this track.
+    /// Remove the audio clip with the given ID from this track.
     pub fn remove_audio_clip(
         &mut self,
-        index: usize,
+        id: AudioClipId,
         save_state: &mut TimelineTrackSaveState,
     ) -> Result<(), ()> {
-        if index >= self.audio_clip_handles.len() {
-            return Err(());
-        }
+        let index = self.audio_clip_index(id).ok_or(())?;
 
         self.audio_clip_handles.remove(index);
         save_state.audio_clips.remove(index);
@@ -104,7 +125,7 @@ impl TimelineTrackHandle {
         let mut new_audio_clip_procs = Vec::clone(&new_process.audio_clips);
 
         // Remove the old clip.
-        new_audio_clip_procs.remove(index);
+        new_audio_clip_procs.retain(|p| p.id() != id);
 
         // Use the new processes.
         new_process.audio_clips = Shared::new(&self.coll_handle, new_audio_clip_procs);
@@ -113,11 +134,25 @@ impl TimelineTrackHandle {
         Ok(())
     }
 
-    pub(super) fn update_tempo_map(
+    /// Set the groove of this track. If this is `None`, then this track will follow the
+    /// project's global groove.
+    pub fn set_groove(
         &mut self,
+        groove: Option<Groove>,
         tempo_map: &TempoMap,
-        save_state: &TimelineTrackSaveState,
+        save_state: &mut TimelineTrackSaveState,
     ) {
+        save_state.groove = groove;
+
+        for (clip, save) in self.audio_clip_handles.iter_mut().zip(save_state.audio_clips.iter()) {
+            clip.set_track_groove(save_state.groove.clone(), tempo_map, save);
+        }
+    }
+
+    /// Update the positions of all audio clips on this track to match the new tempo map.
+    ///
+    /// This must be called whenever the project's tempo map changes.
+    pub fn update_tempo_map(&mut self, tempo_map: &TempoMap, save_state: &TimelineTrackSaveState) {
         for (clip, save) in self.audio_clip_handles.iter_mut().zip(save_state.audio_clips.iter()) {
             clip.update_tempo_map(tempo_map, save);
         }
@@ -142,8 +177,13 @@ impl TimelineTrackNode {
         let mut audio_clip_handles = Vec::<AudioClipHandle>::new();
 
         for audio_clip_save in save_state.audio_clips.iter() {
-            let (process, handle, res) =
-                AudioClipProcess::new(audio_clip_save, resource_cache, tempo_map, coll_handle);
+            let (process, handle, res) = AudioClipProcess::new(
+                audio_clip_save,
+                resource_cache,
+                tempo_map,
+                save_state.groove.as_ref(),
+                coll_handle,
+            );
 
             if let Err(e) = res {
                 audio_clip_errors.push(ResourceLoadError::PCM(e));
@@ -179,7 +219,8 @@ impl TimelineTrackNode {
     fn audio_clips_loop_crossfade_out(
         frames: usize,
         loop_crossfade_out: &SmoothOutputF32<MAX_BLOCKSIZE>,
-        loop_out_playhead: SampleTime,
+        loop_out_playhead: (SampleTime, f64),
+        playback_rate: f64,
         process: &Shared<TimelineTrackProcess>,
         out: &mut StereoBlockBuffer<f32, MAX_BLOCKSIZE>,
         temp_out: &mut StereoBlockBuffer<f32, MAX_BLOCKSIZE>,
@@ -193,14 +234,22 @@ impl TimelineTrackNode {
         // of overwriting them.
         temp_out.clear_frames(frames);
 
-        let end_frame = loop_out_playhead + SampleTime::from_usize(frames);
+        let (loop_out_playhead, loop_out_fract) = loop_out_playhead;
+        let end_frame = loop_out_playhead + timeline_len(loop_out_fract, playback_rate, frames);
 
         for audio_clip in process.audio_clips.iter() {
             let info = audio_clip.info.get();
             // Only use audio clips that lie within range of the current process cycle.
             if loop_out_playhead < info.timeline_end && info.timeline_start < end_frame {
                 // Fill samples from the audio clip into the output buffer.
-                audio_clip.process(loop_out_playhead, frames, temp_out, 0);
+                audio_clip.process(
+                    loop_out_playhead,
+                    loop_out_fract,
+                    playback_rate,
+                    frames,
+                    temp_out,
+                    0,
+                );
             }
         }
 
@@ -233,7 +282,8 @@ impl TimelineTrackNode {
     fn audio_clips_seek_crossfade_out(
         frames: usize,
         seek_crossfade_out: &SmoothOutputF32<MAX_BLOCKSIZE>,
-        seek_out_playhead: SampleTime,
+        seek_out_playhead: (SampleTime, f64),
+        playback_rate: f64,
         process: &Shared<TimelineTrackProcess>,
         out: &mut StereoBlockBuffer<f32, MAX_BLOCKSIZE>,
         temp_out: &mut StereoBlockBuffer<f32, MAX_BLOCKSIZE>,
@@ -245,14 +295,22 @@ impl TimelineTrackNode {
         // of overwriting them.
         temp_out.clear_frames(frames);
 
-        let end_frame = seek_out_playhead + SampleTime::from_usize(frames);
+        let (seek_out_playhead, seek_out_fract) = seek_out_playhead;
+        let end_frame = seek_out_playhead + timeline_len(seek_out_fract, playback_rate, frames);
 
         for audio_clip in process.audio_clips.iter() {
             let info = audio_clip.info.get();
             // Only use audio clips that lie within range.
             if seek_out_playhead < info.timeline_end && info.timeline_start < end_frame {
                 // Fill samples from the audio clip into the output buffer.
-                audio_clip.process(seek_out_playhead, frames, temp_out, 0);
+                audio_clip.process(
+                    seek_out_playhead,
+                    seek_out_fract,
+                    playback_rate,
+                    frames,
+                    temp_out,
+                    0,
+                );
             }
         }
 
@@ -298,11 +356,12 @@ impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for TimelineTrackNode {
         }
 
         // Keep playing if there is an active pause/stop fade out.
-        let playhead = global_data
-            .transport
-            .audio_clip_declick()
-            .stop_fade_playhead()
-            .unwrap_or(global_data.transport.playhead());
+        let (playhead, playhead_fract) =
+            match global_data.transport.audio_clip_declick().stop_fade_playhead() {
+                Some(stop_fade_playhead) => stop_fade_playhead,
+                None => (global_data.transport.playhead(), global_data.transport.playhead_fract()),
+            };
+        let playback_rate = global_data.transport.playback_rate();
 
         let process = self.process.get();
 
@@ -313,28 +372,28 @@ impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for TimelineTrackNode {
         let (loop_crossfade_out, loop_out_playhead) =
             global_data.transport.audio_clip_declick().loop_crossfade_out();
 
-        if let Some(loop_back) = global_data.transport.do_loop_back() {
-            // Transport is currently looping in this process cycle. We will need to process
-            // loop crossfades individually.
+        if let Some(jump) = global_data.transport.playhead_jump() {
+            // The playhead is jumping (looping back or reaching a scheduled seek) in this
+            // process cycle. We will need to process loop crossfades individually.
 
-            let first_frames = (loop_back.loop_end - playhead).0 as usize;
+            let first_frames = jump.first_frames.min(frames);
             let second_frames = frames - first_frames;
 
             // First, process the crossfade in.
             for audio_clip in process.audio_clips.iter() {
                 let info = audio_clip.info.get();
                 // Only use audio clips that lie within range of the current process
-                // cycle after the point where the loop jumps back.
-                if loop_back.loop_start < info.timeline_end
-                    && info.timeline_start < loop_back.playhead_end
-                {
+                // cycle after the point where the playhead jumps.
+                if jump.resume_at < info.timeline_end && info.timeline_start < jump.playhead_end {
                     // Fill samples from the audio clip into the output buffer.
                     //
                     // Here we only want to start filling in the samples after the
-                    // point where the loop jumps back.
+                    // point where the playhead jumps.
                     // (hence `out_offset` is`first_frames`)
                     audio_clip.process(
-                        loop_back.loop_start,
+                        jump.resume_at,
+                        jump.resume_fract,
+                        playback_rate,
                         second_frames,
                         stereo_out,
                         first_frames,
@@ -366,6 +425,7 @@ impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for TimelineTrackNode {
                 frames,
                 &loop_crossfade_out,
                 loop_out_playhead,
+                playback_rate,
                 &process,
                 stereo_out,
                 temp_out,
@@ -373,16 +433,23 @@ impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for TimelineTrackNode {
                 first_frames,
             );
         } else {
-            // Transport is not looping in this process cycle. Process in one chunk.
+            // The playhead is not jumping in this process cycle. Process in one chunk.
 
-            let end_frame = playhead + SampleTime::from_usize(frames);
+            let end_frame = playhead + timeline_len(playhead_fract, playback_rate, frames);
 
             for audio_clip in process.audio_clips.iter() {
                 let info = audio_clip.info.get();
                 // Only use audio clips that lie within range of the current process cycle.
                 if playhead < info.timeline_end && info.timeline_start < end_frame {
                     // Fill samples from the audio clip into the output buffer.
-                    audio_clip.process(playhead, frames, stereo_out, 0);
+                    audio_clip.process(
+                        playhead,
+                        playhead_fract,
+                        playback_rate,
+                        frames,
+                        stereo_out,
+                        0,
+                    );
                 }
             }
 
@@ -405,6 +472,7 @@ impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for TimelineTrackNode {
                     // Tells this method to start copying samples from where the previous
                     // loop out crossfade ended.
                     loop_out_playhead,
+                    playback_rate,
                     &process,
                     stereo_out,
                     temp_out,
@@ -438,6 +506,7 @@ impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for TimelineTrackNode {
                 frames,
                 &seek_crossfade_out,
                 seek_out_playhead,
+                playback_rate,
                 &process,
                 stereo_out,
                 temp_out,
@@ -449,16 +518,30 @@ impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for TimelineTrackNode {
 
         let start_stop_fade = global_data.transport.audio_clip_declick().start_stop_fade();
 
+        // Nothing is played before a count-in ends.
+        let play_offset = global_data.transport.play_offset().min(frames);
+        for i in 0..play_offset {
+            stereo_out.left[i] = 0.0;
+            stereo_out.right[i] = 0.0;
+        }
+
         if start_stop_fade.is_smoothing() {
             // Declick (fade in/out) the filled samples.
-            for i in 0..frames {
-                stereo_out.left[i] *= start_stop_fade[i];
-                stereo_out.right[i] *= start_stop_fade[i];
+            for i in play_offset..frames {
+                stereo_out.left[i] *= start_stop_fade[i - play_offset];
+                stereo_out.right[i] *= start_stop_fade[i - play_offset];
             }
         }
     }
 }
 
+/// The number of samples on the timeline covered by `frames` frames starting at the
+/// sub-sample `playhead_fract` (rounded up).
+#[inline]
+fn timeline_len(playhead_fract: f64, playback_rate: f64, frames: usize) -> SampleTime {
+    SampleTime::new((playhead_fract + (frames as f64 * playback_rate)).ceil() as i64)
+}
+
 #[derive(Clone)]
 pub struct TimelineTrackProcess {
     audio_clips: Shared<Vec<AudioClipProcess>>,
@@ -8,7 +8,12 @@ use rusty_daw_core::{MusicalTime, SampleRate, SampleTime};
 use crate::backend::MAX_BLOCKSIZE;
 
 use super::audio_clip::AudioClipDeclick;
-use super::{TempoMap, TimelineTransportSaveState};
+use super::{TempoMap, TimeSignatureMap, TimelineTransportSaveState};
+
+/// The slowest playback rate.
+pub static MIN_PLAYBACK_RATE: f64 = 0.25;
+/// The fastest playback rate.
+pub static MAX_PLAYBACK_RATE: f64 = 2.0;
 
 pub struct TimelineTransportHandle {
     parameters: Shared<SharedCell<Parameters>>,
@@ -16,10 +21,17 @@ pub struct TimelineTransportHandle {
     tempo_map_shared: Shared<SharedCell<(Shared<TempoMap>, u64)>>,
     tempo_map: Shared<TempoMap>,
 
+    time_signature_map_shared: Shared<SharedCell<TimeSignatureMap>>,
+
     playhead_shared: Arc<AtomicI64>,
     playhead_smps: SampleTime,
     playhead: MusicalTime,
 
+    /// Where playback was last started from. This is stored here instead of being read
+    /// back from the transport, since the transport may not have started playing yet.
+    play_start: MusicalTime,
+    is_playing: bool,
+
     tempo_map_version: u64,
 
     coll_handle: Handle,
@@ -34,12 +46,205 @@ impl TimelineTransportHandle {
         self.parameters.set(Shared::new(&self.coll_handle, params));
     }
 
-    pub fn set_playing(&mut self, playing: bool) {
+    /// Start or pause playback.
+    ///
+    /// When playback starts, the position in the save state is remembered as the play
+    /// start position. When playback is paused, the position in the save state is set to
+    /// the current position of the playhead.
+    pub fn set_playing(&mut self, playing: bool, save_state: &mut TimelineTransportSaveState) {
+        if playing && !self.is_playing {
+            self.play_start = save_state.seek_to;
+        } else if !playing && self.is_playing {
+            save_state.seek_to = self.get_playhead_position();
+        }
+        self.is_playing = playing;
+
         let mut params = Parameters::clone(&self.parameters.get());
         params.is_playing = playing;
         self.parameters.set(Shared::new(&self.coll_handle, params));
     }
 
+    /// Seek to the given position at the next bar line while playing. If the transport
+    /// is not playing, then this seeks immediately.
+    ///
+    /// If the transport loops back before the next bar line, then it seeks at the end
+    /// of the loop instead.
+    ///
+    /// While playing, the position in the save state is left untouched since the playhead
+    /// has not moved yet.
+    pub fn seek_to_at_next_bar(
+        &mut self,
+        seek_to: MusicalTime,
+        save_state: &mut TimelineTransportSaveState,
+    ) {
+        if !self.is_playing {
+            save_state.seek_to = seek_to;
+        }
+
+        let mut params = Parameters::clone(&self.parameters.get());
+        params.seek_to_at_next_bar = (seek_to, params.seek_to_at_next_bar.1 + 1);
+        self.parameters.set(Shared::new(&self.coll_handle, params));
+    }
+
+    /// Add a new marker to the timeline.
+    ///
+    /// Returns the index of the new marker.
+    pub fn add_marker(
+        &mut self,
+        marker: Marker,
+        save_state: &mut TimelineTransportSaveState,
+    ) -> usize {
+        let i = save_state.markers.partition_point(|m| m.position.0 <= marker.position.0);
+        save_state.markers.insert(i, marker);
+        i
+    }
+
+    /// Remove the marker with the given index.
+    ///
+    /// Returns the removed marker, or `None` if the marker does not exist.
+    pub fn remove_marker(
+        &mut self,
+        index: usize,
+        save_state: &mut TimelineTransportSaveState,
+    ) -> Option<Marker> {
+        if index >= save_state.markers.len() {
+            return None;
+        }
+
+        Some(save_state.markers.remove(index))
+    }
+
+    /// Jump to the first marker after the playhead.
+    ///
+    /// Returns the position of the marker, or `None` if there is no marker after the
+    /// playhead.
+    pub fn jump_to_next_marker(
+        &mut self,
+        jump_mode: JumpMode,
+        save_state: &mut TimelineTransportSaveState,
+    ) -> Option<MusicalTime> {
+        let playhead = self.get_playhead_position();
+        let marker =
+            save_state.markers.iter().find(|m| m.position.0 > playhead.0 + MARKER_EPSILON)?;
+
+        let position = marker.position;
+        self.jump_to(position, jump_mode, save_state);
+        Some(position)
+    }
+
+    /// Jump to the last marker before the playhead.
+    ///
+    /// Returns the position of the marker, or `None` if there is no marker before the
+    /// playhead.
+    pub fn jump_to_previous_marker(
+        &mut self,
+        jump_mode: JumpMode,
+        save_state: &mut TimelineTransportSaveState,
+    ) -> Option<MusicalTime> {
+        let playhead = self.get_playhead_position();
+        let marker =
+            save_state.markers.iter().rev().find(|m| m.position.0 < playhead.0 - MARKER_EPSILON)?;
+
+        let position = marker.position;
+        self.jump_to(position, jump_mode, save_state);
+        Some(position)
+    }
+
+    fn jump_to(
+        &mut self,
+        position: MusicalTime,
+        jump_mode: JumpMode,
+        save_state: &mut TimelineTransportSaveState,
+    ) {
+        match jump_mode {
+            JumpMode::Immediate => self.seek_to(position, save_state),
+            JumpMode::NextBar => self.seek_to_at_next_bar(position, save_state),
+        }
+    }
+
+    /// Set the loop region to the range between the two markers with the given indexes.
+    ///
+    /// This will return an error if either marker does not exist, or if the resulting
+    /// loop is not valid (see `set_loop_state()`).
+    pub fn set_loop_from_markers(
+        &mut self,
+        start_index: usize,
+        end_index: usize,
+        save_state: &mut TimelineTransportSaveState,
+    ) -> Result<(), ()> {
+        let (loop_start, loop_end) =
+            match (save_state.markers.get(start_index), save_state.markers.get(end_index)) {
+                (Some(start), Some(end)) => (start.position, end.position),
+                _ => return Err(()),
+            };
+
+        self.set_loop_state(LoopState::Active { loop_start, loop_end }, save_state)
+    }
+
+    /// Stop playback and move the playhead according to the `StopBehavior` in the
+    /// save state.
+    ///
+    /// Returns the new position of the playhead.
+    pub fn stop(&mut self, save_state: &mut TimelineTransportSaveState) -> MusicalTime {
+        self.set_playing(false, save_state);
+
+        match save_state.stop_behavior {
+            StopBehavior::ReturnToPlayStart => {
+                let play_start = self.get_play_start_position();
+                self.seek_to(play_start, save_state);
+            }
+            StopBehavior::ReturnToZero => self.seek_to(MusicalTime::new(0.0), save_state),
+            StopBehavior::Stay => save_state.seek_to = self.get_playhead_position(),
+        }
+
+        save_state.seek_to
+    }
+
+    /// Set where the playhead goes when playback is stopped with `stop()`.
+    pub fn set_stop_behavior(
+        &mut self,
+        stop_behavior: StopBehavior,
+        save_state: &mut TimelineTransportSaveState,
+    ) {
+        save_state.stop_behavior = stop_behavior;
+    }
+
+    /// Set the rate the playhead moves at, where `1.0` is normal speed. Like a tape
+    /// machine, this also changes the pitch of the audio.
+    ///
+    /// Returns the playback rate (this may be clamped to fit within the range
+    /// `[MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE]`).
+    pub fn set_playback_rate(
+        &mut self,
+        playback_rate: f64,
+        save_state: &mut TimelineTransportSaveState,
+    ) -> f64 {
+        let playback_rate = if playback_rate.is_nan() { 1.0 } else { playback_rate };
+        let playback_rate = playback_rate.clamp(MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE);
+
+        save_state.playback_rate = playback_rate;
+
+        let mut params = Parameters::clone(&self.parameters.get());
+        params.playback_rate = playback_rate;
+        self.parameters.set(Shared::new(&self.coll_handle, params));
+
+        playback_rate
+    }
+
+    /// Set what happens before playback starts. This takes effect the next time the
+    /// transport starts playing.
+    pub fn set_count_in_mode(
+        &mut self,
+        count_in_mode: CountInMode,
+        save_state: &mut TimelineTransportSaveState,
+    ) {
+        save_state.count_in_mode = count_in_mode;
+
+        let mut params = Parameters::clone(&self.parameters.get());
+        params.count_in_mode = count_in_mode;
+        self.parameters.set(Shared::new(&self.coll_handle, params));
+    }
+
     /// Set the looping state.
     ///
     /// This will return an error if `loop_end - loop_start` is less than `MAX_BLOCKSIZE` (128).
@@ -48,14 +253,8 @@ impl TimelineTransportHandle {
         loop_state: LoopState,
         save_state: &mut TimelineTransportSaveState,
     ) -> Result<(), ()> {
-        if let LoopState::Active { loop_start, loop_end } = loop_state {
-            let loop_start_smp = self.tempo_map.musical_to_nearest_sample_round(loop_start);
-            let loop_end_smp = self.tempo_map.musical_to_nearest_sample_round(loop_end);
-
-            // Make sure loop is valid.
-            if loop_end_smp - loop_start_smp < SampleTime::new(MAX_BLOCKSIZE as i64) {
-                return Err(());
-            }
+        if !loop_state.is_valid(&self.tempo_map) {
+            return Err(());
         }
 
         save_state.loop_state = loop_state;
@@ -67,6 +266,27 @@ impl TimelineTransportHandle {
         Ok(())
     }
 
+    /// Set the punch-in/punch-out range.
+    ///
+    /// This will return an error if `punch_out - punch_in` is less than `MAX_BLOCKSIZE` (128).
+    pub fn set_punch_state(
+        &mut self,
+        punch_state: PunchState,
+        save_state: &mut TimelineTransportSaveState,
+    ) -> Result<(), ()> {
+        if !punch_state.is_valid(&self.tempo_map) {
+            return Err(());
+        }
+
+        save_state.punch_state = punch_state;
+
+        let mut params = Parameters::clone(&self.parameters.get());
+        params.punch_state = (punch_state, params.punch_state.1 + 1);
+        self.parameters.set(Shared::new(&self.coll_handle, params));
+
+        Ok(())
+    }
+
     pub fn get_playhead_position(&mut self) -> MusicalTime {
         let new_pos_smps = SampleTime::new(self.playhead_shared.load(Ordering::Relaxed));
         if self.playhead_smps != new_pos_smps {
@@ -77,6 +297,12 @@ impl TimelineTransportHandle {
         self.playhead
     }
 
+    /// The position where playback was last started from. This does not include any
+    /// pre-roll.
+    pub fn get_play_start_position(&self) -> MusicalTime {
+        self.play_start
+    }
+
     /// Only to be used by the `ProjectStateInterface` struct. If used anywhere else, it could cause
     /// shared state to become desynchronized.
     pub fn _update_tempo_map(&mut self, tempo_map: TempoMap) {
@@ -89,13 +315,23 @@ impl TimelineTransportHandle {
         ));
         self.tempo_map = tempo_map;
     }
+
+    /// Only to be used by the `ProjectStateInterface` struct. If used anywhere else, it could cause
+    /// shared state to become desynchronized.
+    pub fn _update_time_signature_map(&mut self, time_signature_map: TimeSignatureMap) {
+        self.time_signature_map_shared.set(Shared::new(&self.coll_handle, time_signature_map));
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 struct Parameters {
     seek_to: (MusicalTime, u64),
+    seek_to_at_next_bar: (MusicalTime, u64),
     is_playing: bool,
     loop_state: (LoopState, u64),
+    punch_state: (PunchState, u64),
+    count_in_mode: CountInMode,
+    playback_rate: f64,
 }
 
 /// The state of the timeline transport.
@@ -105,21 +341,46 @@ pub struct TimelineTransport {
     tempo_map_shared: Shared<SharedCell<(Shared<TempoMap>, u64)>>,
     tempo_map: Shared<TempoMap>,
 
+    time_signature_map_shared: Shared<SharedCell<TimeSignatureMap>>,
+    time_signature_map: Shared<TimeSignatureMap>,
+
     playhead: SampleTime,
+    /// The sub-sample part of the playhead (between `0.0` and `1.0`).
+    playhead_fract: f64,
     is_playing: bool,
+    playback_rate: f64,
+
+    /// Where playback was last started from.
+    play_start: SampleTime,
 
     loop_state: LoopStateProcInfo,
+    punch_state: PunchStateProcInfo,
 
     loop_back_info: Option<LoopBackInfo>,
+    jump_info: Option<JumpInfo>,
     seek_info: Option<SeekInfo>,
 
+    /// A seek waiting for the playhead to reach a bar line, as `(at, seek_to)`.
+    scheduled_seek: Option<(SampleTime, SampleTime)>,
+    count_in_info: Option<CountInInfo>,
+
+    /// The number of frames left in the current count-in.
+    count_in_remaining: Option<usize>,
+    /// The frame in the current process cycle where playback starts.
+    play_offset: usize,
+    /// Whether or not the transport was told to play on the previous process cycle.
+    play_requested: bool,
+
     range_checker: RangeChecker,
     next_playhead: SampleTime,
+    next_playhead_fract: f64,
 
     audio_clip_declick: Option<AudioClipDeclick>,
 
     seek_to_version: u64,
+    seek_to_at_next_bar_version: u64,
     loop_state_version: u64,
+    punch_state_version: u64,
     tempo_map_version: u64,
 
     tempo_map_changed: bool,
@@ -153,8 +414,12 @@ impl TimelineTransport {
                 &coll_handle,
                 Parameters {
                     seek_to: (save_state.seek_to, 0),
+                    seek_to_at_next_bar: (save_state.seek_to, 0),
                     is_playing: false,
                     loop_state: (save_state.loop_state, 0),
+                    punch_state: (save_state.punch_state, 0),
+                    count_in_mode: save_state.count_in_mode,
+                    playback_rate: save_state.playback_rate,
                 },
             )),
         );
@@ -164,6 +429,7 @@ impl TimelineTransport {
         let playhead = tempo_map.musical_to_nearest_sample_round(save_state.seek_to);
         let playhead_shared = Arc::new(AtomicI64::new(playhead.0));
         let loop_state = save_state.loop_state.to_proc_info(&tempo_map);
+        let punch_state = save_state.punch_state.to_proc_info(&tempo_map);
 
         let tempo_map = Shared::new(&coll_handle, tempo_map);
         let tempo_map_shared = Shared::new(
@@ -171,22 +437,41 @@ impl TimelineTransport {
             SharedCell::new(Shared::new(&coll_handle, (Shared::clone(&tempo_map), 0))),
         );
 
+        let time_signature_map = Shared::new(&coll_handle, TimeSignatureMap::default());
+        let time_signature_map_shared =
+            Shared::new(&coll_handle, SharedCell::new(Shared::clone(&time_signature_map)));
+
         (
             TimelineTransport {
                 parameters: Shared::clone(&parameters),
                 tempo_map_shared: Shared::clone(&tempo_map_shared),
                 tempo_map: Shared::clone(&tempo_map),
+                time_signature_map_shared: Shared::clone(&time_signature_map_shared),
+                time_signature_map,
                 playhead,
+                playhead_fract: 0.0,
                 is_playing: false,
+                playback_rate: save_state.playback_rate,
+                play_start: playhead,
                 loop_state,
+                punch_state,
                 loop_back_info: None,
+                jump_info: None,
                 seek_info: None,
+                scheduled_seek: None,
+                count_in_info: None,
+                count_in_remaining: None,
+                play_offset: 0,
+                play_requested: false,
                 range_checker: RangeChecker::Paused,
                 next_playhead: playhead,
+                next_playhead_fract: 0.0,
                 audio_clip_declick: Some(AudioClipDeclick::new(sample_rate)),
                 seek_to_version: 0,
+                seek_to_at_next_bar_version: 0,
                 tempo_map_version: 0,
                 loop_state_version: 0,
+                punch_state_version: 0,
                 tempo_map_changed: false,
                 playhead_shared: Arc::clone(&playhead_shared),
             },
@@ -194,22 +479,40 @@ impl TimelineTransport {
                 parameters,
                 tempo_map_shared,
                 tempo_map,
+                time_signature_map_shared,
                 coll_handle,
                 tempo_map_version: 0,
                 playhead_shared,
                 playhead_smps: playhead,
                 playhead: save_state.seek_to,
+                play_start: save_state.seek_to,
+                is_playing: false,
             },
         )
     }
 
     /// Update the state of this transport.
     pub fn process(&mut self, frames: usize) {
-        let Parameters { seek_to, is_playing, loop_state } = *self.parameters.get();
-
-        let st_frames = SampleTime::from_usize(frames);
+        let Parameters {
+            seek_to,
+            seek_to_at_next_bar,
+            is_playing,
+            loop_state,
+            punch_state,
+            count_in_mode,
+            playback_rate,
+        } = *self.parameters.get();
+
+        if playback_rate == 1.0 && self.next_playhead_fract != 0.0 {
+            // Snap the playhead back onto a whole sample when returning to normal speed.
+            if self.next_playhead_fract >= 0.5 {
+                self.next_playhead += SampleTime::new(1);
+            }
+            self.next_playhead_fract = 0.0;
+        }
 
         self.playhead = self.next_playhead;
+        self.playback_rate = playback_rate;
 
         let mut loop_state_changed = false;
         if self.loop_state_version != loop_state.1 {
@@ -217,6 +520,12 @@ impl TimelineTransport {
             loop_state_changed = true;
         }
 
+        let mut punch_state_changed = false;
+        if self.punch_state_version != punch_state.1 {
+            self.punch_state_version = punch_state.1;
+            punch_state_changed = true;
+        }
+
         // Check if the tempo map has changed.
         self.tempo_map_changed = false;
         let (new_tempo_map, new_version) = &*self.tempo_map_shared.get();
@@ -225,6 +534,10 @@ impl TimelineTransport {
 
             // Get musical time of the playhead using the old tempo map.
             let playhead = self.tempo_map.sample_to_musical(self.playhead);
+            let play_start = self.tempo_map.sample_to_musical(self.play_start);
+            let scheduled_seek = self.scheduled_seek.map(|(at, seek_to)| {
+                (self.tempo_map.sample_to_musical(at), self.tempo_map.sample_to_musical(seek_to))
+            });
 
             // Make sure the audio clip declicker updates it internal playheads.
             self.audio_clip_declick
@@ -237,18 +550,37 @@ impl TimelineTransport {
 
             // Update proc info.
             self.playhead = self.tempo_map.musical_to_nearest_sample_round(playhead);
+            self.next_playhead = self.playhead;
+            self.next_playhead_fract = 0.0;
+            self.play_start = self.tempo_map.musical_to_nearest_sample_round(play_start);
+            self.scheduled_seek = scheduled_seek.map(|(at, seek_to)| {
+                (
+                    self.tempo_map.musical_to_nearest_sample_round(at),
+                    self.tempo_map.musical_to_nearest_sample_round(seek_to),
+                )
+            });
             loop_state_changed = true;
+            punch_state_changed = true;
         }
 
+        self.time_signature_map = self.time_signature_map_shared.get();
+
         // Seek if gotten a new version of the seek_to value.
         self.seek_info = None;
         if self.seek_to_version != seek_to.1 {
             self.seek_to_version = seek_to.1;
 
-            self.seek_info = Some(SeekInfo { seeked_from_playhead: self.playhead });
+            self.seek_info = Some(SeekInfo {
+                seeked_from_playhead: self.playhead,
+                seeked_from_playhead_fract: self.next_playhead_fract,
+            });
 
             self.playhead = self.tempo_map.musical_to_nearest_sample_round(seek_to.0);
             self.next_playhead = self.playhead;
+            self.next_playhead_fract = 0.0;
+
+            // A manual seek cancels any scheduled seek.
+            self.scheduled_seek = None;
         };
 
         if loop_state_changed {
@@ -261,39 +593,166 @@ impl TimelineTransport {
             };
         }
 
+        if punch_state_changed {
+            self.punch_state = punch_state.0.to_proc_info(&self.tempo_map);
+        }
+
+        if self.seek_to_at_next_bar_version != seek_to_at_next_bar.1 {
+            self.seek_to_at_next_bar_version = seek_to_at_next_bar.1;
+
+            let seek_to = self.tempo_map.musical_to_nearest_sample_round(seek_to_at_next_bar.0);
+            self.scheduled_seek = Some((self.next_bar_line_after_playhead(), seek_to));
+        }
+
+        if let (false, Some((_, seek_to))) = (is_playing, self.scheduled_seek) {
+            // Not playing, so seek immediately.
+            self.seek_info = Some(SeekInfo {
+                seeked_from_playhead: self.next_playhead,
+                seeked_from_playhead_fract: self.next_playhead_fract,
+            });
+
+            self.next_playhead = seek_to;
+            self.next_playhead_fract = 0.0;
+            self.scheduled_seek = None;
+        }
+
+        let start_playing = is_playing && !self.play_requested;
+        self.play_requested = is_playing;
+        if !is_playing {
+            // Stopping cancels any active count-in.
+            self.count_in_remaining = None;
+        }
+        if start_playing {
+            self.play_start = self.next_playhead;
+
+            match count_in_mode {
+                CountInMode::Off => {}
+                CountInMode::CountIn { bars } => {
+                    // The length of the count-in in frames. This is counted in frames
+                    // rather than in whole process cycles, so the count-in stays exactly
+                    // this long even if the block size changes while counting in.
+                    let len = (self.count_in_len(bars).0 as f64 / playback_rate).round() as usize;
+                    if len > 0 {
+                        self.count_in_remaining = Some(len);
+                    }
+                }
+                CountInMode::PreRoll { bars } => {
+                    // Start playing earlier, but never before the start of the timeline. Note
+                    // that the start position stored in the save state stays the same.
+                    let pre_roll = self.count_in_len(bars);
+                    self.next_playhead = if pre_roll > self.next_playhead {
+                        SampleTime::new(0)
+                    } else {
+                        self.next_playhead - pre_roll
+                    };
+                }
+            }
+        }
+
+        self.count_in_info = None;
+        self.play_offset = 0;
         self.is_playing = is_playing;
+        if let Some(remaining) = self.count_in_remaining {
+            // Hold the playhead until the count-in is done.
+            let remaining_timeline = remaining as f64 * playback_rate;
+            self.count_in_info = Some(CountInInfo {
+                playhead: self.next_playhead - SampleTime::new(remaining_timeline.round() as i64),
+                end: self.next_playhead,
+                frames: remaining.min(frames),
+            });
+
+            if remaining >= frames {
+                self.count_in_remaining =
+                    if remaining > frames { Some(remaining - frames) } else { None };
+                self.is_playing = false;
+            } else {
+                // The count-in ends in the middle of this process cycle, so playback starts
+                // at that frame. The playhead is moved back to where it would have been at
+                // the start of this cycle, so it reaches the start position exactly at
+                // `play_offset`. Nodes must stay silent before `play_offset`.
+                self.count_in_remaining = None;
+                self.play_offset = remaining;
+
+                let playhead =
+                    self.next_playhead.0 as f64 + self.next_playhead_fract - remaining_timeline;
+                self.next_playhead = SampleTime::new(playhead.floor() as i64);
+                self.next_playhead_fract = playhead - playhead.floor();
+            }
+        }
+
         self.loop_back_info = None;
+        self.jump_info = None;
         self.playhead = self.next_playhead;
+        self.playhead_fract = self.next_playhead_fract;
         if self.is_playing {
             // Advance the playhead.
-            let mut did_loop = false;
-            if let LoopStateProcInfo::Active { loop_start, loop_end } = self.loop_state {
-                if self.playhead < loop_end && self.playhead + st_frames >= loop_end {
-                    let first_frames = loop_end - self.playhead;
-                    let second_frames = st_frames - first_frames;
-
-                    self.range_checker = RangeChecker::Looping {
-                        end_frame_1: loop_end,
-                        start_frame_2: loop_start,
-                        end_frame_2: loop_start + second_frames,
-                    };
-
-                    self.next_playhead = loop_start + second_frames;
+            let playhead = self.playhead.0 as f64 + self.playhead_fract;
+            let end = playhead + (frames as f64 * playback_rate);
+
+            // Where playback actually starts in this process cycle.
+            let play_from = playhead + (self.play_offset as f64 * playback_rate);
+
+            // The playhead jumps either to a scheduled seek, or back to the start of the
+            // loop, as `(jump_from, jump_to, is_loop_back)`.
+            let mut jump = None;
+            if let Some((at, seek_to)) = self.scheduled_seek {
+                if play_from < at.0 as f64 && end >= at.0 as f64 {
+                    jump = Some((at, seek_to, false));
+                    self.scheduled_seek = None;
+                }
+            }
+            if jump.is_none() {
+                if let LoopStateProcInfo::Active { loop_start, loop_end } = self.loop_state {
+                    if play_from < loop_end.0 as f64 && end >= loop_end.0 as f64 {
+                        jump = Some((loop_end, loop_start, true));
+                    }
+                }
+            }
 
+            if let Some((jump_from, jump_to, is_loop_back)) = jump {
+                let first_len = jump_from.0 as f64 - playhead;
+                let first_frames = ((first_len / playback_rate).ceil() as usize).min(frames);
+
+                // The first frame after the jump lies slightly past `jump_to` when the
+                // jump falls between two frames, by however far the last frame before the
+                // jump overshot `jump_from`.
+                let overshoot = ((first_frames as f64 * playback_rate) - first_len).max(0.0);
+                let resume_at = jump_to + SampleTime::new(overshoot.floor() as i64);
+                let resume_fract = overshoot.fract();
+
+                let second_end = resume_fract + ((frames - first_frames) as f64 * playback_rate);
+
+                self.range_checker = RangeChecker::Looping {
+                    end_frame_1: jump_from,
+                    start_frame_2: resume_at,
+                    end_frame_2: resume_at + SampleTime::new(second_end.ceil() as i64),
+                };
+
+                self.next_playhead = resume_at + SampleTime::new(second_end.floor() as i64);
+                self.next_playhead_fract = second_end.fract();
+
+                self.jump_info = Some(JumpInfo {
+                    jump_from,
+                    jump_to,
+                    resume_at,
+                    resume_fract,
+                    playhead_end: self.next_playhead,
+                    first_frames,
+                });
+                if is_loop_back {
                     self.loop_back_info = Some(LoopBackInfo {
-                        loop_start,
-                        loop_end,
+                        loop_start: jump_to,
+                        loop_end: jump_from,
                         playhead_end: self.next_playhead,
+                        first_frames,
                     });
-
-                    did_loop = true;
                 }
-            }
-
-            if !did_loop {
-                self.next_playhead = self.playhead + st_frames;
+            } else {
+                self.next_playhead = SampleTime::new(end.floor() as i64);
+                self.next_playhead_fract = end.fract();
 
-                self.range_checker = RangeChecker::Playing { end_frame: self.next_playhead };
+                self.range_checker =
+                    RangeChecker::Playing { end_frame: SampleTime::new(end.ceil() as i64) };
             }
         } else {
             self.range_checker = RangeChecker::Paused;
@@ -307,6 +766,50 @@ impl TimelineTransport {
         self.audio_clip_declick = Some(audio_clip_declick);
     }
 
+    /// The position of the first bar line after the playhead. If the transport is looping,
+    /// then this will be no later than the end of the loop.
+    fn next_bar_line_after_playhead(&self) -> SampleTime {
+        let playhead = self.tempo_map.sample_to_musical(self.next_playhead + SampleTime::new(1));
+        let next_bar_line = self
+            .tempo_map
+            .musical_to_nearest_sample_round(self.time_signature_map.next_bar_line(playhead));
+
+        if let LoopStateProcInfo::Active { loop_end, .. } = self.loop_state {
+            if self.next_playhead < loop_end && next_bar_line > loop_end {
+                return loop_end;
+            }
+        }
+
+        next_bar_line
+    }
+
+    /// The length of the given number of bars before the playhead.
+    ///
+    /// Each bar uses the time signature in force during that bar, not the one at the
+    /// playhead. Before the start of the timeline, the first time signature is used.
+    fn count_in_len(&self, bars: u32) -> SampleTime {
+        let time_signature_map = &*self.time_signature_map;
+
+        let start = self.tempo_map.sample_to_musical(self.next_playhead);
+        let mut count_in_start = start;
+        for _ in 0..bars {
+            let before = MusicalTime::new(count_in_start.0 - 1.0e-6);
+            let on_bar_line =
+                (time_signature_map.next_bar_line(before).0 - count_in_start.0).abs() < 1.0e-6;
+
+            count_in_start = if on_bar_line && before.0 > 0.0 {
+                // Step back to the previous bar line. This also counts a bar that was cut
+                // short by a meter change as a whole bar.
+                time_signature_map.bar_start(time_signature_map.musical_to_bbt(before).bar)
+            } else {
+                let bar_len = time_signature_map.time_signature_at(before).bar_len();
+                MusicalTime::new(count_in_start.0 - bar_len)
+            };
+        }
+
+        self.next_playhead - self.tempo_map.musical_to_nearest_sample_round(count_in_start)
+    }
+
     /// When `plackback_state()` is of type `Playing`, then this position is the frame at the start
     /// of this process block. (And `playhead + proc_info.frames` is the end position (exclusive) of
     /// this process block.)
@@ -315,6 +818,28 @@ impl TimelineTransport {
         self.playhead
     }
 
+    /// The position where playback was last started from. This does not include any
+    /// pre-roll.
+    #[inline]
+    pub fn play_start(&self) -> SampleTime {
+        self.play_start
+    }
+
+    /// The sub-sample part of the playhead (between `0.0` and `1.0`). This is only ever
+    /// non-zero when the playback rate is not `1.0`.
+    #[inline]
+    pub fn playhead_fract(&self) -> f64 {
+        self.playhead_fract
+    }
+
+    /// The rate the playhead moves at, where `1.0` is normal speed. While playing, the
+    /// playhead moves `frames * playback_rate()` samples along the timeline each process
+    /// cycle.
+    #[inline]
+    pub fn playback_rate(&self) -> f64 {
+        self.playback_rate
+    }
+
     /// Whether or not the timeline is playing.
     #[inline]
     pub fn is_playing(&self) -> bool {
@@ -327,18 +852,63 @@ impl TimelineTransport {
         self.loop_state
     }
 
+    /// The punch-in/punch-out range on this transport.
+    #[inline]
+    pub fn punch_state(&self) -> PunchStateProcInfo {
+        self.punch_state
+    }
+
     /// Returns `Some` if the transport is looping back on this current process cycle.
     #[inline]
     pub fn do_loop_back(&self) -> Option<&LoopBackInfo> {
         self.loop_back_info.as_ref()
     }
 
+    /// Returns `Some` if the playhead reaches a seek scheduled with `seek_to_at_next_bar()`
+    /// on this current process cycle.
+    #[inline]
+    pub fn did_scheduled_seek(&self) -> Option<&JumpInfo> {
+        if self.loop_back_info.is_none() {
+            self.jump_info.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Returns `Some` if the playhead jumps in the middle of this current process cycle,
+    /// either because the transport is looping back or because it reached a scheduled
+    /// seek.
+    ///
+    /// Nodes that play back material from the timeline should use this instead of
+    /// `do_loop_back()`.
+    #[inline]
+    pub fn playhead_jump(&self) -> Option<&JumpInfo> {
+        self.jump_info.as_ref()
+    }
+
     /// Returns `Some` if the transport has seeked to a new position this current process cycle.
     #[inline]
     pub fn did_seek(&self) -> Option<&SeekInfo> {
         self.seek_info.as_ref()
     }
 
+    /// Returns `Some` if the transport is counting in on this current process cycle.
+    ///
+    /// While counting in, `is_playing()` returns `false` and the playhead does not move,
+    /// except on the last cycle of the count-in if playback starts partway through it.
+    #[inline]
+    pub fn count_in(&self) -> Option<&CountInInfo> {
+        self.count_in_info.as_ref()
+    }
+
+    /// The frame in this current process cycle where playback starts. This is only non-zero
+    /// on the cycle where a count-in ends, in which case nothing should be played before
+    /// this frame.
+    #[inline]
+    pub fn play_offset(&self) -> usize {
+        self.play_offset
+    }
+
     /// Returns true if the tempo map has changed this current process cycle.
     #[inline]
     pub fn did_tempo_map_change(&self) -> bool {
@@ -351,6 +921,12 @@ impl TimelineTransport {
         &*self.tempo_map
     }
 
+    /// Returns the current time signature map.
+    #[inline]
+    pub fn time_signature_map(&self) -> &TimeSignatureMap {
+        &*self.time_signature_map
+    }
+
     /// Use this to check whether a range of samples lies inside this current process block.
     ///
     /// This will properly handle playing, paused, and looping conditions.
@@ -372,12 +948,44 @@ impl TimelineTransport {
         self.range_checker.is_sample_active(self.playhead, sample)
     }
 
+    /// Use this to check whether any part of a range of samples that lies inside the punch
+    /// range also lies inside this current process block.
+    ///
+    /// This will always return false when the punch range is inactive, or when the transport
+    /// status is `Paused` or `Clear`.
+    ///
+    /// * `start` - The start of the range (inclusive).
+    /// * `end` - The end of the range (exclusive).
+    pub fn is_punch_range_active(&self, start: SampleTime, end: SampleTime) -> bool {
+        if let PunchStateProcInfo::Active { punch_in, punch_out } = self.punch_state {
+            let start = if start > punch_in { start } else { punch_in };
+            let end = if end < punch_out { end } else { punch_out };
+
+            start < end && self.range_checker.is_range_active(self.playhead, start, end)
+        } else {
+            false
+        }
+    }
+
+    /// Use this to check whether a particular sample lies inside the punch range.
+    ///
+    /// This will always return false when the punch range is inactive.
+    #[inline]
+    pub fn is_sample_in_punch(&self, sample: SampleTime) -> bool {
+        if let PunchStateProcInfo::Active { punch_in, punch_out } = self.punch_state {
+            sample >= punch_in && sample < punch_out
+        } else {
+            false
+        }
+    }
+
     /// Returns the audio clip declicker helper struct.
     pub fn audio_clip_declick(&self) -> &AudioClipDeclick {
         self.audio_clip_declick.as_ref().unwrap()
     }
 }
 
+/// Info about the transport looping back in the middle of this process cycle.
 #[derive(Debug, Clone, Copy)]
 pub struct LoopBackInfo {
     /// The frame where the loop starts on the timeline (inclusive).
@@ -388,6 +996,36 @@ pub struct LoopBackInfo {
 
     /// The frame where the playhead will end on this current process cycle (exclusive).
     pub playhead_end: SampleTime,
+
+    /// The number of frames in this process cycle before the playhead jumps back. This
+    /// is only equal to `loop_end - playhead` when the playback rate is `1.0`.
+    pub first_frames: usize,
+}
+
+/// Info about the playhead jumping in the middle of this process cycle. This happens when
+/// the transport loops back, and when a seek scheduled with `seek_to_at_next_bar()` is
+/// reached.
+#[derive(Debug, Clone, Copy)]
+pub struct JumpInfo {
+    /// The frame on the timeline where the playhead jumps from (exclusive).
+    pub jump_from: SampleTime,
+
+    /// The frame on the timeline where the playhead jumps to (inclusive).
+    pub jump_to: SampleTime,
+
+    /// The position of the first frame after the jump. When the playback rate is not
+    /// `1.0`, this can lie slightly after `jump_to`.
+    pub resume_at: SampleTime,
+
+    /// The sub-sample part of `resume_at` (between `0.0` and `1.0`).
+    pub resume_fract: f64,
+
+    /// The frame where the playhead will end on this current process cycle (exclusive).
+    pub playhead_end: SampleTime,
+
+    /// The number of frames in this process cycle before the playhead jumps. This is only
+    /// equal to `jump_from - playhead` when the playback rate is `1.0`.
+    pub first_frames: usize,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -395,6 +1033,25 @@ pub struct SeekInfo {
     /// This is what the playhead would have been if the transport did not seek this
     /// process cycle.
     pub seeked_from_playhead: SampleTime,
+
+    /// The sub-sample part of `seeked_from_playhead` (between `0.0` and `1.0`).
+    pub seeked_from_playhead_fract: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CountInInfo {
+    /// The position of the count-in at the start of this process cycle. This lies before
+    /// the playhead, so the count-in follows the tempo and time signature of the bars
+    /// leading up to the playhead.
+    pub playhead: SampleTime,
+
+    /// The frame where the count-in ends and playback starts (exclusive).
+    pub end: SampleTime,
+
+    /// The number of frames in this process cycle that are part of the count-in. If this
+    /// is less than the number of frames in the cycle, then playback starts right after
+    /// the count-in (see `TimelineTransport::play_offset()`).
+    pub frames: usize,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -434,6 +1091,47 @@ impl RangeChecker {
     }
 }
 
+/// The distance (in beats) the playhead must be from a marker to jump past it.
+static MARKER_EPSILON: f64 = 1.0e-6;
+
+/// A named position on the timeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub name: String,
+    pub position: MusicalTime,
+}
+
+/// When to jump to a new position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JumpMode {
+    /// Jump immediately.
+    Immediate,
+    /// Jump at the next bar line while playing, or immediately when not playing.
+    NextBar,
+}
+
+/// Where the playhead goes when the transport is stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopBehavior {
+    /// Return to the position where playback was last started from.
+    ReturnToPlayStart,
+    /// Return to the beginning of the timeline.
+    ReturnToZero,
+    /// Stay at the current position.
+    Stay,
+}
+
+/// What happens before the transport starts playing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CountInMode {
+    /// Start playing immediately.
+    Off,
+    /// Count in the given number of bars with the metronome before starting to play.
+    CountIn { bars: u32 },
+    /// Start playing the given number of bars before the playhead.
+    PreRoll { bars: u32 },
+}
+
 /// The status of looping on this transport.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LoopState {
@@ -449,6 +1147,16 @@ pub enum LoopState {
 }
 
 impl LoopState {
+    /// Whether the loop is long enough to be used. An inactive loop is always valid.
+    pub fn is_valid(&self, tempo_map: &TempoMap) -> bool {
+        match self.to_proc_info(tempo_map) {
+            LoopStateProcInfo::Inactive => true,
+            LoopStateProcInfo::Active { loop_start, loop_end } => {
+                loop_end - loop_start >= SampleTime::new(MAX_BLOCKSIZE as i64)
+            }
+        }
+    }
+
     fn to_proc_info(&self, tempo_map: &TempoMap) -> LoopStateProcInfo {
         match self {
             LoopState::Inactive => LoopStateProcInfo::Inactive,
@@ -460,6 +1168,57 @@ impl LoopState {
     }
 }
 
+/// The punch-in/punch-out range on this transport.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PunchState {
+    /// Recording is not limited to a range.
+    Inactive,
+    /// Recording only happens inside the given range.
+    Active {
+        /// The start of the punch range (inclusive).
+        punch_in: MusicalTime,
+        /// The end of the punch range (exclusive).
+        punch_out: MusicalTime,
+    },
+}
+
+impl PunchState {
+    /// Whether the punch range is long enough to be used. An inactive punch range is
+    /// always valid.
+    pub fn is_valid(&self, tempo_map: &TempoMap) -> bool {
+        match self.to_proc_info(tempo_map) {
+            PunchStateProcInfo::Inactive => true,
+            PunchStateProcInfo::Active { punch_in, punch_out } => {
+                punch_out - punch_in >= SampleTime::new(MAX_BLOCKSIZE as i64)
+            }
+        }
+    }
+
+    fn to_proc_info(&self, tempo_map: &TempoMap) -> PunchStateProcInfo {
+        match self {
+            PunchState::Inactive => PunchStateProcInfo::Inactive,
+            &PunchState::Active { punch_in, punch_out } => PunchStateProcInfo::Active {
+                punch_in: tempo_map.musical_to_nearest_sample_round(punch_in),
+                punch_out: tempo_map.musical_to_nearest_sample_round(punch_out),
+            },
+        }
+    }
+}
+
+/// The punch-in/punch-out range on this transport.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PunchStateProcInfo {
+    /// Recording is not limited to a range.
+    Inactive,
+    /// Recording only happens inside the given range.
+    Active {
+        /// The start of the punch range (inclusive).
+        punch_in: SampleTime,
+        /// The end of the punch range (exclusive).
+        punch_out: SampleTime,
+    },
+}
+
 /// The status of looping on this transport.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LoopStateProcInfo {
@@ -532,4 +1291,329 @@ mod tests {
         assert!(!r.is_sample_active(playhead, SampleTime::new(24)));
         assert!(!r.is_sample_active(playhead, SampleTime::new(25)));
     }
+
+    fn new_transport(
+        collector: &basedrop::Collector,
+    ) -> (super::TimelineTransport, super::TimelineTransportHandle) {
+        // At 120 BPM and 48 kHz, one beat is 24000 samples long.
+        super::TimelineTransport::new(collector.handle(), rusty_daw_core::SampleRate::new(48_000.0))
+    }
+
+    #[test]
+    fn count_in_uses_meter_before_playhead() {
+        use super::*;
+        use crate::backend::timeline::{
+            TimeSignature, TimeSignaturePoint, TimelineTransportSaveState,
+        };
+
+        let collector = basedrop::Collector::new();
+        let (mut transport, mut handle) = new_transport(&collector);
+        let mut save_state = TimelineTransportSaveState::default();
+
+        // Two bars of 4/4, then 3/4 from bar 3.
+        let mut map = TimeSignatureMap::default();
+        map.insert_point(TimeSignaturePoint {
+            position: MusicalTime::new(8.0),
+            time_signature: TimeSignature::new(3, 4).unwrap(),
+        })
+        .unwrap();
+        handle._update_time_signature_map(map);
+
+        handle.seek_to(MusicalTime::new(8.0), &mut save_state);
+        transport.process(128);
+        assert_eq!(transport.count_in_len(1), SampleTime::new(4 * 24_000));
+
+        handle.seek_to(MusicalTime::new(11.0), &mut save_state);
+        transport.process(128);
+        assert_eq!(transport.count_in_len(1), SampleTime::new(3 * 24_000));
+        assert_eq!(transport.count_in_len(2), SampleTime::new(7 * 24_000));
+
+        // In the middle of a bar, a whole bar of the current meter is counted.
+        handle.seek_to(MusicalTime::new(2.0), &mut save_state);
+        transport.process(128);
+        assert_eq!(transport.count_in_len(1), SampleTime::new(4 * 24_000));
+    }
+
+    #[test]
+    fn count_in_lasts_exact_number_of_bars() {
+        use super::*;
+        use crate::backend::timeline::TimelineTransportSaveState;
+
+        let collector = basedrop::Collector::new();
+        let (mut transport, mut handle) = new_transport(&collector);
+        let mut save_state = TimelineTransportSaveState::default();
+
+        // One bar is 96000 frames, which is 755 whole cycles of 127 frames plus 115 frames.
+        handle.set_count_in_mode(CountInMode::CountIn { bars: 1 }, &mut save_state);
+        handle.seek_to(MusicalTime::new(4.0), &mut save_state);
+        handle.set_playing(true, &mut save_state);
+
+        transport.process(127);
+        assert_eq!(transport.count_in().unwrap().playhead, SampleTime::new(0));
+        for _ in 1..755 {
+            transport.process(127);
+            assert_eq!(transport.count_in().unwrap().frames, 127);
+            assert!(!transport.is_playing());
+        }
+
+        // Playback starts partway through the last cycle of the count-in.
+        transport.process(127);
+        assert_eq!(transport.count_in().unwrap().frames, 115);
+        assert!(transport.is_playing());
+        assert_eq!(transport.play_offset(), 115);
+        assert_eq!(transport.playhead(), SampleTime::new(96_000 - 115));
+        assert_eq!(transport.play_start(), SampleTime::new(96_000));
+
+        transport.process(127);
+        assert!(transport.count_in().is_none());
+        assert_eq!(transport.play_offset(), 0);
+        assert_eq!(transport.playhead(), SampleTime::new(96_000 + 12));
+    }
+
+    #[test]
+    fn count_in_ignores_block_size_changes() {
+        use super::*;
+        use crate::backend::timeline::TimelineTransportSaveState;
+
+        let collector = basedrop::Collector::new();
+        let (mut transport, mut handle) = new_transport(&collector);
+        let mut save_state = TimelineTransportSaveState::default();
+
+        handle.set_count_in_mode(CountInMode::CountIn { bars: 1 }, &mut save_state);
+        handle.seek_to(MusicalTime::new(0.0), &mut save_state);
+        handle.set_playing(true, &mut save_state);
+
+        // Alternate between two block sizes, and count the frames of the count-in.
+        let mut counted = 0;
+        let mut cycle = 0;
+        loop {
+            let frames = if cycle % 2 == 0 { 128 } else { 77 };
+            cycle += 1;
+
+            transport.process(frames);
+            counted += transport.count_in().unwrap().frames;
+            if transport.is_playing() {
+                break;
+            }
+        }
+        assert_eq!(counted, 96_000);
+
+        // Playback starts exactly at the start of the timeline, even if that means the
+        // playhead lies before it at the start of the cycle.
+        let playhead = transport.playhead().0 + transport.play_offset() as i64;
+        assert_eq!(playhead, 0);
+    }
+
+    #[test]
+    fn pre_roll_stops_at_timeline_start() {
+        use super::*;
+        use crate::backend::timeline::TimelineTransportSaveState;
+
+        let collector = basedrop::Collector::new();
+        let (mut transport, mut handle) = new_transport(&collector);
+        let mut save_state = TimelineTransportSaveState::default();
+
+        handle.set_count_in_mode(CountInMode::PreRoll { bars: 2 }, &mut save_state);
+        handle.seek_to(MusicalTime::new(4.0), &mut save_state);
+        handle.set_playing(true, &mut save_state);
+
+        transport.process(128);
+        assert_eq!(transport.playhead(), SampleTime::new(0));
+        assert_eq!(transport.play_start(), SampleTime::new(96_000));
+    }
+
+    #[test]
+    fn stop_behavior() {
+        use super::*;
+        use crate::backend::timeline::TimelineTransportSaveState;
+
+        let collector = basedrop::Collector::new();
+        let (mut transport, mut handle) = new_transport(&collector);
+        let mut save_state = TimelineTransportSaveState::default();
+
+        handle.seek_to(MusicalTime::new(1.0), &mut save_state);
+        handle.set_playing(true, &mut save_state);
+        for _ in 0..10 {
+            transport.process(128);
+        }
+        assert_eq!(handle.stop(&mut save_state), MusicalTime::new(1.0));
+        transport.process(128);
+        assert_eq!(transport.playhead(), SampleTime::new(24_000));
+
+        // Stopping before the transport has started playing still returns to where
+        // playback was started from, not where it was started from the last time.
+        handle.seek_to(MusicalTime::new(3.0), &mut save_state);
+        handle.set_playing(true, &mut save_state);
+        assert_eq!(handle.stop(&mut save_state), MusicalTime::new(3.0));
+        transport.process(128);
+        assert!(!transport.is_playing());
+        assert_eq!(transport.playhead(), SampleTime::new(72_000));
+
+        handle.set_stop_behavior(StopBehavior::ReturnToZero, &mut save_state);
+        handle.set_playing(true, &mut save_state);
+        transport.process(128);
+        assert_eq!(handle.stop(&mut save_state), MusicalTime::new(0.0));
+        transport.process(128);
+        assert_eq!(transport.playhead(), SampleTime::new(0));
+
+        handle.set_stop_behavior(StopBehavior::Stay, &mut save_state);
+        handle.set_playing(true, &mut save_state);
+        for _ in 0..10 {
+            transport.process(120);
+        }
+        assert!((handle.stop(&mut save_state).0 - 0.05).abs() < 1.0e-9);
+        transport.process(128);
+        assert_eq!(transport.playhead(), SampleTime::new(1_200));
+    }
+
+    #[test]
+    fn scheduled_seek_is_not_a_loop_back() {
+        use super::*;
+        use crate::backend::timeline::TimelineTransportSaveState;
+
+        let collector = basedrop::Collector::new();
+        let (mut transport, mut handle) = new_transport(&collector);
+        let mut save_state = TimelineTransportSaveState::default();
+
+        handle.set_playing(true, &mut save_state);
+        transport.process(128);
+        handle.seek_to_at_next_bar(MusicalTime::new(8.0), &mut save_state);
+
+        // The playhead has not moved yet.
+        assert_eq!(save_state.seek_to, MusicalTime::new(0.0));
+
+        let mut cycles = 1;
+        while transport.did_scheduled_seek().is_none() {
+            transport.process(128);
+            assert!(transport.do_loop_back().is_none());
+            cycles += 1;
+        }
+        assert_eq!(cycles, 750);
+
+        let jump = transport.did_scheduled_seek().unwrap();
+        assert_eq!(jump.jump_from, SampleTime::new(96_000));
+        assert_eq!(jump.jump_to, SampleTime::new(192_000));
+        assert_eq!(jump.first_frames, 128);
+        assert_eq!(transport.playhead_jump().unwrap().playhead_end, SampleTime::new(192_000));
+    }
+
+    #[test]
+    fn punch_range() {
+        use super::*;
+        use crate::backend::timeline::TimelineTransportSaveState;
+
+        let collector = basedrop::Collector::new();
+        let (mut transport, mut handle) = new_transport(&collector);
+        let mut save_state = TimelineTransportSaveState::default();
+
+        transport.process(128);
+        assert!(!transport.is_sample_in_punch(SampleTime::new(0)));
+
+        // Punch ranges shorter than one block are rejected and leave the state untouched.
+        let too_short = PunchState::Active {
+            punch_in: MusicalTime::new(1.0),
+            punch_out: MusicalTime::new(1.0 + (100.0 / 24_000.0)),
+        };
+        assert!(handle.set_punch_state(too_short, &mut save_state).is_err());
+        assert_eq!(save_state.punch_state, PunchState::Inactive);
+
+        let punch_state = PunchState::Active {
+            punch_in: MusicalTime::new(1.0),
+            punch_out: MusicalTime::new(2.0),
+        };
+        handle.set_punch_state(punch_state, &mut save_state).unwrap();
+        assert_eq!(save_state.punch_state, punch_state);
+
+        transport.process(128);
+        assert_eq!(
+            transport.punch_state(),
+            PunchStateProcInfo::Active {
+                punch_in: SampleTime::new(24_000),
+                punch_out: SampleTime::new(48_000)
+            }
+        );
+        assert!(!transport.is_sample_in_punch(SampleTime::new(23_999)));
+        assert!(transport.is_sample_in_punch(SampleTime::new(24_000)));
+        assert!(transport.is_sample_in_punch(SampleTime::new(47_999)));
+        assert!(!transport.is_sample_in_punch(SampleTime::new(48_000)));
+
+        // The punch range is only active while playing.
+        assert!(!transport.is_punch_range_active(SampleTime::new(0), SampleTime::new(100_000)));
+        handle.seek_to(MusicalTime::new(47_936.0 / 24_000.0), &mut save_state);
+        handle.set_playing(true, &mut save_state);
+        transport.process(128);
+        assert!(transport.is_punch_range_active(SampleTime::new(0), SampleTime::new(100_000)));
+        assert!(!transport.is_punch_range_active(SampleTime::new(48_000), SampleTime::new(48_064)));
+
+        handle.set_punch_state(PunchState::Inactive, &mut save_state).unwrap();
+        transport.process(128);
+        assert!(!transport.is_sample_in_punch(SampleTime::new(24_000)));
+    }
+
+    #[test]
+    fn playback_rate_changes() {
+        use super::*;
+        use crate::backend::timeline::TimelineTransportSaveState;
+
+        let collector = basedrop::Collector::new();
+        let (mut transport, mut handle) = new_transport(&collector);
+        let mut save_state = TimelineTransportSaveState::default();
+
+        assert_eq!(handle.set_playback_rate(10.0, &mut save_state), MAX_PLAYBACK_RATE);
+        assert_eq!(handle.set_playback_rate(0.0, &mut save_state), MIN_PLAYBACK_RATE);
+
+        // 128 frames at 0.7x moves the playhead 89.6 samples.
+        handle.set_playback_rate(0.7, &mut save_state);
+        handle.set_playing(true, &mut save_state);
+        transport.process(128);
+        transport.process(128);
+        assert_eq!(transport.playhead(), SampleTime::new(89));
+        assert!((transport.playhead_fract() - 0.6).abs() < 1.0e-9);
+
+        // Returning to normal speed snaps the playhead to the nearest sample.
+        handle.set_playback_rate(1.0, &mut save_state);
+        transport.process(128);
+        assert_eq!(transport.playhead(), SampleTime::new(179));
+        assert_eq!(transport.playhead_fract(), 0.0);
+        transport.process(128);
+        assert_eq!(transport.playhead(), SampleTime::new(307));
+        assert_eq!(transport.playhead_fract(), 0.0);
+    }
+
+    #[test]
+    fn loop_at_non_unity_playback_rate() {
+        use super::*;
+        use crate::backend::timeline::TimelineTransportSaveState;
+
+        let collector = basedrop::Collector::new();
+        let (mut transport, mut handle) = new_transport(&collector);
+        let mut save_state = TimelineTransportSaveState::default();
+
+        let loop_state = LoopState::Active {
+            loop_start: MusicalTime::new(0.0),
+            loop_end: MusicalTime::new(4.0),
+        };
+        handle.set_loop_state(loop_state, &mut save_state).unwrap();
+        handle.seek_to(MusicalTime::new(95_900.0 / 24_000.0), &mut save_state);
+        handle.set_playback_rate(1.5, &mut save_state);
+        handle.set_playing(true, &mut save_state);
+
+        // The loop end is 100 samples (66.7 frames) away, so the 67th frame overshoots it by
+        // half a sample. That overshoot carries over to the loop start.
+        transport.process(128);
+        let loop_back = transport.do_loop_back().unwrap();
+        assert_eq!(loop_back.first_frames, 67);
+        assert_eq!(loop_back.loop_end, SampleTime::new(96_000));
+
+        let jump = transport.playhead_jump().unwrap();
+        assert_eq!(jump.resume_at, SampleTime::new(0));
+        assert!((jump.resume_fract - 0.5).abs() < 1.0e-9);
+        assert_eq!(jump.playhead_end, SampleTime::new(92));
+        assert!(transport.is_sample_active(SampleTime::new(91)));
+
+        transport.process(128);
+        assert!(transport.do_loop_back().is_none());
+        assert_eq!(transport.playhead(), SampleTime::new(92));
+        assert!(transport.playhead_fract().abs() < 1.0e-9);
+    }
 }
@@ -0,0 +1,228 @@
+// Renders projects without opening a window or an audio device.
+
+use std::path::PathBuf;
+use std::process;
+
+use rusty_daw_core::{MusicalTime, SampleRate};
+
+use meadowlark::backend::dsp::dither::{DitherMode, DEFAULT_DITHER_SEED};
+use meadowlark::backend::export::{
+    ExportFormat, ExportRange, ExportSettings, StemExportSettings, StemTapPoint, WavBitDepth,
+};
+use meadowlark::project::{export_project, export_stems, load_project_file};
+
+static USAGE: &str = "\
+Usage: meadowlark-cli <PROJECT> -o <OUTPUT> [OPTIONS]
+
+Render a project to a WAV or FLAC file.
+
+Options:
+    -o, --output <PATH>       The file to write to (or the directory with --stems)
+    -s, --search <DIR>        Also look for missing media files in this directory
+        --stems               Write every track to its own file
+        --range <RANGE>       `song` (default), `loop`, or `<start>:<end>` in beats
+        --format <FORMAT>     `wav` (default) or `flac`
+        --bit-depth <DEPTH>   `16`, `24` (default), or `32f` (WAV only)
+        --sample-rate <RATE>  The sample rate of the output (default 48000)
+        --dither <MODE>       `none`, `tpdf` (default), or `shaped` (44.1/48 kHz only)
+        --seed <SEED>         The seed of the dither noise
+    -q, --quiet               Don't print progress
+    -h, --help                Print this message";
+
+struct Args {
+    project: PathBuf,
+    search_folders: Vec<PathBuf>,
+    output: PathBuf,
+    stems: bool,
+    range: ExportRange,
+    format: ExportFormat,
+    bit_depth: WavBitDepth,
+    sample_rate: SampleRate,
+    dither: DitherMode,
+    dither_seed: u64,
+    quiet: bool,
+}
+
+fn main() {
+    meadowlark::backend::cpu_id::init();
+
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    simple_logger::SimpleLogger::new().with_level(log::LevelFilter::Warn).init().unwrap();
+
+    let project_save_state = match load_project_file(&args.project, &args.search_folders) {
+        Ok(project_save_state) => project_save_state,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let quiet = args.quiet;
+    let mut last_percent = None;
+    let progress = |p: f32| {
+        let percent = (p * 100.0) as u32;
+        if !quiet && last_percent != Some(percent) {
+            eprint!("\rRendering... {:3}%", percent);
+            last_percent = Some(percent);
+        }
+        true
+    };
+
+    let res = if args.stems {
+        export_stems(
+            &project_save_state,
+            &StemExportSettings {
+                directory: args.output.clone(),
+                range: args.range,
+                format: args.format,
+                bit_depth: args.bit_depth,
+                sample_rate: args.sample_rate,
+                dither: args.dither,
+                dither_seed: args.dither_seed,
+                tap_point: StemTapPoint::PostFader,
+                include_master: false,
+            },
+            progress,
+        )
+    } else {
+        export_project(
+            &project_save_state,
+            &ExportSettings {
+                path: args.output.clone(),
+                range: args.range,
+                format: args.format,
+                bit_depth: args.bit_depth,
+                sample_rate: args.sample_rate,
+                dither: args.dither,
+                dither_seed: args.dither_seed,
+            },
+            progress,
+        )
+    };
+
+    if !quiet {
+        eprintln!();
+    }
+
+    if let Err(e) = res {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+
+    if !quiet {
+        eprintln!("Wrote {:?}", args.output);
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, String> {
+    let mut project = None;
+    let mut search_folders = Vec::new();
+    let mut output = None;
+    let mut stems = false;
+    let mut range = ExportRange::WholeSong;
+    let mut format = ExportFormat::Wav;
+    let mut bit_depth = WavBitDepth::Int24;
+    let mut sample_rate = SampleRate::new(48_000.0);
+    let mut dither = DitherMode::Tpdf;
+    let mut dither_seed = DEFAULT_DITHER_SEED;
+    let mut quiet = false;
+
+    while let Some(arg) = args.next() {
+        let mut value =
+            |name: &str| args.next().ok_or_else(|| format!("missing value for {}", name));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(PathBuf::from(value(&arg)?)),
+            "-s" | "--search" => search_folders.push(PathBuf::from(value(&arg)?)),
+            "--stems" => stems = true,
+            "--range" => range = parse_range(&value(&arg)?)?,
+            "--format" => {
+                format = match value(&arg)?.as_str() {
+                    "wav" => ExportFormat::Wav,
+                    "flac" => ExportFormat::Flac,
+                    v => return Err(format!("invalid format {:?}", v)),
+                }
+            }
+            "--bit-depth" => {
+                bit_depth = match value(&arg)?.as_str() {
+                    "16" => WavBitDepth::Int16,
+                    "24" => WavBitDepth::Int24,
+                    "32f" => WavBitDepth::Float32,
+                    v => return Err(format!("invalid bit depth {:?}", v)),
+                }
+            }
+            "--sample-rate" => {
+                let v = value(&arg)?;
+                match v.parse::<f64>() {
+                    Ok(rate) if (8_000.0..=384_000.0).contains(&rate) => {
+                        sample_rate = SampleRate::new(rate)
+                    }
+                    _ => return Err(format!("invalid sample rate {:?}", v)),
+                }
+            }
+            "--dither" => {
+                dither = match value(&arg)?.as_str() {
+                    "none" => DitherMode::None,
+                    "tpdf" => DitherMode::Tpdf,
+                    "shaped" => DitherMode::TpdfNoiseShaped,
+                    v => return Err(format!("invalid dither mode {:?}", v)),
+                }
+            }
+            "--seed" => {
+                let v = value(&arg)?;
+                dither_seed = v.parse().map_err(|_| format!("invalid seed {:?}", v))?;
+            }
+            "-q" | "--quiet" => quiet = true,
+            a if a.starts_with('-') => return Err(format!("unknown option {:?}", a)),
+            _ => {
+                if project.is_some() {
+                    return Err(format!("unexpected argument {:?}", arg));
+                }
+                project = Some(PathBuf::from(arg));
+            }
+        }
+    }
+
+    Ok(Some(Args {
+        project: project.ok_or_else(|| String::from("missing project"))?,
+        search_folders,
+        output: output.ok_or_else(|| String::from("missing output"))?,
+        stems,
+        range,
+        format,
+        bit_depth,
+        sample_rate,
+        dither,
+        dither_seed,
+        quiet,
+    }))
+}
+
+fn parse_range(range: &str) -> Result<ExportRange, String> {
+    match range {
+        "song" => Ok(ExportRange::WholeSong),
+        "loop" => Ok(ExportRange::LoopRegion),
+        _ => {
+            let mut parts = range.splitn(2, ':').map(|s| s.trim().parse::<f64>());
+            match (parts.next(), parts.next()) {
+                (Some(Ok(start)), Some(Ok(end))) => Ok(ExportRange::Selection {
+                    start: MusicalTime::new(start),
+                    end: MusicalTime::new(end),
+                }),
+                _ => Err(format!("invalid range {:?}", range)),
+            }
+        }
+    }
+}
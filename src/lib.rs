@@ -0,0 +1,12 @@
+//! The code shared by the Meadowlark application and the `meadowlark-cli` renderer.
+//!
+//! The GUI (and the state that drives it) is only built with the `gui` feature, so
+//! `meadowlark-cli` can be built without it.
+
+pub mod backend;
+pub mod project;
+#[cfg(feature = "gui")]
+pub mod state;
+#[cfg(feature = "gui")]
+pub mod ui;
+pub mod util;
@@ -1,7 +1,4 @@
-mod backend;
-mod state;
-mod ui;
-mod util;
+use meadowlark::{backend, ui};
 
 fn main() {
     backend::cpu_id::init();
@@ -11,5 +8,8 @@ fn main() {
     // TODO: Use something more sophisticated
     simple_logger::SimpleLogger::new().init().unwrap();
 
-    ui::run();
+    // The project to open can be given as the first argument.
+    let project_path = std::env::args_os().nth(1).map(std::path::PathBuf::from);
+
+    ui::run(project_path);
 }
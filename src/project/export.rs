@@ -0,0 +1,214 @@
+use rusty_daw_core::{MusicalTime, SampleRate};
+
+use crate::backend::export::{
+    stem_file_names, write_audio_file, ExportError, ExportSettings, StemExportSettings,
+};
+use crate::backend::timeline::TimelineTrackSaveState;
+use crate::backend::{OfflineRenderer, RenderedAudio};
+
+use super::{add_timeline_track_nodes, ProjectSaveState};
+
+/// The state of an export (or another long job, such as collecting a project) running
+/// on a separate thread.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportStatus {
+    /// The export is running. This holds the fraction that is done so far (between
+    /// `0.0` and `1.0`).
+    Running(f32),
+    /// The export finished successfully.
+    Finished,
+    /// The export failed with the given error.
+    Failed(String),
+}
+
+/// Render the master output of the project and write it to a file.
+///
+/// This builds a new graph from the save state, so it can be called from any thread
+/// while the project is playing. `progress` is called with the fraction of the range
+/// that has been rendered so far. Return `false` from it to cancel the export.
+pub fn export_project<P: FnMut(f32) -> bool>(
+    project_save_state: &ProjectSaveState,
+    settings: &ExportSettings,
+    progress: P,
+) -> Result<(), ExportError> {
+    let (start, end) = settings.range.resolve(
+        &project_save_state.backend.timeline_transport.loop_state,
+        &project_save_state.timeline_tracks,
+        &project_save_state.backend.tempo_map,
+    )?;
+
+    let audio = render_tracks(
+        project_save_state,
+        &project_save_state.timeline_tracks,
+        settings.sample_rate,
+        start,
+        end,
+        progress,
+    )?;
+
+    write_audio_file(
+        &settings.path,
+        &audio,
+        settings.format,
+        settings.bit_depth,
+        settings.dither,
+        settings.dither_seed,
+    )
+}
+
+/// Render every timeline track of the project on its own and write each one to a
+/// separate file. All files cover the same range.
+///
+/// Since tracks have no faders and the master output has no processing yet, the tap
+/// point and `include_master` settings don't change the output.
+///
+/// `progress` is called with the fraction of the whole export that is done so far.
+/// Return `false` from it to cancel the export.
+pub fn export_stems<P: FnMut(f32) -> bool>(
+    project_save_state: &ProjectSaveState,
+    settings: &StemExportSettings,
+    mut progress: P,
+) -> Result<(), ExportError> {
+    let (start, end) = settings.range.resolve(
+        &project_save_state.backend.timeline_transport.loop_state,
+        &project_save_state.timeline_tracks,
+        &project_save_state.backend.tempo_map,
+    )?;
+
+    std::fs::create_dir_all(&settings.directory)
+        .map_err(|e| ExportError::CouldNotCreateDirectory((settings.directory.clone(), e)))?;
+
+    let file_names = stem_file_names(&project_save_state.timeline_tracks, settings.format);
+    let n_tracks = project_save_state.timeline_tracks.len();
+
+    for (i, (track, file_name)) in
+        project_save_state.timeline_tracks.iter().zip(file_names.iter()).enumerate()
+    {
+        let audio = render_tracks(
+            project_save_state,
+            std::slice::from_ref(track),
+            settings.sample_rate,
+            start,
+            end,
+            |p| progress((i as f32 + p) / n_tracks as f32),
+        )?;
+
+        write_audio_file(
+            &settings.directory.join(file_name),
+            &audio,
+            settings.format,
+            settings.bit_depth,
+            settings.dither,
+            settings.dither_seed,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Render the given timeline tracks mixed together over the range `[start, end)`.
+fn render_tracks<P: FnMut(f32) -> bool>(
+    project_save_state: &ProjectSaveState,
+    timeline_tracks: &[TimelineTrackSaveState],
+    sample_rate: SampleRate,
+    start: MusicalTime,
+    end: MusicalTime,
+    progress: P,
+) -> Result<RenderedAudio, ExportError> {
+    let mut save_state = project_save_state.backend.clone_with_sample_rate(sample_rate);
+
+    let mut renderer = OfflineRenderer::from_save_state(sample_rate, &mut save_state);
+
+    let mut resource_load_errors = Vec::new();
+    let tempo_map = save_state.tempo_map.clone();
+    let mut connected = true;
+    let res = renderer.backend_handle_mut().modify_graph(|mut graph, resource_cache| {
+        match add_timeline_track_nodes(
+            &mut graph,
+            resource_cache,
+            timeline_tracks,
+            &tempo_map,
+            sample_rate,
+        ) {
+            Ok((_, mut res)) => resource_load_errors.append(&mut res),
+            Err(()) => connected = false,
+        }
+    });
+
+    match res {
+        Ok(Some(warning)) => log::warn!("Audio graph compiled with a warning: {:?}", warning),
+        Ok(None) => {}
+        Err(e) => return Err(ExportError::Graph(format!("{:?}", e))),
+    }
+    if !connected {
+        return Err(ExportError::Graph(String::from("could not connect a track to the output")));
+    }
+
+    // Exporting a project with missing audio would silently leave out those clips.
+    if !resource_load_errors.is_empty() {
+        return Err(resource_load_errors.into());
+    }
+
+    Ok(renderer.render(start, end, &mut save_state, progress)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_daw_core::Seconds;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::backend::timeline::{
+        AudioClipFades, AudioClipId, AudioClipSaveState, TimelineTrackId,
+    };
+
+    #[test]
+    fn render_matches_golden_file() {
+        let mut project = ProjectSaveState::new_empty();
+        project.timeline_tracks.push(TimelineTrackSaveState {
+            id: TimelineTrackId::new(),
+            name: String::from("Keys"),
+            audio_clips: vec![AudioClipSaveState {
+                id: AudioClipId::new(),
+                name: String::from("Keys"),
+                pcm_path: PathBuf::from(
+                    "./assets/test_files/synth_keys/synth_keys_48000_float32.wav",
+                ),
+                timeline_start: MusicalTime::new(0.0),
+                duration: Seconds(1.0),
+                clip_start_offset: Seconds(0.0),
+                clip_gain_db: 0.0,
+                fades: AudioClipFades::no_fade(),
+            }],
+            groove: None,
+        });
+
+        let end = project.backend.tempo_map.seconds_to_musical(Seconds(0.1));
+        let audio = render_tracks(
+            &project,
+            &project.timeline_tracks,
+            SampleRate::new(48_000.0),
+            MusicalTime::new(0.0),
+            end,
+            |_| true,
+        )
+        .unwrap();
+
+        // The golden file holds the expected output as interleaved little-endian `f32`s.
+        // Since the clip has no fades and no gain, this is the start of the audio file
+        // itself. In particular, the first few milliseconds must not be faded in.
+        let golden: Vec<f32> =
+            std::fs::read("./assets/test_files/golden/synth_keys_render_4800.f32")
+                .unwrap()
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+
+        assert_eq!(audio.len(), 4800);
+        assert_eq!(golden.len(), 4800 * 2);
+        for (i, frame) in golden.chunks_exact(2).enumerate() {
+            assert!((audio.left[i] - frame[0]).abs() < 1.0e-6, "left differs at frame {}", i);
+            assert!((audio.right[i] - frame[1]).abs() < 1.0e-6, "right differs at frame {}", i);
+        }
+    }
+}
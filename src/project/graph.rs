@@ -0,0 +1,57 @@
+use rusty_daw_audio_graph::{GraphStateRef, NodeRef, PortType};
+use rusty_daw_core::SampleRate;
+
+use crate::backend::timeline::{
+    TempoMap, TimelineTrackHandle, TimelineTrackNode, TimelineTrackSaveState,
+};
+use crate::backend::{GlobalNodeData, ResourceCache, ResourceLoadError, MAX_BLOCKSIZE};
+
+/// Add a node for every timeline track to the graph, and connect them to the root node.
+///
+/// Returns a reference and a handle to every new node, along with any errors that
+/// happened while loading resources. If a node could not be connected, the graph is left
+/// half-modified and should not be used.
+pub(crate) fn add_timeline_track_nodes(
+    graph: &mut GraphStateRef<'_, GlobalNodeData, MAX_BLOCKSIZE>,
+    resource_cache: &ResourceCache,
+    timeline_tracks: &[TimelineTrackSaveState],
+    tempo_map: &TempoMap,
+    sample_rate: SampleRate,
+) -> Result<(Vec<(NodeRef, TimelineTrackHandle)>, Vec<ResourceLoadError>), ()> {
+    let mut nodes = Vec::with_capacity(timeline_tracks.len());
+    let mut resource_load_errors = Vec::new();
+
+    for timeline_track_save_state in timeline_tracks.iter() {
+        let (timeline_track_node, timeline_track_handle, mut res) = TimelineTrackNode::new(
+            timeline_track_save_state,
+            resource_cache,
+            tempo_map,
+            sample_rate,
+            graph.coll_handle(),
+        );
+
+        // Append any errors that happened while loading resources.
+        resource_load_errors.append(&mut res);
+
+        // Add the track node to the graph.
+        let timeline_track_node_ref = graph.add_new_node(Box::new(timeline_track_node));
+
+        nodes.push((timeline_track_node_ref, timeline_track_handle));
+
+        // Connect the track node to the root node.
+        connect_to_root(graph, timeline_track_node_ref)?;
+    }
+
+    Ok((nodes, resource_load_errors))
+}
+
+/// Connect the stereo output of the node to the root node.
+pub(crate) fn connect_to_root(
+    graph: &mut GraphStateRef<'_, GlobalNodeData, MAX_BLOCKSIZE>,
+    node_ref: NodeRef,
+) -> Result<(), ()> {
+    let root_node_ref = graph.root_node();
+    graph.connect_ports(PortType::StereoAudio, node_ref, 0, root_node_ref, 0).map_err(|e| {
+        log::error!("Failed to connect node to the root node: {:?}", e);
+    })
+}
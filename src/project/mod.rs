@@ -0,0 +1,17 @@
+//! The project and its file format.
+//!
+//! Nothing in here depends on the GUI, so this is also used by `meadowlark-cli`.
+
+mod export;
+mod graph;
+mod project_file;
+mod project_save_state;
+
+pub use export::{export_project, export_stems, ExportStatus};
+pub use project_file::{
+    load_project_file, save_project_file, ProjectFileError, PROJECT_FILE_VERSION,
+};
+pub use project_save_state::ProjectSaveState;
+
+pub(crate) use graph::{add_timeline_track_nodes, connect_to_root};
+pub(crate) use project_file::{project_dir, project_file_json};
@@ -0,0 +1,429 @@
+//! The current version of the project file format.
+//!
+//! These types only describe what is written to disk. They are kept separate from the
+//! save states so the save states can change without breaking older project files.
+
+use rusty_daw_core::{MusicalTime, SampleRate, Seconds};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+use crate::backend::timeline::{
+    AudioClipFades, AudioClipId, AudioClipSaveState, CountInMode, Groove, GrooveTemplate,
+    LoopState, Marker, MetronomeSaveState, PunchState, StopBehavior, SwingDivision, TempoMap,
+    TempoPoint, TempoRamp, TimeSignature, TimeSignatureMap, TimeSignaturePoint, TimelineTrackId,
+    TimelineTrackSaveState, TimelineTransportSaveState,
+};
+use crate::backend::BackendSaveState;
+
+use super::super::ProjectSaveState;
+use super::media_path::MediaPathFile;
+use super::PROJECT_FILE_VERSION;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct ProjectFile {
+    pub version: u32,
+
+    pub transport: TransportFile,
+    pub tempo_points: Vec<TempoPointFile>,
+    pub groove: GrooveFile,
+    pub time_signature_points: Vec<TimeSignaturePointFile>,
+    pub metronome: MetronomeFile,
+    /// In seconds.
+    pub audio_clip_declick_time: f64,
+
+    pub timeline_tracks: Vec<TimelineTrackFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct TransportFile {
+    pub seek_to: f64,
+    pub loop_state: RangeFile,
+    pub punch_state: RangeFile,
+    pub count_in_mode: CountInModeFile,
+    pub playback_rate: f64,
+    pub stop_behavior: StopBehaviorFile,
+    pub markers: Vec<MarkerFile>,
+}
+
+/// A loop or punch range. Both positions are in beats.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum RangeFile {
+    Inactive,
+    Active { start: f64, end: f64 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum CountInModeFile {
+    Off,
+    CountIn { bars: u32 },
+    PreRoll { bars: u32 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum StopBehaviorFile {
+    ReturnToPlayStart,
+    ReturnToZero,
+    Stay,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct MarkerFile {
+    pub name: String,
+    pub position: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct TempoPointFile {
+    pub position: f64,
+    pub bpm: f64,
+    pub ramp: TempoRampFile,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum TempoRampFile {
+    Step,
+    Linear,
+    Curved,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum GrooveFile {
+    None,
+    Swing { division: SwingDivisionFile, percent: f64 },
+    Template { name: String, len: f64, points: Vec<(f64, f64)> },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum SwingDivisionFile {
+    Eighth,
+    Sixteenth,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct TimeSignaturePointFile {
+    pub position: f64,
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct MetronomeFile {
+    pub enabled: bool,
+    pub gain_db: f32,
+    pub click_path: Option<MediaPathFile>,
+    pub downbeat_click_path: Option<MediaPathFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct TimelineTrackFile {
+    pub id: u64,
+    pub name: String,
+    pub audio_clips: Vec<AudioClipFile>,
+    pub groove: Option<GrooveFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct AudioClipFile {
+    pub id: u64,
+    pub name: String,
+    pub pcm_path: MediaPathFile,
+    /// In beats.
+    pub timeline_start: f64,
+    /// In seconds.
+    pub duration: f64,
+    /// In seconds.
+    pub clip_start_offset: f64,
+    pub clip_gain_db: f32,
+    /// In seconds.
+    pub start_fade_duration: f64,
+    /// In seconds.
+    pub end_fade_duration: f64,
+}
+
+impl ProjectFile {
+    /// Create the file contents from a save state.
+    ///
+    /// * `project_dir` - The absolute path of the folder the file will be saved in.
+    pub fn from_save_state(save_state: &ProjectSaveState, project_dir: &Path) -> Self {
+        let media_path = |path: &PathBuf| MediaPathFile::new(path, project_dir);
+
+        let backend = &save_state.backend;
+        let transport = &backend.timeline_transport;
+
+        Self {
+            version: PROJECT_FILE_VERSION,
+
+            transport: TransportFile {
+                seek_to: transport.seek_to.0,
+                loop_state: match transport.loop_state {
+                    LoopState::Inactive => RangeFile::Inactive,
+                    LoopState::Active { loop_start, loop_end } => {
+                        RangeFile::Active { start: loop_start.0, end: loop_end.0 }
+                    }
+                },
+                punch_state: match transport.punch_state {
+                    PunchState::Inactive => RangeFile::Inactive,
+                    PunchState::Active { punch_in, punch_out } => {
+                        RangeFile::Active { start: punch_in.0, end: punch_out.0 }
+                    }
+                },
+                count_in_mode: match transport.count_in_mode {
+                    CountInMode::Off => CountInModeFile::Off,
+                    CountInMode::CountIn { bars } => CountInModeFile::CountIn { bars },
+                    CountInMode::PreRoll { bars } => CountInModeFile::PreRoll { bars },
+                },
+                playback_rate: transport.playback_rate,
+                stop_behavior: match transport.stop_behavior {
+                    StopBehavior::ReturnToPlayStart => StopBehaviorFile::ReturnToPlayStart,
+                    StopBehavior::ReturnToZero => StopBehaviorFile::ReturnToZero,
+                    StopBehavior::Stay => StopBehaviorFile::Stay,
+                },
+                markers: transport
+                    .markers
+                    .iter()
+                    .map(|m| MarkerFile { name: m.name.clone(), position: m.position.0 })
+                    .collect(),
+            },
+            tempo_points: backend
+                .tempo_map
+                .points()
+                .iter()
+                .map(|p| TempoPointFile {
+                    position: p.position.0,
+                    bpm: p.bpm,
+                    ramp: match p.ramp {
+                        TempoRamp::Step => TempoRampFile::Step,
+                        TempoRamp::Linear => TempoRampFile::Linear,
+                        TempoRamp::Curved => TempoRampFile::Curved,
+                    },
+                })
+                .collect(),
+            groove: GrooveFile::from_groove(backend.tempo_map.groove()),
+            time_signature_points: backend
+                .time_signature_map
+                .points()
+                .iter()
+                .map(|p| TimeSignaturePointFile {
+                    position: p.position.0,
+                    numerator: p.time_signature.numerator,
+                    denominator: p.time_signature.denominator,
+                })
+                .collect(),
+            metronome: MetronomeFile {
+                enabled: backend.metronome.enabled,
+                gain_db: backend.metronome.gain_db,
+                click_path: backend.metronome.click_path.as_ref().map(media_path),
+                downbeat_click_path: backend.metronome.downbeat_click_path.as_ref().map(media_path),
+            },
+            audio_clip_declick_time: backend.audio_clip_declick_time.0,
+
+            timeline_tracks: save_state
+                .timeline_tracks
+                .iter()
+                .map(|track| TimelineTrackFile {
+                    id: track.id.raw(),
+                    name: track.name.clone(),
+                    audio_clips: track
+                        .audio_clips
+                        .iter()
+                        .map(|clip| AudioClipFile {
+                            id: clip.id.raw(),
+                            name: clip.name.clone(),
+                            pcm_path: media_path(&clip.pcm_path),
+                            timeline_start: clip.timeline_start.0,
+                            duration: clip.duration.0,
+                            clip_start_offset: clip.clip_start_offset.0,
+                            clip_gain_db: clip.clip_gain_db,
+                            start_fade_duration: clip.fades.start_fade_duration.0,
+                            end_fade_duration: clip.fades.end_fade_duration.0,
+                        })
+                        .collect(),
+                    groove: track.groove.as_ref().map(GrooveFile::from_groove),
+                })
+                .collect(),
+        }
+    }
+
+    /// Convert this file into a save state.
+    ///
+    /// This will return an error describing the problem if any of the data is invalid.
+    ///
+    /// * `project_dir` - The folder the file was loaded from.
+    /// * `search_folders` - Additional folders to look for media files in.
+    pub fn into_save_state(
+        self,
+        project_dir: &Path,
+        search_folders: &[PathBuf],
+    ) -> Result<ProjectSaveState, String> {
+        let media_path = |path: MediaPathFile| path.resolve(project_dir, search_folders);
+
+        let transport = self.transport;
+
+        let timeline_transport = TimelineTransportSaveState {
+            seek_to: MusicalTime(transport.seek_to),
+            loop_state: match transport.loop_state {
+                RangeFile::Inactive => LoopState::Inactive,
+                RangeFile::Active { start, end } => {
+                    LoopState::Active { loop_start: MusicalTime(start), loop_end: MusicalTime(end) }
+                }
+            },
+            punch_state: match transport.punch_state {
+                RangeFile::Inactive => PunchState::Inactive,
+                RangeFile::Active { start, end } => {
+                    PunchState::Active { punch_in: MusicalTime(start), punch_out: MusicalTime(end) }
+                }
+            },
+            count_in_mode: match transport.count_in_mode {
+                CountInModeFile::Off => CountInMode::Off,
+                CountInModeFile::CountIn { bars } => CountInMode::CountIn { bars },
+                CountInModeFile::PreRoll { bars } => CountInMode::PreRoll { bars },
+            },
+            playback_rate: transport.playback_rate,
+            stop_behavior: match transport.stop_behavior {
+                StopBehaviorFile::ReturnToPlayStart => StopBehavior::ReturnToPlayStart,
+                StopBehaviorFile::ReturnToZero => StopBehavior::ReturnToZero,
+                StopBehaviorFile::Stay => StopBehavior::Stay,
+            },
+            markers: {
+                let mut markers: Vec<Marker> = transport
+                    .markers
+                    .into_iter()
+                    .map(|m| Marker { name: m.name, position: MusicalTime(m.position) })
+                    .collect();
+                markers.sort_by(|a, b| {
+                    a.position.0.partial_cmp(&b.position.0).unwrap_or(Ordering::Equal)
+                });
+                markers
+            },
+        };
+
+        let tempo_points = self
+            .tempo_points
+            .into_iter()
+            .map(|p| TempoPoint {
+                position: MusicalTime(p.position),
+                bpm: p.bpm,
+                ramp: match p.ramp {
+                    TempoRampFile::Step => TempoRamp::Step,
+                    TempoRampFile::Linear => TempoRamp::Linear,
+                    TempoRampFile::Curved => TempoRamp::Curved,
+                },
+            })
+            .collect();
+        // The sample rate is set once the project is loaded into the backend.
+        let mut tempo_map = TempoMap::from_points(tempo_points, SampleRate::default())
+            .map_err(|_| String::from("invalid tempo map"))?;
+        tempo_map.set_groove(self.groove.into_groove()?);
+
+        let mut time_signature_map = TimeSignatureMap::default();
+        let time_signature_points = self
+            .time_signature_points
+            .into_iter()
+            .map(|p| {
+                Ok(TimeSignaturePoint {
+                    position: MusicalTime(p.position),
+                    time_signature: TimeSignature::new(p.numerator, p.denominator).map_err(
+                        |_| format!("invalid time signature {}/{}", p.numerator, p.denominator),
+                    )?,
+                })
+            })
+            .collect::<Result<Vec<TimeSignaturePoint>, String>>()?;
+        time_signature_map
+            .set_points(time_signature_points)
+            .map_err(|_| String::from("invalid time signature map"))?;
+
+        let mut backend = BackendSaveState::new(timeline_transport, tempo_map, time_signature_map);
+        backend.metronome = MetronomeSaveState {
+            enabled: self.metronome.enabled,
+            gain_db: self.metronome.gain_db,
+            click_path: self.metronome.click_path.map(media_path).transpose()?,
+            downbeat_click_path: self.metronome.downbeat_click_path.map(media_path).transpose()?,
+        };
+        backend.audio_clip_declick_time = Seconds(self.audio_clip_declick_time);
+
+        // Tracks and clips are looked up by their IDs, so they must be unique.
+        let mut ids: Vec<u64> = Vec::new();
+        for track in self.timeline_tracks.iter() {
+            ids.push(track.id);
+            ids.extend(track.audio_clips.iter().map(|clip| clip.id));
+        }
+        ids.sort_unstable();
+        if let Some(id) = ids.windows(2).find(|w| w[0] == w[1]).map(|w| w[0]) {
+            return Err(format!("duplicate track or clip id {}", id));
+        }
+
+        let timeline_tracks = self
+            .timeline_tracks
+            .into_iter()
+            .map(|track| {
+                Ok(TimelineTrackSaveState {
+                    id: TimelineTrackId::from_raw(track.id),
+                    name: track.name,
+                    audio_clips: track
+                        .audio_clips
+                        .into_iter()
+                        .map(|clip| {
+                            Ok(AudioClipSaveState {
+                                id: AudioClipId::from_raw(clip.id),
+                                name: clip.name,
+                                pcm_path: media_path(clip.pcm_path)?,
+                                timeline_start: MusicalTime(clip.timeline_start),
+                                duration: Seconds(clip.duration),
+                                clip_start_offset: Seconds(clip.clip_start_offset),
+                                clip_gain_db: clip.clip_gain_db,
+                                fades: AudioClipFades {
+                                    start_fade_duration: Seconds(clip.start_fade_duration),
+                                    end_fade_duration: Seconds(clip.end_fade_duration),
+                                },
+                            })
+                        })
+                        .collect::<Result<Vec<AudioClipSaveState>, String>>()?,
+                    groove: track.groove.map(GrooveFile::into_groove).transpose()?,
+                })
+            })
+            .collect::<Result<Vec<TimelineTrackSaveState>, String>>()?;
+
+        Ok(ProjectSaveState { backend, timeline_tracks })
+    }
+}
+
+impl GrooveFile {
+    fn from_groove(groove: &Groove) -> Self {
+        match groove {
+            Groove::None => GrooveFile::None,
+            Groove::Swing { division, percent } => GrooveFile::Swing {
+                division: match division {
+                    SwingDivision::Eighth => SwingDivisionFile::Eighth,
+                    SwingDivision::Sixteenth => SwingDivisionFile::Sixteenth,
+                },
+                percent: *percent,
+            },
+            Groove::Template(template) => GrooveFile::Template {
+                name: template.name.clone(),
+                len: template.len().0,
+                points: template.points().map(|(s, g)| (s.0, g.0)).collect(),
+            },
+        }
+    }
+
+    fn into_groove(self) -> Result<Groove, String> {
+        Ok(match self {
+            GrooveFile::None => Groove::None,
+            GrooveFile::Swing { division, percent } => Groove::swing(
+                match division {
+                    SwingDivisionFile::Eighth => SwingDivision::Eighth,
+                    SwingDivisionFile::Sixteenth => SwingDivision::Sixteenth,
+                },
+                percent,
+            ),
+            GrooveFile::Template { name, len, points } => {
+                let points =
+                    points.into_iter().map(|(s, g)| (MusicalTime(s), MusicalTime(g))).collect();
+                let template = GrooveTemplate::new(name, MusicalTime(len), points)
+                    .map_err(|_| String::from("invalid groove template"))?;
+                Groove::Template(template)
+            }
+        })
+    }
+}
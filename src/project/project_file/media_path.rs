@@ -0,0 +1,198 @@
+//! How paths to media files (such as audio clips) are stored in a project file.
+//!
+//! Paths are stored relative to the folder containing the project file where possible,
+//! so a project folder can be moved to another location or machine. The absolute path
+//! at the time of saving is stored as well and used as a fallback.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct MediaPathFile {
+    /// The path relative to the folder of the project file, always using `/` as the
+    /// separator.
+    pub relative: Option<String>,
+    /// The absolute path at the time the project was saved.
+    pub absolute: Option<PathBuf>,
+}
+
+impl MediaPathFile {
+    pub fn new(path: &Path, project_dir: &Path) -> Self {
+        let absolute = absolute_path(path);
+
+        let relative = relative_path(&absolute, project_dir).and_then(|p| to_portable(&p));
+
+        Self { relative, absolute: Some(absolute) }
+    }
+
+    /// Find the file this path points to.
+    ///
+    /// This tries (in order) the relative path inside the project folder, the absolute
+    /// path, and then the relative path and the file name inside each of the
+    /// `search_folders`. If the file cannot be found, the path inside the project folder
+    /// is returned (or the absolute path if there is no relative path).
+    pub fn resolve(
+        self,
+        project_dir: &Path,
+        search_folders: &[PathBuf],
+    ) -> Result<PathBuf, String> {
+        let relative = self.relative.as_ref().map(|p| from_portable(p));
+
+        let mut candidates: Vec<PathBuf> = Vec::new();
+        if let Some(relative) = &relative {
+            candidates.push(project_dir.join(relative));
+        }
+        if let Some(absolute) = &self.absolute {
+            candidates.push(absolute.clone());
+        }
+        for folder in search_folders.iter() {
+            if let Some(relative) = &relative {
+                candidates.push(folder.join(relative));
+            }
+            let file_name =
+                relative.as_ref().or(self.absolute.as_ref()).and_then(|p| p.file_name());
+            if let Some(file_name) = file_name {
+                candidates.push(folder.join(file_name));
+            }
+        }
+
+        if candidates.is_empty() {
+            return Err(String::from("media file has no path"));
+        }
+
+        let found = candidates.iter().position(|p| p.is_file()).unwrap_or(0);
+
+        Ok(normalize(&candidates[found]))
+    }
+}
+
+/// Make the path absolute by joining it to the current working directory if needed.
+pub(super) fn absolute_path(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        normalize(path)
+    } else {
+        match std::env::current_dir() {
+            Ok(current_dir) => normalize(&current_dir.join(path)),
+            Err(_) => normalize(path),
+        }
+    }
+}
+
+/// Remove all `.` components and resolve `..` components without touching the
+/// filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                let can_pop =
+                    matches!(normalized.components().next_back(), Some(Component::Normal(_)));
+                if can_pop {
+                    normalized.pop();
+                } else if !normalized.has_root() {
+                    normalized.push("..");
+                }
+            }
+            c => normalized.push(c.as_os_str()),
+        }
+    }
+    normalized
+}
+
+/// Get the path of `path` relative to the folder `base`. Both paths must be absolute.
+///
+/// Returns `None` if there is no relative path between them (i.e. they are on
+/// different drives on Windows).
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let path = normalize(path);
+    let base = normalize(base);
+
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+
+    // The prefix (drive) and root must be the same.
+    match (path_components.peek(), base_components.peek()) {
+        (Some(Component::Prefix(a)), Some(Component::Prefix(b))) if a != b => return None,
+        (Some(Component::Prefix(_)), Some(Component::Prefix(_))) => {}
+        (Some(Component::Prefix(_)), _) | (_, Some(Component::Prefix(_))) => return None,
+        _ => {}
+    }
+
+    while let (Some(a), Some(b)) = (path_components.peek(), base_components.peek()) {
+        if a != b {
+            break;
+        }
+        path_components.next();
+        base_components.next();
+    }
+
+    let mut relative = PathBuf::new();
+    for component in base_components {
+        match component {
+            Component::Normal(_) => relative.push(".."),
+            _ => return None,
+        }
+    }
+    for component in path_components {
+        match component {
+            Component::Normal(c) => relative.push(c),
+            _ => return None,
+        }
+    }
+
+    Some(relative)
+}
+
+/// Convert a relative path to a string that uses `/` as the separator on every
+/// platform. Returns `None` if the path is not valid unicode.
+fn to_portable(path: &Path) -> Option<String> {
+    let parts = path
+        .components()
+        .map(|c| match c {
+            Component::ParentDir => Some(".."),
+            Component::Normal(c) => c.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<&str>>>()?;
+
+    Some(parts.join("/"))
+}
+
+fn from_portable(path: &str) -> PathBuf {
+    path.split('/').filter(|p| !p.is_empty() && *p != ".").collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn media_paths() {
+        assert_eq!(
+            relative_path(Path::new("/a/b/audio/c.wav"), Path::new("/a/b")),
+            Some(PathBuf::from("audio/c.wav"))
+        );
+        assert_eq!(
+            relative_path(Path::new("/a/samples/c.wav"), Path::new("/a/b/./project")),
+            Some(PathBuf::from("../../samples/c.wav"))
+        );
+
+        assert_eq!(normalize(Path::new("/a/./b/../c.wav")), PathBuf::from("/a/c.wav"));
+        assert_eq!(normalize(Path::new("../a/b/../../..")), PathBuf::from("../.."));
+
+        assert_eq!(to_portable(Path::new("../audio/c.wav")), Some(String::from("../audio/c.wav")));
+        assert_eq!(from_portable("./audio//c.wav"), PathBuf::from("audio/c.wav"));
+
+        let media_path = MediaPathFile::new(Path::new("/a/b/audio/c.wav"), Path::new("/a/b"));
+        assert_eq!(media_path.relative.as_deref(), Some("audio/c.wav"));
+        assert_eq!(media_path.absolute.as_deref(), Some(Path::new("/a/b/audio/c.wav")));
+
+        // Nothing exists, so the path inside the (moved) project folder is used.
+        assert_eq!(
+            media_path.resolve(Path::new("/x/y"), &[PathBuf::from("/z")]),
+            Ok(PathBuf::from("/x/y/audio/c.wav"))
+        );
+    }
+}
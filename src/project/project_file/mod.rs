@@ -0,0 +1,349 @@
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::ProjectSaveState;
+
+mod format;
+mod media_path;
+
+use format::ProjectFile;
+
+/// The version of the project file format written by this version of Meadowlark.
+///
+/// This must be increased (and a migration added to `MIGRATIONS`) every time the format
+/// changes in a way older versions cannot read.
+pub static PROJECT_FILE_VERSION: u32 = 3;
+
+/// The migrations between consecutive versions of the format, where `MIGRATIONS[i]`
+/// converts a file from version `i + 1` to version `i + 2`.
+///
+/// Migrations act on the raw JSON, so the types in `format` only ever have to describe
+/// the current version.
+static MIGRATIONS: &[fn(&mut Value) -> Result<(), String>] =
+    &[migrate_v1_media_paths, migrate_v2_ids];
+
+#[derive(Debug)]
+pub enum ProjectFileError {
+    CouldNotRead((PathBuf, std::io::Error)),
+    CouldNotWrite((PathBuf, std::io::Error)),
+    /// The project could not be written as JSON (i.e. a media path is not valid UTF-8).
+    CouldNotSerialize((PathBuf, serde_json::Error)),
+    InvalidJson((PathBuf, serde_json::Error)),
+    MissingVersion(PathBuf),
+    /// The file was saved by a newer version of Meadowlark.
+    NewerVersion((PathBuf, u64)),
+    UnsupportedVersion((PathBuf, u64)),
+    MigrationFailed((PathBuf, u64, String)),
+    InvalidData((PathBuf, String)),
+}
+
+impl Error for ProjectFileError {}
+
+impl fmt::Display for ProjectFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ProjectFileError::*;
+
+        match self {
+            CouldNotRead((path, e)) => {
+                write!(f, "Failed to load project: could not read file | {} | path: {:?}", e, path)
+            }
+            CouldNotWrite((path, e)) => {
+                write!(f, "Failed to save project: could not write file | {} | path: {:?}", e, path)
+            }
+            CouldNotSerialize((path, e)) => {
+                write!(f, "Failed to save project: could not write JSON | {} | path: {:?}", e, path)
+            }
+            InvalidJson((path, e)) => {
+                write!(f, "Failed to load project: invalid file | {} | path: {:?}", e, path)
+            }
+            MissingVersion(path) => {
+                write!(f, "Failed to load project: no version found | path: {:?}", path)
+            }
+            NewerVersion((path, version)) => write!(
+                f,
+                "Failed to load project: saved by a newer version | version: {} | path: {:?}",
+                version, path
+            ),
+            UnsupportedVersion((path, version)) => write!(
+                f,
+                "Failed to load project: unsupported version {} | path: {:?}",
+                version, path
+            ),
+            MigrationFailed((path, version, e)) => write!(
+                f,
+                "Failed to load project: could not upgrade from version {} | {} | path: {:?}",
+                version, e, path
+            ),
+            InvalidData((path, e)) => {
+                write!(f, "Failed to load project: {} | path: {:?}", e, path)
+            }
+        }
+    }
+}
+
+/// Load a project from a file, upgrading it from an older version of the format if
+/// needed.
+///
+/// Media files are looked for relative to the project file first, then at the absolute
+/// path they were saved with, and then inside each of the `search_folders`.
+pub fn load_project_file(
+    path: &Path,
+    search_folders: &[PathBuf],
+) -> Result<ProjectSaveState, ProjectFileError> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| ProjectFileError::CouldNotRead((path.to_path_buf(), e)))?;
+
+    let value: Value = serde_json::from_str(&text)
+        .map_err(|e| ProjectFileError::InvalidJson((path.to_path_buf(), e)))?;
+
+    let value = migrate(value, path)?;
+
+    let project_file: ProjectFile = serde_json::from_value(value)
+        .map_err(|e| ProjectFileError::InvalidJson((path.to_path_buf(), e)))?;
+
+    project_file
+        .into_save_state(&project_dir(path), search_folders)
+        .map_err(|e| ProjectFileError::InvalidData((path.to_path_buf(), e)))
+}
+
+/// Save the project to a file using the current version of the format.
+///
+/// The project is first written to a temporary file next to `path`, so an existing
+/// file is never left half-written.
+pub fn save_project_file(
+    path: &Path,
+    save_state: &ProjectSaveState,
+) -> Result<(), ProjectFileError> {
+    let text = project_file_json(save_state, &project_dir(path))
+        .map_err(|e| ProjectFileError::CouldNotSerialize((path.to_path_buf(), e)))?;
+
+    let mut temp_path = path.as_os_str().to_os_string();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    fs::write(&temp_path, text)
+        .and_then(|_| fs::rename(&temp_path, path))
+        .map_err(|e| ProjectFileError::CouldNotWrite((path.to_path_buf(), e)))
+}
+
+/// Write the project in the current version of the format.
+///
+/// * `project_dir` - The absolute path of the folder the file will be saved in.
+pub(crate) fn project_file_json(
+    save_state: &ProjectSaveState,
+    project_dir: &Path,
+) -> Result<String, serde_json::Error> {
+    let project_file = ProjectFile::from_save_state(save_state, project_dir);
+
+    serde_json::to_string_pretty(&project_file)
+}
+
+/// The absolute path of the folder containing the project file.
+pub(crate) fn project_dir(path: &Path) -> PathBuf {
+    media_path::absolute_path(path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// Upgrade a project file to the current version of the format.
+fn migrate(mut value: Value, path: &Path) -> Result<Value, ProjectFileError> {
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| ProjectFileError::MissingVersion(path.to_path_buf()))?;
+
+    if version > u64::from(PROJECT_FILE_VERSION) {
+        return Err(ProjectFileError::NewerVersion((path.to_path_buf(), version)));
+    }
+    if version == 0 {
+        return Err(ProjectFileError::UnsupportedVersion((path.to_path_buf(), version)));
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        (migration)(&mut value).map_err(|e| {
+            ProjectFileError::MigrationFailed((path.to_path_buf(), i as u64 + 1, e))
+        })?;
+    }
+
+    value["version"] = Value::from(PROJECT_FILE_VERSION);
+
+    Ok(value)
+}
+
+/// Version 1 stored media paths as plain strings, which were relative to the working
+/// directory if not absolute.
+fn migrate_v1_media_paths(value: &mut Value) -> Result<(), String> {
+    let convert = |path: &mut Value| -> Result<(), String> {
+        let old_path = path.as_str().ok_or_else(|| String::from("invalid media path"))?;
+        let absolute = media_path::absolute_path(Path::new(old_path));
+        *path = serde_json::json!({
+            "relative": null,
+            "absolute": absolute.to_string_lossy(),
+        });
+        Ok(())
+    };
+
+    if let Some(metronome) = value.get_mut("metronome") {
+        for key in ["click_path", "downbeat_click_path"].iter() {
+            match metronome.get_mut(*key) {
+                Some(Value::Null) | None => {}
+                Some(path) => convert(path)?,
+            }
+        }
+    }
+
+    if let Some(tracks) = value.get_mut("timeline_tracks").and_then(Value::as_array_mut) {
+        for track in tracks.iter_mut() {
+            if let Some(clips) = track.get_mut("audio_clips").and_then(Value::as_array_mut) {
+                for clip in clips.iter_mut() {
+                    if let Some(path) = clip.get_mut("pcm_path") {
+                        convert(path)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Version 2 did not store IDs for tracks and clips. They are numbered in the order
+/// they appear in the file.
+fn migrate_v2_ids(value: &mut Value) -> Result<(), String> {
+    let mut next_id: u64 = 1;
+    let mut next = || {
+        let id = next_id;
+        next_id += 1;
+        Value::from(id)
+    };
+
+    if let Some(tracks) = value.get_mut("timeline_tracks").and_then(Value::as_array_mut) {
+        for track in tracks.iter_mut() {
+            let track = track.as_object_mut().ok_or_else(|| String::from("invalid track"))?;
+            track.insert(String::from("id"), next());
+
+            if let Some(clips) = track.get_mut("audio_clips").and_then(Value::as_array_mut) {
+                for clip in clips.iter_mut() {
+                    let clip =
+                        clip.as_object_mut().ok_or_else(|| String::from("invalid audio clip"))?;
+                    clip.insert(String::from("id"), next());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PROJECT: &str = "./assets/test_files/test_project.json";
+    const TEST_CLIP: &str = "./assets/test_files/synth_keys/synth_keys_48000_16bit.wav";
+
+    /// Create an empty folder for a test to write to.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("meadowlark-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write the test project with its version set to `version` and the parts that
+    /// version did not have removed.
+    fn write_old_version(dir: &Path, version: u64) -> PathBuf {
+        let mut value: Value =
+            serde_json::from_str(&fs::read_to_string(TEST_PROJECT).unwrap()).unwrap();
+        value["version"] = Value::from(version);
+
+        for track in value["timeline_tracks"].as_array_mut().unwrap() {
+            track.as_object_mut().unwrap().remove("id");
+
+            for clip in track["audio_clips"].as_array_mut().unwrap() {
+                let clip = clip.as_object_mut().unwrap();
+                clip.remove("id");
+
+                let absolute = media_path::absolute_path(Path::new(TEST_CLIP));
+                clip["pcm_path"] = if version == 1 {
+                    Value::from(TEST_CLIP)
+                } else {
+                    serde_json::json!({ "relative": null, "absolute": absolute })
+                };
+            }
+        }
+
+        let path = dir.join(format!("v{}.json", version));
+        fs::write(&path, serde_json::to_string(&value).unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn save_and_load() {
+        let project = load_project_file(Path::new(TEST_PROJECT), &[]).unwrap();
+        assert_eq!(project.timeline_tracks.len(), 2);
+        assert_eq!(project.timeline_tracks[1].id.raw(), 3);
+        assert_eq!(project.timeline_tracks[1].audio_clips[0].id.raw(), 4);
+        assert_eq!(
+            project.timeline_tracks[0].audio_clips[0].pcm_path,
+            media_path::absolute_path(Path::new(TEST_CLIP))
+        );
+
+        let dir = test_dir("save_and_load");
+        let path = dir.join("project.json");
+        save_project_file(&path, &project).unwrap();
+        let loaded = load_project_file(&path, &[]).unwrap();
+
+        // The clips still use the same files when loaded from the new location.
+        assert_eq!(
+            project_file_json(&loaded, &dir).unwrap(),
+            project_file_json(&project, &dir).unwrap()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn migrate_old_versions() {
+        let dir = test_dir("migrate_old_versions");
+        let current = load_project_file(Path::new(TEST_PROJECT), &[]).unwrap();
+
+        for &version in [1, 2].iter() {
+            let project = load_project_file(&write_old_version(&dir, version), &[]).unwrap();
+
+            // Tracks and clips are numbered in the order they appear, which matches the
+            // IDs in the test project.
+            assert_eq!(
+                project_file_json(&project, &dir).unwrap(),
+                project_file_json(&current, &dir).unwrap(),
+                "version {}",
+                version
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn version_errors() {
+        let path = Path::new("project.json");
+
+        match migrate(serde_json::json!({ "version": PROJECT_FILE_VERSION + 1 }), path) {
+            Err(ProjectFileError::NewerVersion((_, version))) => {
+                assert_eq!(version, u64::from(PROJECT_FILE_VERSION) + 1)
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        assert!(matches!(
+            migrate(serde_json::json!({ "timeline_tracks": [] }), path),
+            Err(ProjectFileError::MissingVersion(_))
+        ));
+        assert!(matches!(
+            migrate(serde_json::json!({ "version": "3" }), path),
+            Err(ProjectFileError::MissingVersion(_))
+        ));
+    }
+}
@@ -1,14 +1,16 @@
 use rusty_daw_core::{MusicalTime, Seconds};
 
 use crate::backend::timeline::{
-    AudioClipSaveState, LoopState, TempoMap, TimelineTrackSaveState, TimelineTransportSaveState,
+    AudioClipId, AudioClipSaveState, CountInMode, LoopState, PunchState, StopBehavior, TempoMap,
+    TimeSignatureMap, TimelineTrackId, TimelineTrackSaveState, TimelineTransportSaveState,
 };
 use crate::backend::BackendSaveState;
 
 /// This struct should contain all information needed to create a "save file"
 /// for a project.
 ///
-/// TODO: Project file format. This will need to be future-proof.
+/// This is written to and read from disk with `load_project_file` and
+/// `save_project_file`.
 #[derive(Debug, Clone)]
 pub struct ProjectSaveState {
     pub backend: BackendSaveState,
@@ -27,15 +29,26 @@ impl ProjectSaveState {
                 loop_start: MusicalTime::new(0.0),
                 loop_end: MusicalTime::new(4.0),
             },
+            punch_state: PunchState::Inactive,
+            count_in_mode: CountInMode::Off,
+            playback_rate: 1.0,
+            stop_behavior: StopBehavior::ReturnToPlayStart,
+            markers: Vec::new(),
         };
 
-        let backend = BackendSaveState::new(timeline_transport, TempoMap::default());
+        let backend = BackendSaveState::new(
+            timeline_transport,
+            TempoMap::default(),
+            TimeSignatureMap::default(),
+        );
 
         let mut timeline_tracks: Vec<TimelineTrackSaveState> = Vec::new();
 
         timeline_tracks.push(TimelineTrackSaveState {
+            id: TimelineTrackId::new(),
             name: String::from("Track 1"),
             audio_clips: vec![AudioClipSaveState {
+                id: AudioClipId::new(),
                 name: String::from("Audio Clip 1"),
                 pcm_path: "./assets/test_files/synth_keys/synth_keys_48000_16bit.wav".into(),
                 timeline_start: MusicalTime::new(0.0),
@@ -44,11 +57,14 @@ impl ProjectSaveState {
                 clip_gain_db: -3.0,
                 fades: Default::default(),
             }],
+            groove: None,
         });
 
         timeline_tracks.push(TimelineTrackSaveState {
+            id: TimelineTrackId::new(),
             name: String::from("Track 2"),
             audio_clips: vec![AudioClipSaveState {
+                id: AudioClipId::new(),
                 name: String::from("Audio Clip 1"),
                 pcm_path: "./assets/test_files/synth_keys/synth_keys_48000_16bit.wav".into(),
                 timeline_start: MusicalTime::new(1.0),
@@ -57,6 +73,7 @@ impl ProjectSaveState {
                 clip_gain_db: -3.0,
                 fades: Default::default(),
             }],
+            groove: None,
         });
 
         Self { backend, timeline_tracks }
@@ -0,0 +1,223 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::event::{ClipEvent, StateSystemEvent, TrackEvent};
+use crate::project::{save_project_file, ProjectSaveState};
+
+/// How often the project is autosaved while it is being edited.
+pub static AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The project is autosaved right away after this many edits, even if the interval
+/// has not passed yet.
+pub static AUTOSAVE_EDIT_COUNT: usize = 20;
+
+enum Message {
+    Snapshot { save_state: Box<ProjectSaveState>, recovery_path: PathBuf, immediate: bool },
+    Discard(PathBuf),
+    Quit,
+}
+
+/// Writes the project to a recovery file on a separate thread, so edits can be
+/// recovered after a crash.
+pub struct Autosave {
+    sender: Sender<Message>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Autosave {
+    pub fn new(interval: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        let thread = thread::spawn(move || run(receiver, interval));
+
+        Self { sender, thread: Some(thread) }
+    }
+
+    /// Autosave the given state of the project.
+    ///
+    /// The state is written the next time the interval has passed, or right away if
+    /// `immediate` is `true`. Only the latest state is written.
+    pub fn snapshot(&self, save_state: &ProjectSaveState, recovery_path: PathBuf, immediate: bool) {
+        let _ = self.sender.send(Message::Snapshot {
+            save_state: Box::new(save_state.clone()),
+            recovery_path,
+            immediate,
+        });
+    }
+
+    /// Delete the recovery file (i.e. after the project was saved), and drop any state
+    /// that was not written yet.
+    pub fn discard(&self, recovery_path: PathBuf) {
+        let _ = self.sender.send(Message::Discard(recovery_path));
+    }
+}
+
+impl Drop for Autosave {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Message::Quit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(receiver: Receiver<Message>, interval: Duration) {
+    let mut pending: Option<(Box<ProjectSaveState>, PathBuf)> = None;
+    let mut last_save = Instant::now();
+
+    loop {
+        let timeout = interval.checked_sub(last_save.elapsed()).unwrap_or_default();
+
+        let save_now = match receiver.recv_timeout(timeout) {
+            Ok(Message::Snapshot { save_state, recovery_path, immediate }) => {
+                pending = Some((save_state, recovery_path));
+                immediate
+            }
+            Ok(Message::Discard(recovery_path)) => {
+                pending = None;
+                if recovery_path.exists() {
+                    if let Err(e) = fs::remove_file(&recovery_path) {
+                        log::error!("Failed to delete recovery file {:?}: {}", recovery_path, e);
+                    }
+                }
+                false
+            }
+            Ok(Message::Quit) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => true,
+        };
+
+        if save_now || last_save.elapsed() >= interval {
+            if let Some((save_state, recovery_path)) = pending.take() {
+                write_recovery_file(&save_state, &recovery_path);
+            }
+            last_save = Instant::now();
+        }
+    }
+
+    // Write any edits that were made since the last autosave.
+    if let Some((save_state, recovery_path)) = pending.take() {
+        write_recovery_file(&save_state, &recovery_path);
+    }
+}
+
+fn write_recovery_file(save_state: &ProjectSaveState, recovery_path: &Path) {
+    if let Some(dir) = recovery_path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+
+    match save_project_file(recovery_path, save_state) {
+        Ok(()) => log::debug!("Autosaved project to {:?}", recovery_path),
+        Err(e) => log::error!("Autosave failed: {}", e),
+    }
+}
+
+/// The path of the recovery file for the project saved at `project_path`. Projects
+/// that were never saved get a recovery file in the temporary directory for each
+/// session, so running several instances doesn't mix up their autosaves.
+pub fn recovery_path(project_path: Option<&Path>) -> PathBuf {
+    if let Some(project_path) = project_path {
+        let mut path = project_path.as_os_str().to_os_string();
+        path.push(".autosave");
+        PathBuf::from(path)
+    } else {
+        untitled_recovery_dir().join(format!("untitled-{}.autosave", std::process::id()))
+    }
+}
+
+/// The path the project is autosaved to while the user has not restored or discarded
+/// the recovery file at `recovery_path` yet, so it isn't overwritten.
+pub fn pending_recovery_path(recovery_path: &Path) -> PathBuf {
+    let mut path = recovery_path.as_os_str().to_os_string();
+    path.push(".pending");
+    PathBuf::from(path)
+}
+
+/// Return the recovery file of the project if it is newer than the project file.
+///
+/// For projects that were never saved, this is the newest recovery file left behind by
+/// another session.
+pub fn newer_recovery_file(project_path: Option<&Path>) -> Option<PathBuf> {
+    let project_path = if let Some(project_path) = project_path {
+        project_path
+    } else {
+        return newest_untitled_recovery_file(&untitled_recovery_dir(), &recovery_path(None));
+    };
+
+    let recovery_path = recovery_path(Some(project_path));
+    let recovery_modified = fs::metadata(&recovery_path).and_then(|m| m.modified()).ok()?;
+
+    if let Ok(project_modified) = fs::metadata(project_path).and_then(|m| m.modified()) {
+        if project_modified >= recovery_modified {
+            return None;
+        }
+    }
+
+    Some(recovery_path)
+}
+
+fn untitled_recovery_dir() -> PathBuf {
+    std::env::temp_dir().join("meadowlark")
+}
+
+/// Find the newest recovery file of a project that was never saved in `dir`, other than
+/// the one of this session.
+fn newest_untitled_recovery_file(dir: &Path, own_recovery_path: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            path.as_path() != own_recovery_path
+                && name.starts_with("untitled-")
+                && name.ends_with(".autosave")
+        })
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((modified, path))
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+/// Whether the edit made by the event is large enough to autosave right away.
+pub fn is_significant_edit(event: &StateSystemEvent) -> bool {
+    matches!(
+        event,
+        StateSystemEvent::Track(TrackEvent::InsertTrack(..))
+            | StateSystemEvent::Track(TrackEvent::RemoveTrack(..))
+            | StateSystemEvent::Clip(ClipEvent::InsertAudioClip(..))
+            | StateSystemEvent::Clip(ClipEvent::RemoveAudioClip(..))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_untitled_recovery_file_of_other_session() {
+        let dir = std::env::temp_dir()
+            .join(format!("meadowlark-test-{}-untitled-recovery", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let own = dir.join("untitled-1.autosave");
+        let other = dir.join("untitled-2.autosave");
+        fs::write(&other, "").unwrap();
+        fs::write(&own, "").unwrap();
+        fs::write(pending_recovery_path(&other), "").unwrap();
+        fs::write(dir.join("project.json"), "").unwrap();
+
+        // This session's own recovery file and unrelated files are ignored.
+        assert_eq!(newest_untitled_recovery_file(&dir, &own), Some(other.clone()));
+
+        fs::remove_file(&other).unwrap();
+        assert_eq!(newest_untitled_recovery_file(&dir, &own), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,11 @@
+use rusty_daw_core::MusicalTime;
+use std::path::PathBuf;
 use tuix::{Entity, Event, Lens, Model, State};
 
-use super::{ProjectSaveState, StateSystem};
+use crate::backend::timeline::TimeSignature;
+
+use super::{CollectPlan, CollectSettings, MissingMediaReport, StateSystem};
+use crate::project::{ExportStatus, ProjectSaveState};
 
 #[derive(Lens)]
 pub struct BoundGuiState {
@@ -11,7 +16,30 @@ pub struct BoundGuiState {
 
     pub backend_loaded: bool,
     pub is_playing: bool,
+    /// The position of the playhead the last time it was moved by the user or by
+    /// stopping the transport.
+    pub playhead: MusicalTime,
     pub bpm: f64,
+    pub time_signature: TimeSignature,
+    /// The media files of the current project that could not be loaded.
+    pub missing_media: MissingMediaReport,
+    pub can_undo: bool,
+    pub can_redo: bool,
+    /// An autosave that is newer than the current project. The user should be asked
+    /// whether to restore or discard it.
+    pub recovery_file: Option<PathBuf>,
+    /// The warning from the last time the audio graph was compiled (i.e. a feedback
+    /// loop that was left out).
+    pub graph_warning: Option<String>,
+    /// The error from the last time a project could not be opened or loaded.
+    pub project_error: Option<String>,
+    /// The progress or result of the last export.
+    pub export_status: Option<ExportStatus>,
+    /// The media files that will be copied when collecting the project. The user should
+    /// be asked whether to confirm or cancel.
+    pub collect_plan: Option<(CollectSettings, CollectPlan)>,
+    /// The progress or result of the last collect.
+    pub collect_status: Option<ExportStatus>,
 }
 
 impl BoundGuiState {
@@ -21,7 +49,18 @@ impl BoundGuiState {
             save_state: ProjectSaveState::new_empty(),
             backend_loaded: false,
             is_playing: false,
+            playhead: MusicalTime::new(0.0),
             bpm: 110.0,
+            time_signature: TimeSignature::default(),
+            missing_media: MissingMediaReport::default(),
+            can_undo: false,
+            can_redo: false,
+            recovery_file: None,
+            graph_warning: None,
+            project_error: None,
+            export_status: None,
+            collect_plan: None,
+            collect_status: None,
         }
     }
 }
@@ -0,0 +1,491 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::project::{
+    project_dir, project_file_json, save_project_file, ProjectFileError, ProjectSaveState,
+};
+
+/// The name of the folder (next to the project file) that media files are collected
+/// into.
+pub static COLLECTED_MEDIA_FOLDER: &str = "media";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectFormat {
+    /// Write the project file and a media folder next to it.
+    Directory,
+    /// Write a single zip archive containing a folder with the project file and the
+    /// media folder.
+    ZipArchive,
+}
+
+#[derive(Debug, Clone)]
+pub struct CollectSettings {
+    /// The path of the project file to write, or of the zip archive with
+    /// `CollectFormat::ZipArchive`.
+    pub path: PathBuf,
+    pub format: CollectFormat,
+}
+
+/// A media file that will be copied.
+#[derive(Debug, Clone)]
+pub struct CollectedFile {
+    /// The file as it is used by the project now.
+    pub source: PathBuf,
+    /// The file name inside the media folder.
+    pub file_name: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+}
+
+/// Every media file used by a project, found before anything is copied.
+#[derive(Debug, Clone)]
+pub struct CollectPlan {
+    /// Each file appears only once, no matter how many clips use it.
+    pub files: Vec<CollectedFile>,
+    /// The size of all files in bytes.
+    pub total_size: u64,
+}
+
+impl CollectPlan {
+    /// Find every media file used by the project.
+    ///
+    /// This fails if any of the files cannot be found, since the collected project
+    /// would not be complete.
+    pub fn new(project_save_state: &ProjectSaveState) -> Result<Self, CollectError> {
+        let mut sources: Vec<PathBuf> = Vec::new();
+        let mut canonical_paths: Vec<PathBuf> = Vec::new();
+
+        for path in media_paths(project_save_state) {
+            // Different paths can point to the same file.
+            let canonical = fs::canonicalize(path)
+                .map_err(|e| CollectError::CouldNotRead((path.clone(), e)))?;
+
+            if !canonical_paths.contains(&canonical) {
+                canonical_paths.push(canonical);
+                sources.push(path.clone());
+            }
+        }
+
+        let file_names = media_file_names(&sources);
+
+        let mut files = Vec::with_capacity(sources.len());
+        let mut total_size = 0;
+        for (source, file_name) in sources.into_iter().zip(file_names.into_iter()) {
+            let size = fs::metadata(&source)
+                .map_err(|e| CollectError::CouldNotRead((source.clone(), e)))?
+                .len();
+            total_size += size;
+
+            files.push(CollectedFile { source, file_name, size });
+        }
+
+        Ok(Self { files, total_size })
+    }
+}
+
+/// Copy every media file used by the project into a media folder next to a new copy of
+/// the project file, so the project can be moved as a whole.
+///
+/// The project that is currently open is not changed. `progress` is called with the
+/// fraction of bytes that has been copied so far. Return `false` from it to cancel.
+///
+/// If collecting fails or is cancelled, the files and folders it created are removed
+/// again.
+pub fn collect_project<P: FnMut(f32) -> bool>(
+    project_save_state: &ProjectSaveState,
+    plan: &CollectPlan,
+    settings: &CollectSettings,
+    mut progress: P,
+) -> Result<(), CollectError> {
+    // The folder containing the project file and the media folder. For archives this is
+    // where the archive would be extracted to.
+    let (root_dir, project_file_name) = match settings.format {
+        CollectFormat::Directory => {
+            let file_name = settings.path.file_name().map(PathBuf::from).unwrap_or_default();
+            (project_dir(&settings.path), file_name)
+        }
+        CollectFormat::ZipArchive => {
+            let stem = settings.path.file_stem().map(PathBuf::from).unwrap_or_default();
+            (project_dir(&settings.path).join(&stem), stem.with_extension("json"))
+        }
+    };
+    let media_dir = root_dir.join(COLLECTED_MEDIA_FOLDER);
+
+    // Point every clip to its copy in the media folder.
+    let canonical_sources: Vec<Option<PathBuf>> =
+        plan.files.iter().map(|f| fs::canonicalize(&f.source).ok()).collect();
+    let mut collected = project_save_state.clone();
+    for path in media_paths_mut(&mut collected) {
+        let canonical = fs::canonicalize(&path).ok();
+        if let Some(i) = plan
+            .files
+            .iter()
+            .zip(canonical_sources.iter())
+            .position(|(f, c)| f.source == *path || canonical.is_some() && *c == canonical)
+        {
+            *path = media_dir.join(&plan.files[i].file_name);
+        }
+    }
+
+    let mut copied: u64 = 0;
+    let mut report_progress = |copied: u64| {
+        if plan.total_size == 0 {
+            progress(1.0)
+        } else {
+            progress((copied as f64 / plan.total_size as f64) as f32)
+        }
+    };
+
+    match settings.format {
+        CollectFormat::Directory => {
+            // Remember what this creates, so it can be removed again if collecting fails.
+            let created_root_dir = !root_dir.exists();
+            let created_media_dir = !media_dir.exists();
+            let mut created_files: Vec<PathBuf> = Vec::new();
+
+            let mut copy_files = || -> Result<(), CollectError> {
+                fs::create_dir_all(&media_dir)
+                    .map_err(|e| CollectError::CouldNotWrite((media_dir.clone(), e)))?;
+
+                for file in plan.files.iter() {
+                    if !report_progress(copied) {
+                        return Err(CollectError::Cancelled);
+                    }
+
+                    let dest = media_dir.join(&file.file_name);
+
+                    // Copying a file onto itself would truncate it (i.e. when the project
+                    // was already collected into the same folder).
+                    let is_same_file = fs::canonicalize(&dest)
+                        .map(|d| fs::canonicalize(&file.source).ok() == Some(d))
+                        .unwrap_or(false);
+                    if !is_same_file {
+                        if !dest.exists() {
+                            created_files.push(dest.clone());
+                        }
+                        fs::copy(&file.source, &dest)
+                            .map_err(|e| CollectError::CouldNotWrite((dest.clone(), e)))?;
+                    }
+                    copied += file.size;
+                }
+
+                save_project_file(&root_dir.join(&project_file_name), &collected)?;
+                Ok(())
+            };
+
+            if let Err(e) = copy_files() {
+                // Don't leave a half-collected project behind. Files that were already in
+                // the media folder are kept (although they may have been overwritten).
+                for file in created_files.iter() {
+                    let _ = fs::remove_file(file);
+                }
+                if created_media_dir {
+                    let _ = fs::remove_dir(&media_dir);
+                }
+                if created_root_dir {
+                    let _ = fs::remove_dir(&root_dir);
+                }
+                return Err(e);
+            }
+        }
+        CollectFormat::ZipArchive => {
+            let path = &settings.path;
+            let archive_err = |e| CollectError::Archive((path.clone(), e));
+
+            let mut write_archive = || -> Result<(), CollectError> {
+                let archive = File::create(path)
+                    .map_err(|e| CollectError::CouldNotWrite((path.clone(), e)))?;
+                let mut zip = ZipWriter::new(archive);
+
+                // Audio files barely compress, so they are stored as they are.
+                let options = FileOptions::default()
+                    .compression_method(CompressionMethod::Stored)
+                    .large_file(plan.total_size >= u64::from(u32::MAX));
+
+                // Paths inside the archive always use `/`.
+                let root_name = root_dir.file_name().unwrap_or_default().to_string_lossy();
+
+                for file in plan.files.iter() {
+                    if !report_progress(copied) {
+                        return Err(CollectError::Cancelled);
+                    }
+
+                    zip.start_file(
+                        format!("{}/{}/{}", root_name, COLLECTED_MEDIA_FOLDER, file.file_name),
+                        options,
+                    )
+                    .map_err(archive_err)?;
+
+                    let mut source = File::open(&file.source)
+                        .map_err(|e| CollectError::CouldNotRead((file.source.clone(), e)))?;
+                    io::copy(&mut source, &mut zip)
+                        .map_err(|e| CollectError::CouldNotWrite((path.clone(), e)))?;
+                    copied += file.size;
+                }
+
+                zip.start_file(
+                    format!("{}/{}", root_name, project_file_name.to_string_lossy()),
+                    FileOptions::default(),
+                )
+                .map_err(archive_err)?;
+                let text = project_file_json(&collected, &root_dir)
+                    .map_err(|e| ProjectFileError::CouldNotSerialize((path.clone(), e)))?;
+                zip.write_all(text.as_bytes())
+                    .map_err(|e| CollectError::CouldNotWrite((path.clone(), e)))?;
+
+                zip.finish().map_err(archive_err)?;
+                Ok(())
+            };
+
+            if let Err(e) = write_archive() {
+                // A partly written archive can't be opened, so don't leave it behind.
+                let _ = fs::remove_file(path);
+                return Err(e);
+            }
+        }
+    }
+
+    report_progress(copied);
+
+    Ok(())
+}
+
+/// The paths of every media file used by the project, in the order they appear.
+fn media_paths(project_save_state: &ProjectSaveState) -> Vec<&PathBuf> {
+    let metronome = &project_save_state.backend.metronome;
+
+    metronome
+        .click_path
+        .iter()
+        .chain(metronome.downbeat_click_path.iter())
+        .chain(
+            project_save_state
+                .timeline_tracks
+                .iter()
+                .flat_map(|track| track.audio_clips.iter().map(|clip| &clip.pcm_path)),
+        )
+        .collect()
+}
+
+fn media_paths_mut(project_save_state: &mut ProjectSaveState) -> Vec<&mut PathBuf> {
+    let metronome = &mut project_save_state.backend.metronome;
+
+    metronome
+        .click_path
+        .iter_mut()
+        .chain(metronome.downbeat_click_path.iter_mut())
+        .chain(
+            project_save_state
+                .timeline_tracks
+                .iter_mut()
+                .flat_map(|track| track.audio_clips.iter_mut().map(|clip| &mut clip.pcm_path)),
+        )
+        .collect()
+}
+
+/// Give every file a unique name inside the media folder. Files with the same name
+/// (from different folders) are numbered, i.e. "kick.wav" and "kick 2.wav".
+fn media_file_names(paths: &[PathBuf]) -> Vec<String> {
+    let mut names = Vec::<String>::with_capacity(paths.len());
+
+    for (i, path) in paths.iter().enumerate() {
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format!("Media {}", i + 1));
+        let extension =
+            path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+
+        let mut name = format!("{}{}", stem, extension);
+        let mut n = 2;
+        while names.iter().any(|other| other.eq_ignore_ascii_case(&name)) {
+            name = format!("{} {}{}", stem, n, extension);
+            n += 1;
+        }
+
+        names.push(name);
+    }
+
+    names
+}
+
+#[derive(Debug)]
+pub enum CollectError {
+    CouldNotRead((PathBuf, io::Error)),
+    CouldNotWrite((PathBuf, io::Error)),
+    Archive((PathBuf, zip::result::ZipError)),
+    ProjectFile(ProjectFileError),
+    Cancelled,
+}
+
+impl Error for CollectError {}
+
+impl fmt::Display for CollectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use CollectError::*;
+
+        match self {
+            CouldNotRead((path, e)) => {
+                write!(
+                    f,
+                    "Failed to collect project: could not read file | {} | path: {:?}",
+                    e, path
+                )
+            }
+            CouldNotWrite((path, e)) => {
+                write!(
+                    f,
+                    "Failed to collect project: could not write file | {} | path: {:?}",
+                    e, path
+                )
+            }
+            Archive((path, e)) => {
+                write!(
+                    f,
+                    "Failed to collect project: could not write archive | {} | path: {:?}",
+                    e, path
+                )
+            }
+            ProjectFile(e) => write!(f, "Failed to collect project: {}", e),
+            Cancelled => write!(f, "Failed to collect project: cancelled"),
+        }
+    }
+}
+
+impl From<ProjectFileError> for CollectError {
+    fn from(e: ProjectFileError) -> Self {
+        CollectError::ProjectFile(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_daw_core::{MusicalTime, Seconds};
+
+    use super::*;
+    use crate::backend::timeline::{
+        AudioClipFades, AudioClipId, AudioClipSaveState, TimelineTrackId, TimelineTrackSaveState,
+    };
+    use crate::project::load_project_file;
+
+    fn audio_clip(pcm_path: PathBuf) -> AudioClipSaveState {
+        AudioClipSaveState {
+            id: AudioClipId::new(),
+            name: String::from("Clip"),
+            pcm_path,
+            timeline_start: MusicalTime::new(0.0),
+            duration: Seconds(1.0),
+            clip_start_offset: Seconds(0.0),
+            clip_gain_db: 0.0,
+            fades: AudioClipFades::no_fade(),
+        }
+    }
+
+    #[test]
+    fn shared_file_is_collected_once() {
+        let dir =
+            std::env::temp_dir().join(format!("meadowlark-test-{}-collect", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("samples")).unwrap();
+        fs::write(dir.join("samples").join("kick.wav"), [1, 2, 3, 4]).unwrap();
+
+        // Both clips use the same file through different paths.
+        let mut project = ProjectSaveState::new_empty();
+        project.timeline_tracks.push(TimelineTrackSaveState {
+            id: TimelineTrackId::new(),
+            name: String::from("Drums"),
+            audio_clips: vec![
+                audio_clip(dir.join("samples").join("kick.wav")),
+                audio_clip(dir.join("samples").join("..").join("samples").join("kick.wav")),
+            ],
+            groove: None,
+        });
+
+        let plan = CollectPlan::new(&project).unwrap();
+        assert_eq!(plan.files.len(), 1);
+        assert_eq!(plan.files[0].file_name, "kick.wav");
+        assert_eq!(plan.total_size, 4);
+
+        let settings = CollectSettings {
+            path: dir.join("collected").join("project.json"),
+            format: CollectFormat::Directory,
+        };
+        collect_project(&project, &plan, &settings, |_| true).unwrap();
+
+        let media_dir = dir.join("collected").join(COLLECTED_MEDIA_FOLDER);
+        assert_eq!(fs::read(media_dir.join("kick.wav")).unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(fs::read_dir(&media_dir).unwrap().count(), 1);
+
+        // Both clips of the collected project point to the copy.
+        let collected = load_project_file(&settings.path, &[]).unwrap();
+        for clip in collected.timeline_tracks[0].audio_clips.iter() {
+            assert_eq!(clip.pcm_path, media_dir.join("kick.wav"));
+        }
+
+        // The open project is not changed.
+        assert_eq!(
+            project.timeline_tracks[0].audio_clips[0].pcm_path,
+            dir.join("samples").join("kick.wav")
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cancelled_collect_is_cleaned_up() {
+        let dir = std::env::temp_dir()
+            .join(format!("meadowlark-test-{}-collect-cancel", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("kick.wav"), [1, 2, 3, 4]).unwrap();
+        fs::write(dir.join("snare.wav"), [5, 6, 7, 8]).unwrap();
+
+        let mut project = ProjectSaveState::new_empty();
+        project.timeline_tracks.push(TimelineTrackSaveState {
+            id: TimelineTrackId::new(),
+            name: String::from("Drums"),
+            audio_clips: vec![audio_clip(dir.join("kick.wav")), audio_clip(dir.join("snare.wav"))],
+            groove: None,
+        });
+        let plan = CollectPlan::new(&project).unwrap();
+
+        // Cancel after the first file is copied.
+        for (path, format) in [
+            (dir.join("collected").join("project.json"), CollectFormat::Directory),
+            (dir.join("collected.zip"), CollectFormat::ZipArchive),
+        ]
+        .iter()
+        {
+            let settings = CollectSettings { path: path.clone(), format: *format };
+            let mut calls = 0;
+            let res = collect_project(&project, &plan, &settings, |_| {
+                calls += 1;
+                calls < 2
+            });
+
+            assert!(matches!(res, Err(CollectError::Cancelled)));
+            assert!(!dir.join("collected").exists());
+            assert!(!dir.join("collected.zip").exists());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn media_names() {
+        let names = media_file_names(&[
+            PathBuf::from("a/kick.wav"),
+            PathBuf::from("b/kick.wav"),
+            PathBuf::from("c/Kick.wav"),
+            PathBuf::from("c/snare"),
+        ]);
+
+        assert_eq!(names, vec!["kick.wav", "kick 2.wav", "Kick 3.wav", "snare"]);
+    }
+}
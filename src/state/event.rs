@@ -1,10 +1,28 @@
-use super::ProjectSaveState;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::backend::dsp::dither::DitherMode;
+use crate::backend::export::{ExportSettings, StemExportSettings};
+use rusty_daw_core::{MusicalTime, Seconds};
+
+use crate::backend::timeline::{
+    AudioClipFades, AudioClipId, AudioClipSaveState, CountInMode, Groove, JumpMode, LoopState,
+    Marker, MetronomeClick, PunchState, StopBehavior, TempoMap, TempoPoint, TimeSignature,
+    TimeSignatureMap, TimeSignaturePoint, TimelineTrackId, TimelineTrackSaveState,
+};
+
+use super::CollectSettings;
+use crate::project::ProjectSaveState;
 
 #[derive(Debug, Clone)]
 pub enum StateSystemEvent {
     Transport(TransportEvent),
     Tempo(TempoEvent),
+    Metronome(MetronomeEvent),
+    Track(TrackEvent),
+    Clip(ClipEvent),
     Project(ProjectEvent),
+    History(HistoryEvent),
 }
 
 // TODO: Remove this once tuix removes the `PartialEq` requirement
@@ -18,11 +36,116 @@ impl PartialEq for StateSystemEvent {
 #[derive(Debug, Clone)]
 pub enum ProjectEvent {
     LoadProject(Box<ProjectSaveState>),
+    /// Load the project file at the given path.
+    OpenProject(PathBuf),
+    /// Save the current project to the given path.
+    SaveProject(PathBuf),
+    /// Load the autosave that was found when the current project was opened.
+    RestoreRecovery,
+    /// Delete the autosave that was found when the current project was opened.
+    DiscardRecovery,
+    /// Find every media file the current project uses, and store them in
+    /// `BoundGuiState::collect_plan` so the user can confirm before anything is copied.
+    PlanCollect(CollectSettings),
+    /// Save a copy of the current project together with every media file in the
+    /// collect plan.
+    ConfirmCollect,
+    /// Discard the collect plan without copying anything, or stop copying if the collect
+    /// is already running.
+    CancelCollect,
+    /// Update `BoundGuiState::collect_status` with the progress of the running collect.
+    /// The GUI should send this regularly while the collect status is `Running`.
+    PollCollect,
+    /// Set the folders media files are looked for in when they cannot be found next to
+    /// a project that is opened.
+    SetMediaSearchFolders(Vec<PathBuf>),
+    /// Use the file at the second path for every audio clip that uses the file at the
+    /// first path.
+    RelinkMedia(PathBuf, PathBuf),
+    /// Set the dither used when the audio output device only takes integer samples. This
+    /// takes effect the next time the audio output is opened (i.e. when a project is
+    /// loaded).
+    SetStreamDither(DitherMode),
+    /// Look for every missing media file inside the given folder, and relink the ones
+    /// that are found.
+    RelinkMediaFolder(PathBuf),
+    /// Render the master output of the current project to a file.
+    Export(ExportSettings),
+    /// Render every timeline track of the current project to its own file.
+    ExportStems(StemExportSettings),
+    /// Update `BoundGuiState::export_status` with the progress of the running export.
+    /// The GUI should send this regularly while the export status is `Running`.
+    PollExport,
+}
+
+/// Edits to the timeline tracks. Tracks are addressed by their ID.
+#[derive(Debug, Clone)]
+pub enum TrackEvent {
+    /// Insert a timeline track at the given index. An index past the last track adds
+    /// the track to the end. The IDs of the track and its clips must not be used yet.
+    InsertTrack(usize, TimelineTrackSaveState),
+    RemoveTrack(TimelineTrackId),
+    /// Move a timeline track so that it ends up at the given index.
+    MoveTrack(TimelineTrackId, usize),
+    SetName(TimelineTrackId, String),
+}
+
+/// Edits to the audio clips on the timeline tracks. Clips are addressed by the ID of
+/// their track followed by their own ID.
+#[derive(Debug, Clone)]
+pub enum ClipEvent {
+    /// Insert an audio clip at the given index. An index past the last clip adds the
+    /// clip to the end of the track. The ID of the clip must not be used yet.
+    InsertAudioClip(TimelineTrackId, usize, AudioClipSaveState),
+    RemoveAudioClip(TimelineTrackId, AudioClipId),
+    SetName(TimelineTrackId, AudioClipId, String),
+    /// Move an audio clip to a new position on the timeline.
+    SetTimelineStart(TimelineTrackId, AudioClipId, MusicalTime),
+    /// Set the duration of an audio clip on the timeline (trims the end of the clip).
+    SetDuration(TimelineTrackId, AudioClipId, Seconds),
+    /// Set the offset into the audio file where the clip starts playing from. Together
+    /// with `SetTimelineStart` and `SetDuration` this trims the start of the clip.
+    SetClipStartOffset(TimelineTrackId, AudioClipId, Seconds),
+    SetGainDb(TimelineTrackId, AudioClipId, f32),
+    SetFades(TimelineTrackId, AudioClipId, AudioClipFades),
+}
+
+#[derive(Debug, Clone)]
+pub enum HistoryEvent {
+    Undo,
+    Redo,
+    /// Group all edits until the next `EndGroup` into a single undo step.
+    BeginGroup,
+    EndGroup,
 }
 
 #[derive(Debug, Clone)]
 pub enum TempoEvent {
     SetBPM(f64),
+    /// Replace the entire tempo map.
+    SetTempoMap(TempoMap),
+    /// Replace the entire time signature map.
+    SetTimeSignatureMap(TimeSignatureMap),
+    /// The user tapped the tempo at the given time.
+    Tap(Instant),
+    InsertTempoPoint(TempoPoint),
+    RemoveTempoPoint(usize),
+    SetTimeSignature(TimeSignature),
+    InsertTimeSignaturePoint(TimeSignaturePoint),
+    RemoveTimeSignaturePoint(usize),
+    SetGroove(Groove),
+    /// Set the groove of the timeline track with the given ID. `None` means the track
+    /// follows the global groove.
+    SetTrackGroove(TimelineTrackId, Option<Groove>),
+}
+
+#[derive(Debug, Clone)]
+pub enum MetronomeEvent {
+    SetEnabled(bool),
+    SetGainDb(f32),
+    /// Load the sample used for the given click. `None` means the built-in click sound
+    /// is used.
+    LoadClickSample(MetronomeClick, Option<PathBuf>),
 }
 
 #[derive(Debug, Clone)]
@@ -30,6 +153,18 @@ pub enum TransportEvent {
     Play,
     Stop,
     Pause,
+    SetCountInMode(CountInMode),
+    SetStopBehavior(StopBehavior),
+    SetLoopState(LoopState),
+    SetPunchState(PunchState),
+    /// Set the rate the playhead moves at, where `1.0` is normal speed.
+    SetPlaybackRate(f64),
+    AddMarker(Marker),
+    RemoveMarker(usize),
+    JumpToNextMarker(JumpMode),
+    JumpToPreviousMarker(JumpMode),
+    /// Set the loop region to the range between the markers with the given indexes.
+    SetLoopFromMarkers(usize, usize),
 }
 
 impl ProjectEvent {
@@ -54,6 +189,17 @@ impl From<TempoEvent> for StateSystemEvent {
     }
 }
 
+impl MetronomeEvent {
+    pub fn to_state_event(self) -> StateSystemEvent {
+        self.into()
+    }
+}
+impl From<MetronomeEvent> for StateSystemEvent {
+    fn from(e: MetronomeEvent) -> Self {
+        Self::Metronome(e)
+    }
+}
+
 impl TransportEvent {
     pub fn to_state_event(self) -> StateSystemEvent {
         self.into()
@@ -64,3 +210,36 @@ impl From<TransportEvent> for StateSystemEvent {
         Self::Transport(e)
     }
 }
+
+impl TrackEvent {
+    pub fn to_state_event(self) -> StateSystemEvent {
+        self.into()
+    }
+}
+impl From<TrackEvent> for StateSystemEvent {
+    fn from(e: TrackEvent) -> Self {
+        Self::Track(e)
+    }
+}
+
+impl ClipEvent {
+    pub fn to_state_event(self) -> StateSystemEvent {
+        self.into()
+    }
+}
+impl From<ClipEvent> for StateSystemEvent {
+    fn from(e: ClipEvent) -> Self {
+        Self::Clip(e)
+    }
+}
+
+impl HistoryEvent {
+    pub fn to_state_event(self) -> StateSystemEvent {
+        self.into()
+    }
+}
+impl From<HistoryEvent> for StateSystemEvent {
+    fn from(e: HistoryEvent) -> Self {
+        Self::History(e)
+    }
+}
@@ -0,0 +1,254 @@
+use super::event::StateSystemEvent;
+
+/// The maximum number of steps that can be undone.
+pub static MAX_UNDO_STEPS: usize = 256;
+
+/// A single step in the history. This can contain several edits that are undone and
+/// redone together.
+#[derive(Debug, Clone, Default)]
+struct HistoryStep {
+    /// The events that revert this step, in the order they were recorded.
+    undo: Vec<StateSystemEvent>,
+    /// The events that apply this step again, in the order they were recorded.
+    redo: Vec<StateSystemEvent>,
+}
+
+/// The undo/redo history of a project.
+///
+/// Every edit is stored as the event that made it along with an event that reverts it,
+/// so undoing and redoing goes through the same code paths as any other edit.
+#[derive(Debug, Default)]
+pub struct History {
+    undo_stack: Vec<HistoryStep>,
+    redo_stack: Vec<HistoryStep>,
+
+    /// The step that edits are currently being grouped into.
+    group: Option<HistoryStep>,
+    group_depth: usize,
+
+    /// Whether the last step was recorded with `record_merged()` and can still be
+    /// merged with.
+    merging: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an edit.
+    ///
+    /// * `event` - The event that made the edit.
+    /// * `inverse` - The event that reverts the edit.
+    pub fn record(&mut self, event: StateSystemEvent, inverse: StateSystemEvent) {
+        // A new edit makes the redo history invalid.
+        self.redo_stack.clear();
+        self.merging = false;
+
+        if let Some(group) = &mut self.group {
+            group.redo.push(event);
+            group.undo.push(inverse);
+        } else {
+            self.push_undo_step(HistoryStep { undo: vec![inverse], redo: vec![event] });
+        }
+    }
+
+    /// Record an edit that replaces the previous one if that was also recorded with this
+    /// method, and nothing else happened since (i.e. tapping a tempo). Undoing the
+    /// merged step reverts all of the merged edits at once.
+    ///
+    /// * `event` - The event that made the edit.
+    /// * `inverse` - The event that reverts the edit.
+    pub fn record_merged(&mut self, event: StateSystemEvent, inverse: StateSystemEvent) {
+        if self.merging && self.group.is_none() {
+            if let Some(step) = self.undo_stack.last_mut() {
+                // Keep the inverse of the first edit, so undo goes back to the state
+                // before any of them.
+                step.redo = vec![event];
+                return;
+            }
+        }
+
+        self.record(event, inverse);
+        self.merging = true;
+    }
+
+    /// Stop merging edits into the last step.
+    pub fn end_merge(&mut self) {
+        self.merging = false;
+    }
+
+    /// Start grouping all edits that follow into a single step, until `end_group()` is
+    /// called. Groups can be nested, in which case everything is grouped into the
+    /// outermost group.
+    pub fn begin_group(&mut self) {
+        self.merging = false;
+        if self.group_depth == 0 {
+            self.group = Some(HistoryStep::default());
+        }
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        if self.group_depth == 0 {
+            log::warn!("History group ended without being started");
+            return;
+        }
+
+        self.group_depth -= 1;
+        if self.group_depth == 0 {
+            if let Some(group) = self.group.take() {
+                // Don't add a step for a group without any edits.
+                if !group.undo.is_empty() {
+                    self.push_undo_step(group);
+                }
+            }
+        }
+    }
+
+    /// Take the events that revert the last step, in the order they should be applied.
+    ///
+    /// The step is moved to the redo history.
+    pub fn undo(&mut self) -> Option<Vec<StateSystemEvent>> {
+        self.close_group();
+        self.merging = false;
+
+        let step = self.undo_stack.pop()?;
+        let events = step.undo.iter().rev().cloned().collect();
+        self.redo_stack.push(step);
+
+        Some(events)
+    }
+
+    /// Take the events that apply the last undone step again, in the order they should
+    /// be applied.
+    ///
+    /// The step is moved back to the undo history.
+    pub fn redo(&mut self) -> Option<Vec<StateSystemEvent>> {
+        self.close_group();
+        self.merging = false;
+
+        let step = self.redo_stack.pop()?;
+        let events = step.redo.clone();
+        self.undo_stack.push(step);
+
+        Some(events)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Remove all steps (i.e. when a different project is loaded).
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.group = None;
+        self.group_depth = 0;
+        self.merging = false;
+    }
+
+    /// Undoing or redoing in the middle of a group ends the group.
+    fn close_group(&mut self) {
+        if self.group_depth > 0 {
+            log::warn!("History group was not ended before undo/redo");
+            self.group_depth = 1;
+            self.end_group();
+        }
+    }
+
+    fn push_undo_step(&mut self, step: HistoryStep) {
+        if self.undo_stack.len() >= MAX_UNDO_STEPS {
+            self.undo_stack.remove(0);
+        }
+        self.undo_stack.push(step);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::event::TransportEvent;
+
+    fn event(rate: f64) -> StateSystemEvent {
+        StateSystemEvent::Transport(TransportEvent::SetPlaybackRate(rate))
+    }
+
+    fn rates(events: Option<Vec<StateSystemEvent>>) -> Vec<f64> {
+        events
+            .unwrap()
+            .into_iter()
+            .map(|e| match e {
+                StateSystemEvent::Transport(TransportEvent::SetPlaybackRate(rate)) => rate,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn history_groups() {
+        let mut history = History::new();
+        assert!(history.undo().is_none());
+
+        history.record(event(1.5), event(1.0));
+
+        history.begin_group();
+        history.record(event(2.0), event(1.5));
+        history.begin_group();
+        history.record(event(0.5), event(2.0));
+        history.end_group();
+        assert!(history.can_undo());
+        history.end_group();
+
+        // Both edits in the group are reverted in reverse order.
+        assert_eq!(rates(history.undo()), vec![2.0, 1.5]);
+        assert_eq!(rates(history.undo()), vec![1.0]);
+        assert!(!history.can_undo());
+
+        assert_eq!(rates(history.redo()), vec![1.5]);
+        assert_eq!(rates(history.redo()), vec![2.0, 0.5]);
+        assert!(!history.can_redo());
+
+        // A new edit clears the redo history.
+        history.undo();
+        history.record(event(0.25), event(1.5));
+        assert!(!history.can_redo());
+
+        // Empty groups don't add a step.
+        history.begin_group();
+        history.end_group();
+        assert_eq!(rates(history.undo()), vec![1.5]);
+    }
+    #[test]
+    fn merged_edits() {
+        let mut history = History::new();
+
+        history.record(event(1.5), event(1.0));
+        history.record_merged(event(2.0), event(1.5));
+        history.record_merged(event(2.5), event(2.0));
+        history.record_merged(event(3.0), event(2.5));
+
+        // The merged edits are a single step that goes back to the state before them.
+        assert_eq!(rates(history.undo()), vec![1.5]);
+        assert_eq!(rates(history.redo()), vec![3.0]);
+
+        // Undo/redo stops the merge.
+        history.record_merged(event(0.5), event(3.0));
+        history.end_merge();
+        history.record_merged(event(0.75), event(0.5));
+        history.record(event(1.0), event(0.75));
+        history.record_merged(event(1.25), event(1.0));
+
+        assert_eq!(rates(history.undo()), vec![1.0]);
+        assert_eq!(rates(history.undo()), vec![0.75]);
+        assert_eq!(rates(history.undo()), vec![0.5]);
+        assert_eq!(rates(history.undo()), vec![3.0]);
+        assert_eq!(rates(history.undo()), vec![1.5]);
+        assert_eq!(rates(history.undo()), vec![1.0]);
+        assert!(!history.can_undo());
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+
+use crate::backend::timeline::{AudioClipId, TimelineTrackId, TimelineTrackSaveState};
+use crate::backend::ResourceLoadError;
+
+/// The position of an audio clip in the project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioClipLocation {
+    pub track_id: TimelineTrackId,
+    pub clip_id: AudioClipId,
+}
+
+/// A media file used by the project that could not be loaded.
+#[derive(Debug, Clone)]
+pub struct MissingMedia {
+    pub path: PathBuf,
+    /// Why the file could not be loaded.
+    pub reason: String,
+    /// Every audio clip that uses this file. These clips play silence until the file
+    /// is relinked.
+    pub audio_clips: Vec<AudioClipLocation>,
+}
+
+/// All media files used by the project that could not be loaded.
+#[derive(Debug, Clone, Default)]
+pub struct MissingMediaReport {
+    pub missing: Vec<MissingMedia>,
+}
+
+impl MissingMediaReport {
+    /// Create a report from the errors that happened while loading a project.
+    pub fn new(errors: &[ResourceLoadError], timeline_tracks: &[TimelineTrackSaveState]) -> Self {
+        let mut report = Self::default();
+        report.insert_errors(errors, timeline_tracks);
+        report
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Add the files from the errors that happened while loading resources (i.e. when a
+    /// track was added).
+    pub(super) fn insert_errors(
+        &mut self,
+        errors: &[ResourceLoadError],
+        timeline_tracks: &[TimelineTrackSaveState],
+    ) {
+        for error in errors.iter() {
+            match error {
+                ResourceLoadError::PCM(e) => {
+                    self.insert(e.path(), e.to_string(), timeline_tracks);
+                }
+            }
+        }
+    }
+
+    /// Add the file with the given path to the report, along with every audio clip that
+    /// uses it. Nothing is added if no audio clip uses the file.
+    pub(super) fn insert(
+        &mut self,
+        path: &Path,
+        reason: String,
+        timeline_tracks: &[TimelineTrackSaveState],
+    ) {
+        if self.missing.iter().any(|m| m.path == path) {
+            return;
+        }
+
+        let audio_clips = audio_clips_using(path, timeline_tracks);
+        if !audio_clips.is_empty() {
+            self.missing.push(MissingMedia { path: path.to_path_buf(), reason, audio_clips });
+        }
+    }
+
+    pub(super) fn remove(&mut self, path: &Path) {
+        self.missing.retain(|m| m.path != path);
+    }
+
+    /// Find the audio clips that use each file again after tracks or clips were
+    /// removed. Files that are no longer used are removed from the report.
+    pub(super) fn update_audio_clips(&mut self, timeline_tracks: &[TimelineTrackSaveState]) {
+        for m in self.missing.iter_mut() {
+            m.audio_clips = audio_clips_using(&m.path, timeline_tracks);
+        }
+        self.missing.retain(|m| !m.audio_clips.is_empty());
+    }
+
+    /// Look for every missing file (by its file name) inside the given folder.
+    ///
+    /// Returns the old and the new path of every file that was found.
+    pub fn find_in_folder(&self, folder: &Path) -> Vec<(PathBuf, PathBuf)> {
+        self.missing
+            .iter()
+            .filter_map(|m| {
+                let new_path = folder.join(m.path.file_name()?);
+                if new_path.is_file() {
+                    Some((m.path.clone(), new_path))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Log every missing file.
+    pub fn log(&self) {
+        for m in self.missing.iter() {
+            log::warn!(
+                "Missing media file used by {} audio clip(s): {}",
+                m.audio_clips.len(),
+                m.reason
+            );
+        }
+    }
+}
+
+fn audio_clips_using(
+    path: &Path,
+    timeline_tracks: &[TimelineTrackSaveState],
+) -> Vec<AudioClipLocation> {
+    let mut audio_clips = Vec::new();
+    for track in timeline_tracks.iter() {
+        for clip in track.audio_clips.iter() {
+            if clip.pcm_path == path {
+                audio_clips.push(AudioClipLocation { track_id: track.id, clip_id: clip.id });
+            }
+        }
+    }
+    audio_clips
+}
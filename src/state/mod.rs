@@ -1,9 +1,20 @@
+mod autosave;
 mod bound_gui_state;
-mod project_save_state;
+mod collect;
+mod history;
+mod missing_media;
 mod state_system;
+mod tap_tempo;
 
 pub mod event;
 
+pub use autosave::{AUTOSAVE_EDIT_COUNT, AUTOSAVE_INTERVAL};
 pub use bound_gui_state::BoundGuiState;
-pub use project_save_state::ProjectSaveState;
+pub use collect::{
+    collect_project, CollectError, CollectFormat, CollectPlan, CollectSettings, CollectedFile,
+    COLLECTED_MEDIA_FOLDER,
+};
+pub use history::{History, MAX_UNDO_STEPS};
+pub use missing_media::{AudioClipLocation, MissingMedia, MissingMediaReport};
 pub use state_system::StateSystem;
+pub use tap_tempo::TapTempo;
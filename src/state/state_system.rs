@@ -1,22 +1,63 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+
 use cpal::Stream;
-use rusty_daw_audio_graph::{NodeRef, PortType};
+use rusty_daw_audio_graph::{CompilerWarning, NodeRef};
 use rusty_daw_core::SampleRate;
 use tuix::PropSet;
 use tuix::{BindEvent, Entity, State};
 
-use crate::backend::timeline::{TimelineTrackHandle, TimelineTrackNode};
+use crate::backend::dsp::dither::DitherMode;
+use crate::backend::export::ExportError;
+use crate::backend::timeline::{
+    AudioClipId, JumpMode, LoopState, MetronomeHandle, MetronomeNode, TimelineTrackHandle,
+    TimelineTrackId, TimelineTrackNode, TimelineTrackSaveState, MAX_BPM, MIN_BPM,
+};
 use crate::backend::{BackendHandle, ResourceLoadError};
 
+use super::autosave::{
+    is_significant_edit, newer_recovery_file, pending_recovery_path, recovery_path, Autosave,
+    AUTOSAVE_EDIT_COUNT, AUTOSAVE_INTERVAL,
+};
+use super::collect::{collect_project, CollectError, CollectPlan};
 use super::event::*;
-use super::{BoundGuiState, ProjectSaveState};
+use super::{BoundGuiState, History, MissingMediaReport, TapTempo};
+use crate::project::{
+    add_timeline_track_nodes, connect_to_root, export_project, export_stems, load_project_file,
+    save_project_file, ExportStatus, ProjectSaveState,
+};
 
 pub struct StateSystem {
     stream: Option<Stream>,
     backend_handle: Option<BackendHandle>,
     //event_queue: VecDeque<StateSystemEvent>,
     timeline_tracks: Vec<(NodeRef, TimelineTrackHandle)>,
+    metronome: Option<(NodeRef, MetronomeHandle)>,
+
+    tap_tempo: TapTempo,
+    history: History,
+
+    /// The file the current project was opened from or last saved to.
+    project_path: Option<PathBuf>,
+    autosave: Autosave,
+    edits_since_autosave: usize,
 
     sample_rate: SampleRate,
+
+    /// Additional folders to look for media files in when opening a project.
+    media_search_folders: Vec<PathBuf>,
+    /// The dither used when the audio output device only takes integer samples.
+    stream_dither: DitherMode,
+
+    /// Receives the progress of the running export.
+    export_receiver: Option<Receiver<ExportStatus>>,
+    /// Receives the progress of the running collect.
+    collect_receiver: Option<Receiver<ExportStatus>>,
+    /// Cancels the running collect when set.
+    collect_cancelled: Option<Arc<AtomicBool>>,
 }
 
 impl StateSystem {
@@ -26,8 +67,23 @@ impl StateSystem {
             backend_handle: None,
             //event_queue: VecDeque::with_capacity(EVENT_QUEUE_INITIAL_SIZE),
             timeline_tracks: Vec::new(),
+            metronome: None,
+
+            tap_tempo: TapTempo::new(),
+            history: History::new(),
+
+            project_path: None,
+            autosave: Autosave::new(AUTOSAVE_INTERVAL),
+            edits_since_autosave: 0,
 
             sample_rate: SampleRate::default(),
+
+            media_search_folders: Vec::new(),
+            stream_dither: DitherMode::default(),
+
+            export_receiver: None,
+            collect_receiver: None,
+            collect_cancelled: None,
         }
     }
 
@@ -38,6 +94,88 @@ impl StateSystem {
         entity: Entity,
         event: &mut StateSystemEvent,
     ) {
+        if let StateSystemEvent::History(event) = event {
+            self.on_history_event(bound_gui_state, state, entity, event);
+            return;
+        }
+
+        self.record_and_apply(bound_gui_state, state, entity, event, false);
+    }
+
+    /// Apply an event, and record it in the history if it edited the project.
+    ///
+    /// If `merge` is `true`, then the edit is merged into the previous step if that was
+    /// also recorded with `merge` (see `History::record_merged()`).
+    fn record_and_apply(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        state: &mut State,
+        entity: Entity,
+        event: &mut StateSystemEvent,
+        merge: bool,
+    ) {
+        // Find out how to revert the edit before it is made.
+        let inverse = self.inverse_event(bound_gui_state, event);
+        let redo_event = inverse.as_ref().map(|_| event.clone());
+
+        // Edits that fail are not recorded, so undo never reverts something that
+        // didn't happen.
+        if !self.apply_event(bound_gui_state, state, entity, event) {
+            return;
+        }
+
+        if let (Some(redo_event), Some(inverse)) = (redo_event, inverse) {
+            if merge {
+                self.history.record_merged(redo_event, inverse);
+            } else {
+                self.history.record(redo_event, inverse);
+            }
+            bound_gui_state.can_undo = self.history.can_undo();
+            bound_gui_state.can_redo = self.history.can_redo();
+            entity.emit(state, BindEvent::Update);
+
+            self.autosave(bound_gui_state, is_significant_edit(event));
+        }
+    }
+
+    /// Send the current state of the project to the autosave thread.
+    fn autosave(&mut self, bound_gui_state: &BoundGuiState, significant: bool) {
+        // Don't overwrite a recovery file the user has not restored or discarded yet.
+        let recovery_path = if let Some(recovery_file) = &bound_gui_state.recovery_file {
+            pending_recovery_path(recovery_file)
+        } else {
+            recovery_path(self.project_path.as_deref())
+        };
+
+        self.edits_since_autosave += 1;
+        let immediate = significant || self.edits_since_autosave >= AUTOSAVE_EDIT_COUNT;
+        if immediate {
+            self.edits_since_autosave = 0;
+        }
+
+        self.autosave.snapshot(&bound_gui_state.save_state, recovery_path, immediate);
+    }
+
+    /// Check if there is a recovery file that is newer than the current project, and
+    /// offer to restore it.
+    fn check_recovery_file(&mut self, bound_gui_state: &mut BoundGuiState) {
+        bound_gui_state.recovery_file = newer_recovery_file(self.project_path.as_deref());
+
+        if let Some(recovery_file) = &bound_gui_state.recovery_file {
+            log::info!("Found an autosave that is newer than the project: {:?}", recovery_file);
+        }
+    }
+
+    /// Apply an event without recording it in the history.
+    ///
+    /// Returns `false` if the event could not be applied.
+    fn apply_event(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        state: &mut State,
+        entity: Entity,
+        event: &mut StateSystemEvent,
+    ) -> bool {
         match event {
             StateSystemEvent::Transport(event) => {
                 self.on_transport_event(bound_gui_state, state, entity, event)
@@ -45,49 +183,425 @@ impl StateSystem {
             StateSystemEvent::Tempo(event) => {
                 self.on_tempo_event(bound_gui_state, state, entity, event)
             }
+            StateSystemEvent::Metronome(event) => {
+                self.on_metronome_event(bound_gui_state, state, entity, event);
+                true
+            }
+            StateSystemEvent::Track(event) => {
+                self.on_track_event(bound_gui_state, state, entity, event)
+            }
+            StateSystemEvent::Clip(event) => {
+                self.on_clip_event(bound_gui_state, state, entity, event)
+            }
             StateSystemEvent::Project(event) => {
-                self.on_project_event(bound_gui_state, state, entity, event)
+                self.on_project_event(bound_gui_state, state, entity, event);
+                true
+            }
+            StateSystemEvent::History(event) => {
+                self.on_history_event(bound_gui_state, state, entity, event);
+                true
+            }
+        }
+    }
+
+    pub fn on_history_event(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        state: &mut State,
+        entity: Entity,
+        event: &mut HistoryEvent,
+    ) {
+        match event {
+            HistoryEvent::Undo | HistoryEvent::Redo => {
+                let events = if let HistoryEvent::Undo = event {
+                    self.history.undo()
+                } else {
+                    self.history.redo()
+                };
+
+                if let Some(events) = events {
+                    for mut event in events.into_iter() {
+                        self.apply_event(bound_gui_state, state, entity, &mut event);
+                    }
+                    self.autosave(bound_gui_state, false);
+                }
             }
+            HistoryEvent::BeginGroup => self.history.begin_group(),
+            HistoryEvent::EndGroup => self.history.end_group(),
         }
+
+        bound_gui_state.can_undo = self.history.can_undo();
+        bound_gui_state.can_redo = self.history.can_redo();
+        entity.emit(state, BindEvent::Update);
+    }
+
+    /// Return the event that reverts the edit made by the given event.
+    ///
+    /// This returns `None` if the event does not edit the project, if the edit is not
+    /// valid, or if it would not change anything.
+    fn inverse_event(
+        &self,
+        bound_gui_state: &BoundGuiState,
+        event: &StateSystemEvent,
+    ) -> Option<StateSystemEvent> {
+        self.backend_handle.as_ref()?;
+
+        let save_state = &bound_gui_state.save_state;
+        let tempo_map = &save_state.backend.tempo_map;
+        let time_signature_map = &save_state.backend.time_signature_map;
+        let transport = &save_state.backend.timeline_transport;
+
+        let inverse = match event {
+            StateSystemEvent::Tempo(event) => match event {
+                TempoEvent::Tap(_) => return None,
+                TempoEvent::SetBPM(bpm) => {
+                    if bpm.clamp(MIN_BPM, MAX_BPM) == tempo_map.bpm() {
+                        return None;
+                    }
+                    TempoEvent::SetTempoMap(tempo_map.clone())
+                }
+                TempoEvent::SetGroove(groove) => {
+                    if groove == tempo_map.groove() {
+                        return None;
+                    }
+                    TempoEvent::SetTempoMap(tempo_map.clone())
+                }
+                TempoEvent::SetTempoMap(_) => TempoEvent::SetTempoMap(tempo_map.clone()),
+                TempoEvent::InsertTempoPoint(point) => {
+                    tempo_map.clone().insert_point(*point).ok()?;
+                    TempoEvent::SetTempoMap(tempo_map.clone())
+                }
+                TempoEvent::RemoveTempoPoint(index) => {
+                    tempo_map.clone().remove_point(*index).ok()?;
+                    TempoEvent::SetTempoMap(tempo_map.clone())
+                }
+                TempoEvent::SetTimeSignatureMap(_) => {
+                    TempoEvent::SetTimeSignatureMap(time_signature_map.clone())
+                }
+                TempoEvent::SetTimeSignature(time_signature) => {
+                    time_signature_map.clone().set_time_signature(*time_signature).ok()?;
+                    TempoEvent::SetTimeSignatureMap(time_signature_map.clone())
+                }
+                TempoEvent::InsertTimeSignaturePoint(point) => {
+                    time_signature_map.clone().insert_point(*point).ok()?;
+                    TempoEvent::SetTimeSignatureMap(time_signature_map.clone())
+                }
+                TempoEvent::RemoveTimeSignaturePoint(index) => {
+                    time_signature_map.clone().remove_point(*index).ok()?;
+                    TempoEvent::SetTimeSignatureMap(time_signature_map.clone())
+                }
+                TempoEvent::SetTrackGroove(track_id, groove) => {
+                    let track = &save_state.timeline_tracks[track_index(save_state, *track_id)?];
+                    if track.groove == *groove {
+                        return None;
+                    }
+                    TempoEvent::SetTrackGroove(*track_id, track.groove.clone())
+                }
+            }
+            .into(),
+            StateSystemEvent::Transport(event) => match event {
+                TransportEvent::SetLoopState(loop_state) => {
+                    if !loop_state.is_valid(tempo_map) {
+                        return None;
+                    }
+                    TransportEvent::SetLoopState(transport.loop_state)
+                }
+                TransportEvent::SetLoopFromMarkers(start_index, end_index) => {
+                    let loop_state = LoopState::Active {
+                        loop_start: transport.markers.get(*start_index)?.position,
+                        loop_end: transport.markers.get(*end_index)?.position,
+                    };
+                    if !loop_state.is_valid(tempo_map) {
+                        return None;
+                    }
+                    TransportEvent::SetLoopState(transport.loop_state)
+                }
+                TransportEvent::SetPunchState(punch_state) => {
+                    if !punch_state.is_valid(tempo_map) {
+                        return None;
+                    }
+                    TransportEvent::SetPunchState(transport.punch_state)
+                }
+                TransportEvent::AddMarker(marker) => {
+                    // This is where the marker will be inserted.
+                    let index =
+                        transport.markers.partition_point(|m| m.position.0 <= marker.position.0);
+                    TransportEvent::RemoveMarker(index)
+                }
+                TransportEvent::RemoveMarker(index) => {
+                    TransportEvent::AddMarker(transport.markers.get(*index)?.clone())
+                }
+                _ => return None,
+            }
+            .into(),
+            StateSystemEvent::Track(event) => match event {
+                TrackEvent::InsertTrack(_, track_save_state) => {
+                    if !ids_are_unused(save_state, track_save_state) {
+                        return None;
+                    }
+                    TrackEvent::RemoveTrack(track_save_state.id)
+                }
+                TrackEvent::RemoveTrack(track_id) => {
+                    let index = track_index(save_state, *track_id)?;
+                    TrackEvent::InsertTrack(index, save_state.timeline_tracks[index].clone())
+                }
+                TrackEvent::MoveTrack(track_id, _) => {
+                    TrackEvent::MoveTrack(*track_id, track_index(save_state, *track_id)?)
+                }
+                TrackEvent::SetName(track_id, name) => {
+                    let track = &save_state.timeline_tracks[track_index(save_state, *track_id)?];
+                    if track.name == *name {
+                        return None;
+                    }
+                    TrackEvent::SetName(*track_id, track.name.clone())
+                }
+            }
+            .into(),
+            StateSystemEvent::Clip(event) => {
+                let clips = |track_id: TimelineTrackId| {
+                    track_index(save_state, track_id)
+                        .map(|index| &save_state.timeline_tracks[index].audio_clips)
+                };
+                let clip = |track_id: TimelineTrackId, clip_id: AudioClipId| {
+                    clips(track_id)?.iter().find(|c| c.id == clip_id)
+                };
+
+                match event {
+                    ClipEvent::InsertAudioClip(track_id, _, clip) => {
+                        clips(*track_id)?;
+                        if contains_id(save_state, clip.id.raw()) {
+                            return None;
+                        }
+                        ClipEvent::RemoveAudioClip(*track_id, clip.id)
+                    }
+                    ClipEvent::RemoveAudioClip(track_id, clip_id) => {
+                        let clips = clips(*track_id)?;
+                        let index = clips.iter().position(|c| c.id == *clip_id)?;
+                        ClipEvent::InsertAudioClip(*track_id, index, clips[index].clone())
+                    }
+                    ClipEvent::SetName(track_id, clip_id, name) => {
+                        let clip = clip(*track_id, *clip_id)?;
+                        if clip.name == *name {
+                            return None;
+                        }
+                        ClipEvent::SetName(*track_id, *clip_id, clip.name.clone())
+                    }
+                    ClipEvent::SetTimelineStart(track_id, clip_id, timeline_start) => {
+                        let clip = clip(*track_id, *clip_id)?;
+                        if clip.timeline_start.0 == timeline_start.0 {
+                            return None;
+                        }
+                        ClipEvent::SetTimelineStart(*track_id, *clip_id, clip.timeline_start)
+                    }
+                    ClipEvent::SetDuration(track_id, clip_id, duration) => {
+                        let clip = clip(*track_id, *clip_id)?;
+                        if clip.duration.0 == duration.0 {
+                            return None;
+                        }
+                        ClipEvent::SetDuration(*track_id, *clip_id, clip.duration)
+                    }
+                    ClipEvent::SetClipStartOffset(track_id, clip_id, clip_start_offset) => {
+                        let clip = clip(*track_id, *clip_id)?;
+                        if clip.clip_start_offset.0 == clip_start_offset.0 {
+                            return None;
+                        }
+                        ClipEvent::SetClipStartOffset(*track_id, *clip_id, clip.clip_start_offset)
+                    }
+                    ClipEvent::SetGainDb(track_id, clip_id, gain_db) => {
+                        let clip = clip(*track_id, *clip_id)?;
+                        if clip.clip_gain_db == *gain_db {
+                            return None;
+                        }
+                        ClipEvent::SetGainDb(*track_id, *clip_id, clip.clip_gain_db)
+                    }
+                    ClipEvent::SetFades(track_id, clip_id, fades) => {
+                        let clip = clip(*track_id, *clip_id)?;
+                        if clip.fades.start_fade_duration.0 == fades.start_fade_duration.0
+                            && clip.fades.end_fade_duration.0 == fades.end_fade_duration.0
+                        {
+                            return None;
+                        }
+                        ClipEvent::SetFades(*track_id, *clip_id, clip.fades)
+                    }
+                }
+                .into()
+            }
+            StateSystemEvent::Metronome(_)
+            | StateSystemEvent::Project(_)
+            | StateSystemEvent::History(_) => return None,
+        };
+
+        Some(inverse)
     }
 
+    /// Returns `false` if the event could not be applied.
     pub fn on_tempo_event(
         &mut self,
         bound_gui_state: &mut BoundGuiState,
         state: &mut State,
         entity: Entity,
         event: &mut TempoEvent,
-    ) {
+    ) -> bool {
+        if let TempoEvent::Tap(time) = event {
+            if let Some(bpm) = self.tap_tempo.tap(*time) {
+                // Go through the history so the new tempo can be undone. Every tap of a
+                // measurement is undone in a single step.
+                self.record_and_apply(
+                    bound_gui_state,
+                    state,
+                    entity,
+                    &mut TempoEvent::SetBPM(bpm).into(),
+                    true,
+                );
+            } else {
+                // This tap starts a new measurement.
+                self.history.end_merge();
+            }
+            return true;
+        }
+
         if let Some(backend_handle) = &mut self.backend_handle {
             match event {
+                TempoEvent::Tap(_) => {}
                 TempoEvent::SetBPM(bpm) => {
-                    let bpm = if *bpm <= 0.0 { 0.1 } else { bpm.clamp(0.0, 100_000.0) };
+                    let bpm = bpm.clamp(MIN_BPM, MAX_BPM);
 
                     bound_gui_state.bpm = bpm;
                     backend_handle.set_bpm(bpm, &mut bound_gui_state.save_state.backend);
+                    self.sync_tempo_map(bound_gui_state);
+                }
+                TempoEvent::SetTempoMap(tempo_map) => {
+                    backend_handle
+                        .set_tempo_map(tempo_map.clone(), &mut bound_gui_state.save_state.backend);
+                    bound_gui_state.bpm = bound_gui_state.save_state.backend.tempo_map.bpm();
+                    self.sync_tempo_map(bound_gui_state);
+                }
+                TempoEvent::SetTimeSignatureMap(time_signature_map) => {
+                    bound_gui_state.time_signature = time_signature_map.time_signature();
+                    backend_handle.set_time_signature_map(
+                        time_signature_map.clone(),
+                        &mut bound_gui_state.save_state.backend,
+                    );
+                }
+                TempoEvent::InsertTempoPoint(point) => {
+                    let mut tempo_map = bound_gui_state.save_state.backend.tempo_map.clone();
+                    if tempo_map.insert_point(*point).is_err() {
+                        log::error!("Invalid tempo point: {:?}", point);
+                        return false;
+                    }
 
-                    entity.emit(state, BindEvent::Update);
+                    backend_handle
+                        .set_tempo_map(tempo_map, &mut bound_gui_state.save_state.backend);
+                    bound_gui_state.bpm = bound_gui_state.save_state.backend.tempo_map.bpm();
+                    self.sync_tempo_map(bound_gui_state);
+                }
+                TempoEvent::RemoveTempoPoint(index) => {
+                    let mut tempo_map = bound_gui_state.save_state.backend.tempo_map.clone();
+                    if tempo_map.remove_point(*index).is_err() {
+                        log::error!("Cannot remove tempo point at index {}", index);
+                        return false;
+                    }
+
+                    backend_handle
+                        .set_tempo_map(tempo_map, &mut bound_gui_state.save_state.backend);
+                    self.sync_tempo_map(bound_gui_state);
+                }
+                TempoEvent::SetGroove(groove) => {
+                    backend_handle
+                        .set_groove(groove.clone(), &mut bound_gui_state.save_state.backend);
+                    self.sync_tempo_map(bound_gui_state);
+                }
+                TempoEvent::SetTrackGroove(track_id, groove) => {
+                    let save_state = &mut bound_gui_state.save_state;
+                    if let Some(index) = track_index(save_state, *track_id) {
+                        self.timeline_tracks[index].1.set_groove(
+                            groove.clone(),
+                            &save_state.backend.tempo_map,
+                            &mut save_state.timeline_tracks[index],
+                        );
+                    } else {
+                        log::error!("No timeline track with ID {}", track_id);
+                        return false;
+                    }
+                }
+                TempoEvent::SetTimeSignature(time_signature) => {
+                    let mut time_signature_map =
+                        bound_gui_state.save_state.backend.time_signature_map.clone();
+                    if time_signature_map.set_time_signature(*time_signature).is_err() {
+                        log::error!("Invalid time signature: {}", time_signature);
+                        return false;
+                    }
+
+                    backend_handle.set_time_signature_map(
+                        time_signature_map,
+                        &mut bound_gui_state.save_state.backend,
+                    );
+                    bound_gui_state.time_signature = *time_signature;
+                }
+                TempoEvent::InsertTimeSignaturePoint(point) => {
+                    let mut time_signature_map =
+                        bound_gui_state.save_state.backend.time_signature_map.clone();
+                    if time_signature_map.insert_point(*point).is_err() {
+                        log::error!("Invalid time signature point: {:?}", point);
+                        return false;
+                    }
+
+                    bound_gui_state.time_signature = time_signature_map.time_signature();
+                    backend_handle.set_time_signature_map(
+                        time_signature_map,
+                        &mut bound_gui_state.save_state.backend,
+                    );
+                }
+                TempoEvent::RemoveTimeSignaturePoint(index) => {
+                    let mut time_signature_map =
+                        bound_gui_state.save_state.backend.time_signature_map.clone();
+                    if time_signature_map.remove_point(*index).is_err() {
+                        log::error!("Cannot remove time signature point at index {}", index);
+                        return false;
+                    }
+
+                    backend_handle.set_time_signature_map(
+                        time_signature_map,
+                        &mut bound_gui_state.save_state.backend,
+                    );
                 }
             }
+
+            entity.emit(state, BindEvent::Update);
+            true
+        } else {
+            false
         }
     }
 
+    /// Make sure all timeline tracks use the latest tempo map so clips stay at their
+    /// musical positions.
+    fn sync_tempo_map(&mut self, bound_gui_state: &BoundGuiState) {
+        let save_state = &bound_gui_state.save_state;
+        for ((_, track_handle), track_save_state) in
+            self.timeline_tracks.iter_mut().zip(save_state.timeline_tracks.iter())
+        {
+            track_handle.update_tempo_map(&save_state.backend.tempo_map, track_save_state);
+        }
+    }
+
+    /// Returns `false` if the event could not be applied.
     pub fn on_transport_event(
         &mut self,
         bound_gui_state: &mut BoundGuiState,
         state: &mut State,
         entity: Entity,
         event: &mut TransportEvent,
-    ) {
+    ) -> bool {
         if let Some(backend_handle) = &mut self.backend_handle {
             match event {
                 TransportEvent::Play => {
                     if !bound_gui_state.is_playing {
                         bound_gui_state.is_playing = true;
 
-                        let (transport, _) = backend_handle
+                        let (transport, save_state) = backend_handle
                             .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
-                        transport.set_playing(true);
+                        transport.set_playing(true, save_state);
 
                         entity.emit(state, BindEvent::Update);
                     }
@@ -97,9 +611,7 @@ impl StateSystem {
 
                     let (transport, save_state) = backend_handle
                         .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
-                    transport.set_playing(false);
-                    // TODO: have the transport struct handle this.
-                    transport.seek_to(0.0.into(), save_state);
+                    bound_gui_state.playhead = transport.stop(save_state);
 
                     entity.emit(state, BindEvent::Update);
                 }
@@ -107,15 +619,439 @@ impl StateSystem {
                     if bound_gui_state.is_playing {
                         bound_gui_state.is_playing = false;
 
-                        let (transport, _) = backend_handle
+                        let (transport, save_state) = backend_handle
                             .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
-                        transport.set_playing(false);
+                        transport.set_playing(false, save_state);
+
+                        entity.emit(state, BindEvent::Update);
+                    }
+                }
+                TransportEvent::AddMarker(marker) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    transport.add_marker(marker.clone(), save_state);
+
+                    entity.emit(state, BindEvent::Update);
+                }
+                TransportEvent::RemoveMarker(index) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    if transport.remove_marker(*index, save_state).is_none() {
+                        log::error!("No marker with index {}", index);
+                        return false;
+                    }
+
+                    entity.emit(state, BindEvent::Update);
+                }
+                TransportEvent::JumpToNextMarker(jump_mode) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    if let Some(position) = transport.jump_to_next_marker(*jump_mode, save_state) {
+                        // A jump at the next bar line only moves the playhead once the bar
+                        // line is reached.
+                        if *jump_mode == JumpMode::Immediate || !bound_gui_state.is_playing {
+                            bound_gui_state.playhead = position;
+                        }
 
                         entity.emit(state, BindEvent::Update);
                     }
                 }
+                TransportEvent::JumpToPreviousMarker(jump_mode) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    if let Some(position) =
+                        transport.jump_to_previous_marker(*jump_mode, save_state)
+                    {
+                        if *jump_mode == JumpMode::Immediate || !bound_gui_state.is_playing {
+                            bound_gui_state.playhead = position;
+                        }
+
+                        entity.emit(state, BindEvent::Update);
+                    }
+                }
+                TransportEvent::SetLoopFromMarkers(start_index, end_index) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    if transport
+                        .set_loop_from_markers(*start_index, *end_index, save_state)
+                        .is_err()
+                    {
+                        log::error!(
+                            "Cannot loop between markers with indexes {} and {}",
+                            start_index,
+                            end_index
+                        );
+                        return false;
+                    }
+
+                    entity.emit(state, BindEvent::Update);
+                }
+                TransportEvent::SetLoopState(loop_state) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    if transport.set_loop_state(*loop_state, save_state).is_err() {
+                        log::error!("Invalid loop range: {:?}", loop_state);
+                        return false;
+                    }
+
+                    entity.emit(state, BindEvent::Update);
+                }
+                TransportEvent::SetPunchState(punch_state) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    if transport.set_punch_state(*punch_state, save_state).is_err() {
+                        log::error!("Invalid punch range: {:?}", punch_state);
+                        return false;
+                    }
+
+                    entity.emit(state, BindEvent::Update);
+                }
+                TransportEvent::SetPlaybackRate(playback_rate) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    transport.set_playback_rate(*playback_rate, save_state);
+
+                    entity.emit(state, BindEvent::Update);
+                }
+                TransportEvent::SetStopBehavior(stop_behavior) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    transport.set_stop_behavior(*stop_behavior, save_state);
+
+                    entity.emit(state, BindEvent::Update);
+                }
+                TransportEvent::SetCountInMode(count_in_mode) => {
+                    let (transport, save_state) = backend_handle
+                        .timeline_transport_mut(&mut bound_gui_state.save_state.backend);
+                    transport.set_count_in_mode(*count_in_mode, save_state);
+
+                    entity.emit(state, BindEvent::Update);
+                }
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn on_metronome_event(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        state: &mut State,
+        entity: Entity,
+        event: &mut MetronomeEvent,
+    ) {
+        if let (Some(backend_handle), Some((_, metronome_handle))) =
+            (&self.backend_handle, &mut self.metronome)
+        {
+            let save_state = &mut bound_gui_state.save_state.backend.metronome;
+
+            match event {
+                MetronomeEvent::SetEnabled(enabled) => {
+                    metronome_handle.set_enabled(*enabled, save_state);
+                }
+                MetronomeEvent::SetGainDb(gain_db) => {
+                    metronome_handle.set_gain_db(*gain_db, save_state);
+                }
+                MetronomeEvent::LoadClickSample(click, pcm_path) => {
+                    if let Err(e) = metronome_handle.set_click_sample(
+                        *click,
+                        pcm_path.clone(),
+                        &backend_handle.resource_cache().resource_loader,
+                        save_state,
+                    ) {
+                        // TODO: Show this error in the GUI.
+                        log::error!("{}", e);
+                    }
+                }
+            }
+
+            entity.emit(state, BindEvent::Update);
+        }
+    }
+
+    /// Returns `false` if the event could not be applied.
+    pub fn on_track_event(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        state: &mut State,
+        entity: Entity,
+        event: &mut TrackEvent,
+    ) -> bool {
+        let backend_handle = if let Some(backend_handle) = &mut self.backend_handle {
+            backend_handle
+        } else {
+            return false;
+        };
+
+        let save_state = &mut bound_gui_state.save_state;
+
+        match event {
+            TrackEvent::InsertTrack(index, track_save_state) => {
+                if !ids_are_unused(save_state, track_save_state) {
+                    log::error!(
+                        "Timeline track {} or one of its clips already exists",
+                        track_save_state.id
+                    );
+                    return false;
+                }
+
+                let index = (*index).min(self.timeline_tracks.len());
+
+                // Load the files used by the track before the graph is modified, so the
+                // other tracks keep playing while they are read from disk.
+                let (timeline_track_node, timeline_track_handle, resource_load_errors) =
+                    TimelineTrackNode::new(
+                        track_save_state,
+                        backend_handle.resource_cache(),
+                        &save_state.backend.tempo_map,
+                        self.sample_rate,
+                        &backend_handle.coll_handle(),
+                    );
+
+                let res = backend_handle.modify_graph_or_revert(
+                    |mut graph, _| {
+                        let node_ref = graph.add_new_node(Box::new(timeline_track_node));
+                        if connect_to_root(&mut graph, node_ref).is_err() {
+                            let _ = graph.delete_node(node_ref);
+                            return None;
+                        }
+                        Some(node_ref)
+                    },
+                    |mut graph, _, node_ref| {
+                        if let Some(node_ref) = node_ref {
+                            let _ = graph.delete_node(node_ref);
+                        }
+                    },
+                );
+
+                let node_ref = match res {
+                    Ok((node_ref, warning)) => {
+                        show_graph_warning(warning, &mut bound_gui_state.graph_warning);
+                        node_ref
+                    }
+                    Err(e) => {
+                        log::error!("Failed to compile audio graph: {:?}", e);
+                        None
+                    }
+                };
+
+                if let Some(node_ref) = node_ref {
+                    self.timeline_tracks.insert(index, (node_ref, timeline_track_handle));
+                    save_state.timeline_tracks.insert(index, track_save_state.clone());
+                } else {
+                    // TODO: Show this error in the GUI.
+                    log::error!("Failed to add timeline track {}", track_save_state.id);
+                    return false;
+                }
+
+                bound_gui_state
+                    .missing_media
+                    .insert_errors(&resource_load_errors, &save_state.timeline_tracks);
+            }
+            TrackEvent::RemoveTrack(track_id) => {
+                let index = if let Some(index) = track_index(save_state, *track_id) {
+                    index
+                } else {
+                    log::error!("No timeline track with ID {}", track_id);
+                    return false;
+                };
+
+                let node_ref = self.timeline_tracks[index].0;
+                let tempo_map = &save_state.backend.tempo_map;
+                let track_save_state = &save_state.timeline_tracks[index];
+                let sample_rate = self.sample_rate;
+                let mut restored_track = None;
+
+                // Only this node is removed. The nodes of the other tracks (and the
+                // state of their clips) are kept as they are.
+                let res = backend_handle.modify_graph_or_revert(
+                    |mut graph, _| graph.delete_node(node_ref).is_ok(),
+                    |mut graph, resource_cache, deleted| {
+                        // The old node is gone, so the track is added again from its
+                        // save state.
+                        if deleted {
+                            if let Ok((mut timeline_tracks, _)) = add_timeline_track_nodes(
+                                &mut graph,
+                                resource_cache,
+                                std::slice::from_ref(track_save_state),
+                                tempo_map,
+                                sample_rate,
+                            ) {
+                                restored_track = timeline_tracks.pop();
+                            }
+                        }
+                    },
+                );
+
+                match res {
+                    Ok((true, warning)) => {
+                        show_graph_warning(warning, &mut bound_gui_state.graph_warning);
+                    }
+                    Ok((false, _)) => {
+                        log::error!("Failed to remove timeline track node from the graph");
+                        return false;
+                    }
+                    Err(e) => {
+                        // TODO: Show this error in the GUI.
+                        log::error!("Failed to compile audio graph: {:?}", e);
+                        if let Some(restored_track) = restored_track {
+                            self.timeline_tracks[index] = restored_track;
+                        }
+                        return false;
+                    }
+                }
+
+                self.timeline_tracks.remove(index);
+                save_state.timeline_tracks.remove(index);
+
+                bound_gui_state.missing_media.update_audio_clips(&save_state.timeline_tracks);
+            }
+            TrackEvent::MoveTrack(track_id, to) => {
+                let from = if let Some(index) = track_index(save_state, *track_id) {
+                    index
+                } else {
+                    log::error!("No timeline track with ID {}", track_id);
+                    return false;
+                };
+
+                // Every track is mixed into the root node the same way, so the order only
+                // matters to the handles and the save state. The graph is left alone.
+                let to = (*to).min(self.timeline_tracks.len() - 1);
+                let timeline_track = self.timeline_tracks.remove(from);
+                self.timeline_tracks.insert(to, timeline_track);
+                let track_save_state = save_state.timeline_tracks.remove(from);
+                save_state.timeline_tracks.insert(to, track_save_state);
+            }
+            TrackEvent::SetName(track_id, name) => {
+                if let Some(index) = track_index(save_state, *track_id) {
+                    self.timeline_tracks[index]
+                        .1
+                        .set_name(name.clone(), &mut save_state.timeline_tracks[index]);
+                } else {
+                    log::error!("No timeline track with ID {}", track_id);
+                    return false;
+                }
+            }
+        }
+
+        entity.emit(state, BindEvent::Update);
+        true
+    }
+
+    /// Returns `false` if the event could not be applied.
+    pub fn on_clip_event(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        state: &mut State,
+        entity: Entity,
+        event: &mut ClipEvent,
+    ) -> bool {
+        let backend_handle = if let Some(backend_handle) = &self.backend_handle {
+            backend_handle
+        } else {
+            return false;
+        };
+
+        let save_state = &mut bound_gui_state.save_state;
+        let tempo_map = &save_state.backend.tempo_map;
+
+        let track_id = match event {
+            ClipEvent::InsertAudioClip(track_id, _, _)
+            | ClipEvent::RemoveAudioClip(track_id, _)
+            | ClipEvent::SetName(track_id, _, _)
+            | ClipEvent::SetTimelineStart(track_id, _, _)
+            | ClipEvent::SetDuration(track_id, _, _)
+            | ClipEvent::SetClipStartOffset(track_id, _, _)
+            | ClipEvent::SetGainDb(track_id, _, _)
+            | ClipEvent::SetFades(track_id, _, _) => *track_id,
+        };
+        let index = if let Some(index) = track_index(save_state, track_id) {
+            index
+        } else {
+            log::error!("No timeline track with ID {}", track_id);
+            return false;
+        };
+
+        if let ClipEvent::InsertAudioClip(_, _, clip) = event {
+            if contains_id(save_state, clip.id.raw()) {
+                log::error!("Audio clip {} already exists", clip.id);
+                return false;
+            }
+        }
+
+        let track_handle = &mut self.timeline_tracks[index].1;
+        let track_save_state = &mut save_state.timeline_tracks[index];
+
+        let mut load_error = None;
+
+        match event {
+            ClipEvent::InsertAudioClip(_, clip_index, clip) => {
+                if let Err(e) = track_handle.insert_audio_clip(
+                    *clip_index,
+                    clip.clone(),
+                    backend_handle.resource_cache(),
+                    tempo_map,
+                    track_save_state,
+                ) {
+                    // The clip is still added, but plays silence.
+                    log::error!("{}", e);
+                    load_error = Some(e);
+                }
+            }
+            ClipEvent::RemoveAudioClip(_, clip_id) => {
+                if track_handle.remove_audio_clip(*clip_id, track_save_state).is_err() {
+                    log::error!("No audio clip with ID {} on track {}", clip_id, track_id);
+                    return false;
+                }
+            }
+            ClipEvent::SetName(_, clip_id, _)
+            | ClipEvent::SetTimelineStart(_, clip_id, _)
+            | ClipEvent::SetDuration(_, clip_id, _)
+            | ClipEvent::SetClipStartOffset(_, clip_id, _)
+            | ClipEvent::SetGainDb(_, clip_id, _)
+            | ClipEvent::SetFades(_, clip_id, _) => {
+                let (clip_handle, clip_save_state) =
+                    if let Some(clip) = track_handle.audio_clip_mut(*clip_id, track_save_state) {
+                        clip
+                    } else {
+                        log::error!("No audio clip with ID {} on track {}", clip_id, track_id);
+                        return false;
+                    };
+
+                match event {
+                    ClipEvent::SetName(_, _, name) => {
+                        clip_handle.set_name(name.clone(), clip_save_state)
+                    }
+                    ClipEvent::SetTimelineStart(_, _, timeline_start) => {
+                        clip_handle.set_timeline_start(*timeline_start, tempo_map, clip_save_state)
+                    }
+                    ClipEvent::SetDuration(_, _, duration) => {
+                        clip_handle.set_duration(*duration, tempo_map, clip_save_state)
+                    }
+                    ClipEvent::SetClipStartOffset(_, _, clip_start_offset) => clip_handle
+                        .set_clip_start_offset(*clip_start_offset, tempo_map, clip_save_state),
+                    ClipEvent::SetGainDb(_, _, gain_db) => {
+                        clip_handle.set_clip_gain_db(*gain_db, clip_save_state);
+                    }
+                    ClipEvent::SetFades(_, _, fades) => {
+                        clip_handle.set_fades(*fades, tempo_map, clip_save_state)
+                    }
+                    _ => unreachable!(),
+                }
             }
         }
+
+        let missing_media = &mut bound_gui_state.missing_media;
+        if let Some(e) = load_error {
+            missing_media.insert(e.path(), e.to_string(), &save_state.timeline_tracks);
+        }
+        if let ClipEvent::RemoveAudioClip(..) = event {
+            missing_media.update_audio_clips(&save_state.timeline_tracks);
+        }
+
+        entity.emit(state, BindEvent::Update);
+        true
     }
 
     pub fn on_project_event(
@@ -127,11 +1063,337 @@ impl StateSystem {
     ) {
         match event {
             ProjectEvent::LoadProject(project_save_state) => {
-                self.load_project(bound_gui_state, project_save_state, state, entity)
+                self.project_path = None;
+                self.load_project(bound_gui_state, project_save_state, state, entity);
+                self.check_recovery_file(bound_gui_state);
+            }
+            ProjectEvent::OpenProject(path) => {
+                match load_project_file(path, &self.media_search_folders) {
+                    Ok(project_save_state) => {
+                        log::info!("Opened project {:?}", path);
+                        self.project_path = Some(path.clone());
+                        self.load_project(
+                            bound_gui_state,
+                            &Box::new(project_save_state),
+                            state,
+                            entity,
+                        );
+                        self.check_recovery_file(bound_gui_state);
+                    }
+                    Err(e) => {
+                        log::error!("{}", e);
+
+                        // If no project is open yet (i.e. the file given at startup
+                        // could not be opened), start with an empty project instead.
+                        if self.backend_handle.is_none() {
+                            self.project_path = None;
+                            self.load_project(
+                                bound_gui_state,
+                                &Box::new(ProjectSaveState::new_empty()),
+                                state,
+                                entity,
+                            );
+                        }
+
+                        bound_gui_state.project_error = Some(e.to_string());
+                        entity.emit(state, BindEvent::Update);
+                    }
+                }
+            }
+            ProjectEvent::RestoreRecovery => {
+                if let Some(recovery_file) = bound_gui_state.recovery_file.take() {
+                    // The edits that were autosaved while the user was deciding are
+                    // replaced by the restored project.
+                    self.autosave.discard(pending_recovery_path(&recovery_file));
+
+                    match load_project_file(&recovery_file, &self.media_search_folders) {
+                        Ok(project_save_state) => {
+                            log::info!("Restored project from {:?}", recovery_file);
+                            // The project is still saved to its original file.
+                            self.load_project(
+                                bound_gui_state,
+                                &Box::new(project_save_state),
+                                state,
+                                entity,
+                            );
+
+                            // The restored project is not saved yet, so it is kept in
+                            // this session's recovery file from now on. A recovery file
+                            // left behind by another session is not needed anymore once
+                            // that is written.
+                            let recovery_path = recovery_path(self.project_path.as_deref());
+                            self.autosave.snapshot(
+                                &bound_gui_state.save_state,
+                                recovery_path.clone(),
+                                true,
+                            );
+                            if recovery_file != recovery_path {
+                                self.autosave.discard(recovery_file);
+                            }
+                        }
+                        Err(e) => log::error!("{}", e),
+                    }
+                    entity.emit(state, BindEvent::Update);
+                }
+            }
+            ProjectEvent::DiscardRecovery => {
+                if let Some(recovery_file) = bound_gui_state.recovery_file.take() {
+                    self.autosave.discard(pending_recovery_path(&recovery_file));
+                    self.autosave.discard(recovery_file);
+
+                    // Keep the edits that were made while the user was deciding in the
+                    // regular recovery file.
+                    if self.history.can_undo() {
+                        self.edits_since_autosave = 0;
+                        self.autosave.snapshot(
+                            &bound_gui_state.save_state,
+                            recovery_path(self.project_path.as_deref()),
+                            true,
+                        );
+                    }
+                    entity.emit(state, BindEvent::Update);
+                }
+            }
+            ProjectEvent::SetMediaSearchFolders(folders) => {
+                self.media_search_folders = folders.clone();
+            }
+            ProjectEvent::SetStreamDither(dither) => {
+                self.stream_dither = *dither;
+            }
+            ProjectEvent::RelinkMedia(from, to) => {
+                self.relink_media(bound_gui_state, &[(from.clone(), to.clone())]);
+                entity.emit(state, BindEvent::Update);
+            }
+            ProjectEvent::RelinkMediaFolder(folder) => {
+                let relinks = bound_gui_state.missing_media.find_in_folder(folder);
+                if relinks.is_empty() {
+                    log::warn!("No missing media files found in {:?}", folder);
+                    return;
+                }
+
+                self.relink_media(bound_gui_state, &relinks);
+                entity.emit(state, BindEvent::Update);
+            }
+            ProjectEvent::SaveProject(path) => {
+                match save_project_file(path, &bound_gui_state.save_state) {
+                    Ok(()) => {
+                        log::info!("Saved project to {:?}", path);
+
+                        // The autosave is not needed anymore.
+                        if let Some(recovery_file) = &bound_gui_state.recovery_file {
+                            self.autosave.discard(pending_recovery_path(recovery_file));
+                        }
+                        self.autosave.discard(recovery_path(self.project_path.as_deref()));
+                        self.project_path = Some(path.clone());
+                        self.edits_since_autosave = 0;
+                    }
+                    Err(e) => log::error!("{}", e),
+                }
+            }
+            ProjectEvent::PlanCollect(settings) => {
+                bound_gui_state.collect_plan = match CollectPlan::new(&bound_gui_state.save_state) {
+                    Ok(plan) => {
+                        log::info!(
+                            "Found {} media file(s) ({:.1} MB) to collect to {:?}",
+                            plan.files.len(),
+                            plan.total_size as f64 / 1_000_000.0,
+                            settings.path
+                        );
+                        Some((settings.clone(), plan))
+                    }
+                    Err(e) => {
+                        log::error!("{}", e);
+                        None
+                    }
+                };
+                entity.emit(state, BindEvent::Update);
+            }
+            ProjectEvent::CancelCollect => {
+                if bound_gui_state.collect_plan.take().is_some() {
+                    entity.emit(state, BindEvent::Update);
+                } else if let (Some(_), Some(cancelled)) =
+                    (&self.collect_receiver, &self.collect_cancelled)
+                {
+                    log::info!("Cancelling collect");
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+            }
+            ProjectEvent::ConfirmCollect => {
+                if self.collect_receiver.is_some() {
+                    log::warn!("Cannot collect while another collect is running");
+                    return;
+                }
+
+                let (settings, plan) =
+                    if let Some(collect_plan) = bound_gui_state.collect_plan.take() {
+                        collect_plan
+                    } else {
+                        return;
+                    };
+
+                let project_save_state = bound_gui_state.save_state.clone();
+
+                // The project may have been edited since the plan was made.
+                let plan_is_current = match CollectPlan::new(&project_save_state) {
+                    Ok(current)
+                        if current
+                            .files
+                            .iter()
+                            .map(|f| &f.source)
+                            .eq(plan.files.iter().map(|f| &f.source)) =>
+                    {
+                        true
+                    }
+                    Ok(current) => {
+                        log::warn!(
+                            "The media files used by the project changed, please confirm again"
+                        );
+                        bound_gui_state.collect_plan = Some((settings.clone(), current));
+                        false
+                    }
+                    Err(e) => {
+                        log::error!("{}", e);
+                        false
+                    }
+                };
+                entity.emit(state, BindEvent::Update);
+                if !plan_is_current {
+                    return;
+                }
+
+                log::info!("Collecting project to {:?}", settings.path);
+
+                let cancelled = Arc::new(AtomicBool::new(false));
+                self.collect_cancelled = Some(Arc::clone(&cancelled));
+                self.collect_receiver = Some(spawn_job(
+                    move |progress| -> Result<(), CollectError> {
+                        collect_project(&project_save_state, &plan, &settings, progress)?;
+                        log::info!("Collected project to {:?}", settings.path);
+                        Ok(())
+                    },
+                    Some(cancelled),
+                ));
+                bound_gui_state.collect_status = Some(ExportStatus::Running(0.0));
+                entity.emit(state, BindEvent::Update);
+            }
+            ProjectEvent::PollCollect => {
+                if poll_job(&mut self.collect_receiver, &mut bound_gui_state.collect_status) {
+                    entity.emit(state, BindEvent::Update);
+                }
+            }
+            ProjectEvent::Export(settings) => {
+                if self.export_receiver.is_some() {
+                    log::warn!("Cannot export while another export is running");
+                    return;
+                }
+
+                let project_save_state = bound_gui_state.save_state.clone();
+                let settings = settings.clone();
+
+                log::info!("Exporting project to {:?}", settings.path);
+                self.spawn_export(bound_gui_state, move |progress| {
+                    export_project(&project_save_state, &settings, progress)?;
+                    log::info!("Exported project to {:?}", settings.path);
+                    Ok(())
+                });
+                entity.emit(state, BindEvent::Update);
+            }
+            ProjectEvent::ExportStems(settings) => {
+                if self.export_receiver.is_some() {
+                    log::warn!("Cannot export while another export is running");
+                    return;
+                }
+
+                let project_save_state = bound_gui_state.save_state.clone();
+                let settings = settings.clone();
+
+                log::info!("Exporting stems to {:?}", settings.directory);
+                self.spawn_export(bound_gui_state, move |progress| {
+                    export_stems(&project_save_state, &settings, progress)?;
+                    log::info!("Exported stems to {:?}", settings.directory);
+                    Ok(())
+                });
+                entity.emit(state, BindEvent::Update);
+            }
+            ProjectEvent::PollExport => {
+                if poll_job(&mut self.export_receiver, &mut bound_gui_state.export_status) {
+                    entity.emit(state, BindEvent::Update);
+                }
+            }
+        }
+    }
+
+    /// Run an export on a separate thread so the GUI doesn't freeze. Its progress and
+    /// result are picked up by `ProjectEvent::PollExport`.
+    fn spawn_export<F>(&mut self, bound_gui_state: &mut BoundGuiState, export: F)
+    where
+        F: FnOnce(&mut dyn FnMut(f32) -> bool) -> Result<(), ExportError> + Send + 'static,
+    {
+        self.export_receiver = Some(spawn_job(export, None));
+        bound_gui_state.export_status = Some(ExportStatus::Running(0.0));
+    }
+
+    /// Replace the files used by audio clips while the project is running. Each entry is
+    /// the old path followed by the new path.
+    fn relink_media(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        relinks: &[(PathBuf, PathBuf)],
+    ) {
+        let resource_cache = if let Some(backend_handle) = &self.backend_handle {
+            backend_handle.resource_cache()
+        } else {
+            return;
+        };
+
+        for (from, to) in relinks.iter() {
+            let mut error = None;
+
+            for ((_, track_handle), track_save_state) in self
+                .timeline_tracks
+                .iter_mut()
+                .zip(bound_gui_state.save_state.timeline_tracks.iter_mut())
+            {
+                let clip_ids: Vec<AudioClipId> = track_save_state
+                    .audio_clips
+                    .iter()
+                    .filter(|clip| clip.pcm_path == *from)
+                    .map(|clip| clip.id)
+                    .collect();
+
+                for clip_id in clip_ids.into_iter() {
+                    if let Some((clip_handle, clip_save_state)) =
+                        track_handle.audio_clip_mut(clip_id, track_save_state)
+                    {
+                        if let Err(e) = clip_handle.set_pcm(
+                            to.clone(),
+                            &resource_cache.resource_loader,
+                            &resource_cache.audio_clip_resource_cache,
+                            clip_save_state,
+                        ) {
+                            error = Some(e);
+                        }
+                    }
+                }
+            }
+
+            let missing_media = &mut bound_gui_state.missing_media;
+            missing_media.remove(from);
+            if let Some(e) = error {
+                log::error!("{}", e);
+                missing_media.insert(
+                    to,
+                    e.to_string(),
+                    &bound_gui_state.save_state.timeline_tracks,
+                );
+            } else {
+                log::info!("Relinked media file {:?} to {:?}", from, to);
             }
         }
     }
 
+    /// Load a project. If it cannot be loaded, an empty project is loaded instead and
+    /// the error is shown to the user.
     fn load_project(
         &mut self,
         bound_gui_state: &mut BoundGuiState,
@@ -139,6 +1401,35 @@ impl StateSystem {
         state: &mut State,
         entity: Entity,
     ) {
+        bound_gui_state.project_error = None;
+
+        if let Err(e) = self.try_load_project(bound_gui_state, project_save_state, state, entity) {
+            log::error!("{}", e);
+
+            // Don't leave the user without a project. The empty project is not saved over
+            // the file the failed project was opened from.
+            self.project_path = None;
+            if let Err(e) = self.try_load_project(
+                bound_gui_state,
+                &Box::new(ProjectSaveState::new_empty()),
+                state,
+                entity,
+            ) {
+                log::error!("{}", e);
+            }
+
+            bound_gui_state.project_error = Some(e);
+            entity.emit(state, BindEvent::Update);
+        }
+    }
+
+    fn try_load_project(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        project_save_state: &Box<ProjectSaveState>,
+        state: &mut State,
+        entity: Entity,
+    ) -> Result<(), String> {
         let mut update_gui = || {
             entity.emit(state, BindEvent::Update);
         };
@@ -148,11 +1439,18 @@ impl StateSystem {
 
         bound_gui_state.backend_loaded = false;
         bound_gui_state.is_playing = false;
+        bound_gui_state.missing_media = MissingMediaReport::default();
+        bound_gui_state.collect_plan = None;
+        self.history.clear();
+        bound_gui_state.can_undo = false;
+        bound_gui_state.can_redo = false;
         update_gui();
 
         // This will drop and automatically close any active backend/stream.
         self.backend_handle = None;
         self.stream = None;
+        self.timeline_tracks.clear();
+        self.metronome = None;
 
         // This function is temporary. Eventually we should use rusty-daw-io instead.
         let sample_rate =
@@ -168,58 +1466,177 @@ impl StateSystem {
         let mut resource_load_errors: Vec<ResourceLoadError> = Vec::new();
 
         // This function is temporary. Eventually we should use rusty-daw-io instead.
-        if let Ok(stream) = crate::backend::rt_thread::run_with_default_output(rt_state) {
+        if let Ok(stream) =
+            crate::backend::rt_thread::run_with_default_output(rt_state, self.stream_dither)
+        {
             bound_gui_state.bpm = project_save_state.backend.tempo_map.bpm();
+            bound_gui_state.time_signature =
+                project_save_state.backend.time_signature_map.time_signature();
+            bound_gui_state.playhead = project_save_state.backend.timeline_transport.seek_to;
             update_gui();
 
-            // TODO: errors and reverting to previous working state
-            let _ = backend_handle.modify_graph(|mut graph, resource_cache| {
-                let root_node_ref = graph.root_node();
-
-                for timeline_track_save_state in project_save_state.timeline_tracks.iter() {
-                    let (timeline_track_node, timeline_track_handle, mut res) =
-                        TimelineTrackNode::new(
-                            timeline_track_save_state,
-                            resource_cache,
-                            &project_save_state.backend.tempo_map,
-                            sample_rate,
-                            graph.coll_handle(),
-                        );
+            let tempo_map = bound_gui_state.save_state.backend.tempo_map.clone();
+
+            let mut connected = true;
+            let res = backend_handle.modify_graph(|mut graph, resource_cache| {
+                let (mut timeline_tracks, mut res) = if let Ok(nodes) = add_timeline_track_nodes(
+                    &mut graph,
+                    resource_cache,
+                    &project_save_state.timeline_tracks,
+                    &tempo_map,
+                    sample_rate,
+                ) {
+                    nodes
+                } else {
+                    connected = false;
+                    return;
+                };
 
-                    // Append any errors that happened while loading resources.
-                    resource_load_errors.append(&mut res);
+                // Append any errors that happened while loading resources.
+                resource_load_errors.append(&mut res);
 
-                    // Add the track node to the graph.
-                    let timeline_track_node_ref = graph.add_new_node(Box::new(timeline_track_node));
+                // Keep a reference and a handle to the track nodes.
+                self.timeline_tracks.append(&mut timeline_tracks);
 
-                    // Keep a reference and a handle to the track node.
-                    self.timeline_tracks.push((timeline_track_node_ref, timeline_track_handle));
+                // TODO: GUI stuff
 
-                    // Connect the track node to the root node.
-                    graph
-                        .connect_ports(
-                            PortType::StereoAudio,
-                            timeline_track_node_ref,
-                            0,
-                            root_node_ref,
-                            0,
-                        )
-                        .unwrap();
+                let (metronome_node, metronome_handle, mut res) = MetronomeNode::new(
+                    &project_save_state.backend.metronome,
+                    resource_cache,
+                    sample_rate,
+                    graph.coll_handle(),
+                );
+                resource_load_errors.append(&mut res);
 
-                    // TODO: GUI stuff
+                // Add the metronome node to the graph and connect it to the root node.
+                let metronome_node_ref = graph.add_new_node(Box::new(metronome_node));
+                self.metronome = Some((metronome_node_ref, metronome_handle));
+                if connect_to_root(&mut graph, metronome_node_ref).is_err() {
+                    connected = false;
                 }
             });
 
+            let res = match res {
+                Ok(warning) => {
+                    show_graph_warning(warning, &mut bound_gui_state.graph_warning);
+                    if connected {
+                        Ok(())
+                    } else {
+                        Err("Failed to connect the audio graph of the project".to_string())
+                    }
+                }
+                Err(e) => Err(format!("Failed to compile the audio graph of the project: {:?}", e)),
+            };
+            if res.is_err() {
+                // There is no working graph to go back to, so the backend is closed again
+                // instead of playing only part of the project.
+                self.timeline_tracks.clear();
+                self.metronome = None;
+                return res;
+            }
+
             self.backend_handle = Some(backend_handle);
             self.stream = Some(stream);
             self.sample_rate = sample_rate;
 
+            // Clips whose files could not be loaded play silence until they are relinked.
+            bound_gui_state.missing_media = MissingMediaReport::new(
+                &resource_load_errors,
+                &bound_gui_state.save_state.timeline_tracks,
+            );
+            bound_gui_state.missing_media.log();
+
             bound_gui_state.backend_loaded = true;
+            Ok(())
         } else {
-            // TODO: Better errors
-            log::error!("Failed to start audio stream");
-            // TODO: Remove this panic
-            panic!("Failed to start audio stream");
+            Err("Failed to start audio stream".to_string())
+        }
+    }
+}
+
+/// Run a long job (such as an export) on a separate thread so the GUI doesn't freeze.
+///
+/// Returns the receiver of its progress and result. The job is cancelled once `cancelled`
+/// is set.
+fn spawn_job<F, E>(job: F, cancelled: Option<Arc<AtomicBool>>) -> Receiver<ExportStatus>
+where
+    F: FnOnce(&mut dyn FnMut(f32) -> bool) -> Result<(), E> + Send + 'static,
+    E: Display,
+{
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        // Only send an update for every whole percent.
+        let mut last_percent = 0;
+        let mut progress = |p: f32| {
+            let percent = (p * 100.0) as u32;
+            if percent != last_percent {
+                last_percent = percent;
+                // Nothing is listening anymore if a new job was started.
+                let _ = sender.send(ExportStatus::Running(p));
+            }
+            !cancelled.as_ref().map(|c| c.load(Ordering::Relaxed)).unwrap_or(false)
+        };
+
+        let status = match job(&mut progress) {
+            Ok(()) => ExportStatus::Finished,
+            Err(e) => {
+                log::error!("{}", e);
+                ExportStatus::Failed(e.to_string())
+            }
+        };
+        let _ = sender.send(status);
+    });
+
+    receiver
+}
+
+/// Update `status` with everything received from a running job, and drop the receiver
+/// once the job is done.
+///
+/// Returns `true` if the status changed.
+fn poll_job(
+    receiver: &mut Option<Receiver<ExportStatus>>,
+    status: &mut Option<ExportStatus>,
+) -> bool {
+    let mut updated = false;
+    let mut done = false;
+    if let Some(receiver) = receiver {
+        for s in receiver.try_iter() {
+            done = !matches!(s, ExportStatus::Running(_));
+            *status = Some(s);
+            updated = true;
         }
     }
+
+    if done {
+        *receiver = None;
+    }
+    updated
+}
+
+/// Show the warning from compiling the audio graph to the user.
+fn show_graph_warning(warning: Option<CompilerWarning>, graph_warning: &mut Option<String>) {
+    if let Some(warning) = &warning {
+        log::warn!("Audio graph compiled with a warning: {:?}", warning);
+    }
+    *graph_warning = warning.map(|w| format!("{:?}", w));
+}
+
+/// The index of the timeline track with the given ID.
+fn track_index(save_state: &ProjectSaveState, track_id: TimelineTrackId) -> Option<usize> {
+    save_state.timeline_tracks.iter().position(|track| track.id == track_id)
+}
+
+/// Whether a track or clip in the project uses the given ID.
+fn contains_id(save_state: &ProjectSaveState, id: u64) -> bool {
+    save_state.timeline_tracks.iter().any(|track| {
+        track.id.raw() == id || track.audio_clips.iter().any(|clip| clip.id.raw() == id)
+    })
+}
+
+/// Whether none of the IDs of the track and its clips are used in the project yet.
+fn ids_are_unused(save_state: &ProjectSaveState, track: &TimelineTrackSaveState) -> bool {
+    !contains_id(save_state, track.id.raw())
+        && track.audio_clips.iter().all(|clip| !contains_id(save_state, clip.id.raw()))
 }
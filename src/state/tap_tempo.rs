@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// If no tap happens within this time, then the next tap starts a new measurement.
+pub static TAP_TEMPO_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The maximum number of intervals between taps used to estimate the tempo.
+pub static TAP_TEMPO_MAX_INTERVALS: usize = 8;
+
+/// Intervals which differ from the median interval by more than this ratio are
+/// considered to be outliers (i.e. a missed or double tap).
+static OUTLIER_RATIO: f64 = 0.3;
+
+/// How much a new estimate moves the smoothed tempo (between `0.0` and `1.0`).
+static SMOOTHING: f64 = 0.5;
+
+/// Estimates a tempo from the times a user taps a button.
+pub struct TapTempo {
+    last_tap: Option<Instant>,
+    intervals: VecDeque<f64>,
+
+    smoothed_bpm: Option<f64>,
+
+    // Allocated once so finding the median doesn't allocate on every tap.
+    sorted: Vec<f64>,
+}
+
+impl Default for TapTempo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TapTempo {
+    pub fn new() -> Self {
+        Self {
+            last_tap: None,
+            intervals: VecDeque::with_capacity(TAP_TEMPO_MAX_INTERVALS),
+            smoothed_bpm: None,
+            sorted: Vec::with_capacity(TAP_TEMPO_MAX_INTERVALS),
+        }
+    }
+
+    /// Register a new tap at the given time.
+    ///
+    /// Returns the new smoothed estimate of the tempo in beats per minute, or `None` if
+    /// there are not enough taps to estimate a tempo yet.
+    pub fn tap(&mut self, time: Instant) -> Option<f64> {
+        let last_tap = self.last_tap.replace(time);
+
+        let interval = match last_tap {
+            Some(last_tap) if time > last_tap && time - last_tap <= TAP_TEMPO_TIMEOUT => {
+                (time - last_tap).as_secs_f64()
+            }
+            _ => {
+                // Start a new measurement.
+                self.intervals.clear();
+                self.smoothed_bpm = None;
+                return None;
+            }
+        };
+
+        if self.intervals.len() == TAP_TEMPO_MAX_INTERVALS {
+            self.intervals.pop_front();
+        }
+        self.intervals.push_back(interval);
+
+        self.sorted.clear();
+        self.sorted.extend(self.intervals.iter());
+        self.sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = self.sorted[self.sorted.len() / 2];
+
+        // Throw out any outliers.
+        let (sum, count) = self
+            .intervals
+            .iter()
+            .filter(|i| (*i - median).abs() <= median * OUTLIER_RATIO)
+            .fold((0.0, 0), |(sum, count), i| (sum + i, count + 1));
+
+        // The median itself is never an outlier, so `count` is always at least 1.
+        let bpm = 60.0 / (sum / f64::from(count));
+
+        let smoothed_bpm = if let Some(prev) = self.smoothed_bpm {
+            prev + ((bpm - prev) * SMOOTHING)
+        } else {
+            bpm
+        };
+        self.smoothed_bpm = Some(smoothed_bpm);
+
+        // Round to a tempo that is easier to read.
+        Some((smoothed_bpm * 100.0).round() / 100.0)
+    }
+
+    /// Clear all taps.
+    pub fn reset(&mut self) {
+        self.last_tap = None;
+        self.intervals.clear();
+        self.smoothed_bpm = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tap_tempo_estimate() {
+        let mut tap_tempo = TapTempo::new();
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        assert_eq!(tap_tempo.tap(at(0)), None);
+        assert_eq!(tap_tempo.tap(at(500)), Some(120.0));
+        assert_eq!(tap_tempo.tap(at(1000)), Some(120.0));
+
+        // A late tap is thrown out as an outlier.
+        assert_eq!(tap_tempo.tap(at(1900)), Some(120.0));
+        assert_eq!(tap_tempo.tap(at(2400)), Some(120.0));
+
+        // Waiting too long starts a new measurement.
+        assert_eq!(tap_tempo.tap(at(5000)), None);
+        assert_eq!(tap_tempo.tap(at(5600)), Some(100.0));
+    }
+}
@@ -1,5 +1,9 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Instant;
 use tuix::*;
 
+use crate::backend::timeline::TimeSignature;
 use crate::state::{event::TempoEvent, BoundGuiState};
 
 use super::ControlBar;
@@ -26,8 +30,24 @@ impl Widget for TempoControlBar {
             .bind(BoundGuiState::bpm, |value| value.to_string())
             .build(state, controls, |builder| builder.set_name("tempo"));
 
-        Button::with_label("TAP").build(state, controls, |builder| builder);
-        Button::with_label("4/4")
+        TempoTapButton::default().build(state, controls, |builder| builder);
+
+        // The time signature of the project, so invalid input can be reverted.
+        let current_time_signature = Rc::new(Cell::new(TimeSignature::default()));
+        let bound_time_signature = Rc::clone(&current_time_signature);
+        Textbox::new("4/4")
+            .on_submit(move |data, state, textbox| {
+                if let Ok(time_signature) = data.text.parse::<TimeSignature>() {
+                    textbox
+                        .emit(state, TempoEvent::SetTimeSignature(time_signature).to_state_event());
+                } else {
+                    data.text = current_time_signature.get().to_string();
+                }
+            })
+            .bind(BoundGuiState::time_signature, move |value| {
+                bound_time_signature.set(*value);
+                value.to_string()
+            })
             .build(state, controls, |builder| builder.set_name("time signature"));
 
         // Dropdown::new("GROOVE").build(state, controls, |builder| builder);
@@ -41,11 +61,6 @@ impl Widget for TempoControlBar {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum TempoTapEvent {
-    Tapped(f32),
-}
-
 /// Widget for the TAP tempo button
 #[derive(Default)]
 pub struct TempoTapButton {}
@@ -55,11 +70,12 @@ impl Widget for TempoTapButton {
     type Data = ();
     fn on_build(&mut self, state: &mut State, entity: Entity) -> Self::Ret {
         Button::with_label("TAP")
-            //.on_press(callback)
+            .on_press(|_, state, button| {
+                // Record the time here so any delay in the event queue doesn't skew the tempo.
+                button.emit(state, TempoEvent::Tap(Instant::now()).to_state_event());
+            })
             .build(state, entity, |builder| builder);
 
         entity
     }
-
-    fn on_event(&mut self, _state: &mut State, _entity: Entity, _event: &mut Event) {}
 }
@@ -6,3 +6,9 @@ pub use header::*;
 
 pub mod control_bars;
 pub use control_bars::*;
+
+pub mod recovery_prompt;
+pub use recovery_prompt::*;
+
+pub mod notice;
+pub use notice::*;
@@ -0,0 +1,32 @@
+use tuix::*;
+
+/// Shows a message to the user (i.e. an error). This is hidden while there is no
+/// message.
+pub struct Notice {
+    message: Entity,
+}
+
+impl Default for Notice {
+    fn default() -> Self {
+        Self { message: Entity::null() }
+    }
+}
+
+impl Widget for Notice {
+    type Ret = Entity;
+    type Data = Option<String>;
+    fn on_build(&mut self, state: &mut State, entity: Entity) -> Self::Ret {
+        self.message = Label::new("").build(state, entity, |builder| builder);
+
+        entity.class(state, "notice").set_display(state, Display::None)
+    }
+
+    fn on_update(&mut self, state: &mut State, entity: Entity, message: &Self::Data) {
+        if let Some(message) = message {
+            self.message.set_text(state, message);
+            entity.set_display(state, Display::Flex);
+        } else {
+            entity.set_display(state, Display::None);
+        }
+    }
+}
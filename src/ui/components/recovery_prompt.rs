@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use tuix::*;
+
+use crate::state::event::ProjectEvent;
+
+/// Asks the user whether to restore or discard an autosave that is newer than the
+/// project. This is hidden while there is no such autosave.
+pub struct RecoveryPrompt {
+    message: Entity,
+}
+
+impl Default for RecoveryPrompt {
+    fn default() -> Self {
+        Self { message: Entity::null() }
+    }
+}
+
+impl Widget for RecoveryPrompt {
+    type Ret = Entity;
+    type Data = Option<PathBuf>;
+    fn on_build(&mut self, state: &mut State, entity: Entity) -> Self::Ret {
+        self.message = Label::new("").build(state, entity, |builder| builder);
+
+        Button::with_label("RESTORE")
+            .on_press(|_, state, button| {
+                button.emit(state, ProjectEvent::RestoreRecovery.to_state_event());
+            })
+            .build(state, entity, |builder| builder);
+
+        Button::with_label("DISCARD")
+            .on_press(|_, state, button| {
+                button.emit(state, ProjectEvent::DiscardRecovery.to_state_event());
+            })
+            .build(state, entity, |builder| builder);
+
+        entity.class(state, "prompt").set_display(state, Display::None)
+    }
+
+    fn on_update(&mut self, state: &mut State, entity: Entity, recovery_file: &Self::Data) {
+        if let Some(recovery_file) = recovery_file {
+            self.message.set_text(
+                state,
+                &format!(
+                    "Found an autosave that is newer than the project: {}",
+                    recovery_file.display()
+                ),
+            );
+            entity.set_display(state, Display::Flex);
+        } else {
+            entity.set_display(state, Display::None);
+        }
+    }
+}
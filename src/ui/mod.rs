@@ -1,6 +1,9 @@
+use std::path::PathBuf;
+
+use crate::project::ProjectSaveState;
 use crate::state::{
     event::{ProjectEvent, StateSystemEvent},
-    BoundGuiState, ProjectSaveState, StateSystem,
+    BoundGuiState, StateSystem,
 };
 
 pub mod components;
@@ -25,6 +28,17 @@ impl Widget for App {
     type Data = ();
     fn on_build(&mut self, state: &mut State, app: Entity) -> Self::Ret {
         Header::default().build(state, app, |builder| builder);
+        RecoveryPrompt::default()
+            .bind(BoundGuiState::recovery_file, |recovery_file| recovery_file.clone())
+            .build(state, app, |builder| builder);
+        Notice::default()
+            .bind(BoundGuiState::project_error, |project_error| project_error.clone())
+            .build(state, app, |builder| builder.class("error"));
+        Notice::default()
+            .bind(BoundGuiState::graph_warning, |warning| {
+                warning.as_ref().map(|w| format!("Audio graph compiled with a warning: {}", w))
+            })
+            .build(state, app, |builder| builder);
 
         app.set_background_color(state, Color::rgb(10, 10, 10))
     }
@@ -32,8 +46,13 @@ impl Widget for App {
     fn on_event(&mut self, state: &mut State, entity: Entity, event: &mut Event) {}
 }
 
-pub fn run() {
-    let project_save_state = Box::new(ProjectSaveState::test());
+/// Open the main window. If `project_path` is `None` or the project cannot be opened, an
+/// empty project is created.
+pub fn run(project_path: Option<PathBuf>) {
+    let project_event = match project_path {
+        Some(path) => ProjectEvent::OpenProject(path),
+        None => ProjectEvent::LoadProject(Box::new(ProjectSaveState::new_empty())),
+    };
 
     let window_description = WindowDescription::new().with_title("Meadowlark");
     let app = Application::new(window_description, |state, window| {
@@ -46,8 +65,7 @@ pub fn run() {
 
         let app = App::new().build(state, bound_gui_state, |builder| builder);
 
-        bound_gui_state
-            .emit(state, StateSystemEvent::Project(ProjectEvent::LoadProject(project_save_state)));
+        bound_gui_state.emit(state, StateSystemEvent::Project(project_event));
     });
 
     app.run();